@@ -0,0 +1,237 @@
+//!
+//! Beep and XO-CHIP audio playback using sdl2
+//!
+
+use sdl2::AudioSubsystem;
+use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioDevice};
+
+use fish_n_chip::AudioBuffer;
+
+const DEFAULT_VOLUME: f32 = 0.25;
+
+// Keep a volume within the 0.0 (silent) to 1.0 (full scale) range
+fn clamp_volume(volume: f32) -> f32
+{
+    if volume.is_nan() {
+        return 0.0;
+    }
+    volume.clamp(0.0, 1.0)
+}
+
+struct SquareWave {
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+    muted: bool,
+    sample_rate: f32,
+    buffer: Option<AudioBuffer>,
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let volume = if self.muted { 0.0 } else { self.volume };
+        if let Some(buffer) = self.buffer {
+            buffer.fill(out, &mut self.phase, self.sample_rate, volume);
+            return;
+        }
+        // Generate a square wave
+        for x in out.iter_mut() {
+            *x = if self.phase <= 0.5 {
+                volume
+            } else {
+                -volume
+            };
+            self.phase = (self.phase + self.phase_inc) % 1.0;
+        }
+    }
+}
+
+impl SquareWave {
+    fn set_frequency(&mut self, freq: f32) {
+        self.phase_inc = freq / self.sample_rate;
+    }
+}
+
+// Plays the beep on the audio device, or does nothing when sound is disabled
+pub struct Beeper {
+    device: Option<AudioDevice<SquareWave>>,
+    buffer: Option<AudioBuffer>,
+    frequency: f32,
+    volume: f32,
+    muted: bool,
+}
+
+impl Beeper
+{
+    // Open the audio device, failing e.g. without any sound card
+    pub fn new(audio_subsystem: &AudioSubsystem, freq: f32) -> Result<Beeper, String>
+    {
+        let desired_spec = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),  // mono
+            samples: None       // default sample size
+        };
+
+        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
+            SquareWave {
+                phase_inc: freq / spec.freq as f32,
+                phase: 0.0,
+                volume: DEFAULT_VOLUME,
+                muted: false,
+                sample_rate: spec.freq as f32,
+                buffer: None,
+            }
+        })?;
+        Ok(Beeper { device: Some(device), buffer: None, frequency: freq, volume: DEFAULT_VOLUME, muted: false })
+    }
+
+    // Silent beeper, for when the audio device cannot be opened
+    pub fn disabled(freq: f32) -> Beeper
+    {
+        Beeper { device: None, buffer: None, frequency: freq, volume: DEFAULT_VOLUME, muted: false }
+    }
+
+    pub fn frequency(&self) -> f32
+    {
+        self.frequency
+    }
+
+    // Change the square wave frequency while it plays
+    pub fn set_frequency(&mut self, hz: f32)
+    {
+        if let Some(device) = self.device.as_mut() {
+            device.lock().set_frequency(hz);
+        }
+        self.frequency = hz;
+    }
+
+    // Change the amplitude while it plays, clamped to 0.0 - 1.0
+    pub fn set_volume(&mut self, volume: f32)
+    {
+        self.volume = clamp_volume(volume);
+        if let Some(device) = self.device.as_mut() {
+            device.lock().volume = self.volume;
+        }
+    }
+
+    pub fn is_muted(&self) -> bool
+    {
+        self.muted
+    }
+
+    // Silence the beep without pausing the emulation
+    pub fn set_muted(&mut self, muted: bool)
+    {
+        self.muted = muted;
+        if let Some(device) = self.device.as_mut() {
+            device.lock().muted = muted;
+        }
+    }
+
+    // Play the XO-CHIP pattern, or the square wave when None
+    pub fn set_buffer(&mut self, buffer: Option<AudioBuffer>)
+    {
+        if self.buffer != buffer {
+            if let Some(device) = self.device.as_mut() {
+                let mut voice = device.lock();
+                voice.buffer = buffer;
+                voice.phase = 0.0;
+            }
+            self.buffer = buffer;
+        }
+    }
+
+    pub fn beep(&self)
+    {
+        if let Some(device) = &self.device {
+            device.resume();
+        }
+    }
+
+    pub fn pause_beep(&self)
+    {
+        if let Some(device) = &self.device {
+            device.pause();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn square_wave(freq: f32, sample_rate: f32) -> SquareWave
+    {
+        let mut wave = SquareWave {
+            phase_inc: 0.0,
+            phase: 0.0,
+            volume: DEFAULT_VOLUME,
+            muted: false,
+            sample_rate,
+            buffer: None,
+        };
+        wave.set_frequency(freq);
+        wave
+    }
+
+    #[test]
+    fn square_wave_frequency()
+    {
+        // a period every 4 samples
+        let mut wave = square_wave(1000.0, 4000.0);
+        let mut out = [0.0; 8];
+        wave.callback(&mut out);
+        assert_eq!(out, [0.25, 0.25, 0.25, -0.25, 0.25, 0.25, 0.25, -0.25]);
+
+        // half the frequency, a period every 8 samples
+        let mut wave = square_wave(500.0, 4000.0);
+        let mut out = [0.0; 8];
+        wave.callback(&mut out);
+        assert_eq!(out, [0.25, 0.25, 0.25, 0.25, 0.25, -0.25, -0.25, -0.25]);
+
+        // changing the frequency keeps the current phase
+        wave.set_frequency(1000.0);
+        assert_eq!(wave.phase_inc, 0.25);
+        assert_eq!(wave.phase, 0.0);
+    }
+
+    #[test]
+    fn volume_and_mute()
+    {
+        assert_eq!(clamp_volume(0.5), 0.5);
+        assert_eq!(clamp_volume(1.5), 1.0);
+        assert_eq!(clamp_volume(-0.5), 0.0);
+        assert_eq!(clamp_volume(f32::NAN), 0.0);
+
+        let mut wave = square_wave(1000.0, 4000.0);
+        wave.volume = 1.0;
+        let mut out = [0.0; 4];
+        wave.callback(&mut out);
+        assert_eq!(out, [1.0, 1.0, 1.0, -1.0]);
+
+        wave.muted = true;
+        wave.callback(&mut out);
+        assert_eq!(out, [0.0; 4]);
+        wave.buffer = Some(AudioBuffer { pattern: [0xFF; 16], pitch: 64 });
+        wave.callback(&mut out);
+        assert_eq!(out, [0.0; 4]);
+    }
+
+    #[test]
+    fn disabled_beeper()
+    {
+        let mut beeper = Beeper::disabled(440.0);
+        assert!(beeper.device.is_none());
+        beeper.set_frequency(880.0);
+        assert_eq!(beeper.frequency(), 880.0);
+        beeper.set_volume(2.0);
+        beeper.set_muted(true);
+        assert!(beeper.is_muted());
+        beeper.set_buffer(Some(AudioBuffer { pattern: [0xFF; 16], pitch: 64 }));
+        beeper.beep();
+        beeper.pause_beep();
+    }
+}
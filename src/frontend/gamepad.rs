@@ -0,0 +1,222 @@
+//!
+//! Game controller input using sdl2
+//!
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use sdl2::Sdl;
+use sdl2::controller::{Button, GameController};
+
+// Bindings from the game controller buttons to the chip-8 keys
+#[derive(Debug, Clone, PartialEq)]
+pub struct GamepadMap
+{
+    bindings: HashMap<Button, u8>,
+}
+
+// The d-pad on the 2/4/6/8 keys most games move with, the face buttons on the keys around them
+impl Default for GamepadMap
+{
+    fn default() -> GamepadMap
+    {
+        let mut gamepad_map = GamepadMap { bindings: HashMap::new() };
+        let bindings = [
+            (Button::DPadUp, 0x2),
+            (Button::DPadLeft, 0x4),
+            (Button::DPadRight, 0x6),
+            (Button::DPadDown, 0x8),
+            (Button::A, 0x5),
+            (Button::B, 0x0),
+            (Button::X, 0x7),
+            (Button::Y, 0x9),
+            (Button::LeftShoulder, 0x1),
+            (Button::RightShoulder, 0x3),
+            (Button::Back, 0xE),
+            (Button::Start, 0xF),
+        ];
+        for &(button, nibble) in bindings.iter() {
+            gamepad_map.bind(button, nibble);
+        }
+        gamepad_map
+    }
+}
+
+impl GamepadMap
+{
+    // Apply a config made of `<hex key> = <button name>` lines on top of the current bindings,
+    // e.g. `5 = a`, blank lines and lines starting with # are ignored
+    pub fn apply(&mut self, config: &str) -> Result<(), String>
+    {
+        for (number, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=').map(str::trim);
+            let (nibble, name) = match (parts.next(), parts.next()) {
+                (Some(nibble), Some(name)) => (nibble, name),
+                _ => return Err(format!("line {}: expected `<hex key> = <button name>`", number + 1)),
+            };
+            let nibble = match u8::from_str_radix(nibble, 16) {
+                Ok(nibble) if nibble < 16 => nibble,
+                _ => return Err(format!("line {}: invalid chip-8 key {}", number + 1, nibble)),
+            };
+            let button = match button_from_name(name) {
+                Some(button) => button,
+                None => return Err(format!("line {}: unknown button name {}", number + 1, name)),
+            };
+            self.bind(button, nibble);
+        }
+        Ok(())
+    }
+
+    // Bind a button to a chip-8 key, replacing the previous binding of the button
+    pub fn bind(&mut self, button: Button, nibble: u8)
+    {
+        self.bindings.insert(button, nibble);
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, button: Button) -> Option<u8>
+    {
+        self.bindings.get(&button).copied()
+    }
+}
+
+// Parse a config overriding the default bindings
+impl FromStr for GamepadMap
+{
+    type Err = String;
+
+    fn from_str(config: &str) -> Result<Self, Self::Err>
+    {
+        let mut gamepad_map = GamepadMap::default();
+        gamepad_map.apply(config)?;
+        Ok(gamepad_map)
+    }
+}
+
+// Button names, as used in SDL game controller mappings
+fn button_from_name(name: &str) -> Option<Button>
+{
+    match name.to_ascii_lowercase().as_str() {
+        "a" => Some(Button::A),
+        "b" => Some(Button::B),
+        "x" => Some(Button::X),
+        "y" => Some(Button::Y),
+        "back" => Some(Button::Back),
+        "guide" => Some(Button::Guide),
+        "start" => Some(Button::Start),
+        "leftstick" => Some(Button::LeftStick),
+        "rightstick" => Some(Button::RightStick),
+        "leftshoulder" => Some(Button::LeftShoulder),
+        "rightshoulder" => Some(Button::RightShoulder),
+        "dpup" => Some(Button::DPadUp),
+        "dpdown" => Some(Button::DPadDown),
+        "dpleft" => Some(Button::DPadLeft),
+        "dpright" => Some(Button::DPadRight),
+        _ => None,
+    }
+}
+
+// Chip-8 keys held on the first game controller found, if any
+pub struct GamepadInput
+{
+    controller: Option<GameController>,
+    gamepad_map: GamepadMap,
+}
+
+impl GamepadInput
+{
+    // Open the first game controller plugged in, playing without one when there is none
+    pub fn open(sdl_context: &Sdl, gamepad_map: GamepadMap) -> GamepadInput
+    {
+        let controller = sdl_context.game_controller().ok().and_then(|subsystem| {
+            let count = subsystem.num_joysticks().unwrap_or(0);
+            (0..count)
+                .filter(|&index| subsystem.is_game_controller(index))
+                .find_map(|index| subsystem.open(index).ok())
+        });
+        GamepadInput { controller, gamepad_map }
+    }
+
+    // Name of the opened game controller
+    pub fn name(&self) -> Option<String>
+    {
+        self.controller.as_ref().map(GameController::name)
+    }
+
+    // Mark the chip-8 keys of the held buttons as pressed
+    pub fn press(&self, pressed: &mut [u8; 16])
+    {
+        if let Some(controller) = &self.controller {
+            press_buttons(&self.gamepad_map, |button| controller.button(button), pressed);
+        }
+    }
+}
+
+// Mark the chip-8 keys bound to the buttons reported held as pressed
+fn press_buttons<F>(gamepad_map: &GamepadMap, held: F, pressed: &mut [u8; 16])
+    where F: Fn(Button) -> bool
+{
+    for (&button, &nibble) in gamepad_map.bindings.iter() {
+        if held(button) {
+            pressed[nibble as usize] = 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn default_gamepad_map()
+    {
+        let gamepad_map = GamepadMap::default();
+        assert_eq!(gamepad_map.get(Button::DPadUp), Some(0x2));
+        assert_eq!(gamepad_map.get(Button::DPadLeft), Some(0x4));
+        assert_eq!(gamepad_map.get(Button::DPadRight), Some(0x6));
+        assert_eq!(gamepad_map.get(Button::DPadDown), Some(0x8));
+        assert_eq!(gamepad_map.get(Button::A), Some(0x5));
+        assert_eq!(gamepad_map.get(Button::Guide), None);
+    }
+
+    #[test]
+    fn gamepad_map_config()
+    {
+        let gamepad_map: GamepadMap = "# two buttons on the same key\n5 = B\nC = guide\n".parse().unwrap();
+        assert_eq!(gamepad_map.get(Button::A), Some(0x5));
+        assert_eq!(gamepad_map.get(Button::B), Some(0x5));
+        assert_eq!(gamepad_map.get(Button::Guide), Some(0xC));
+
+        assert!("5 = turbo".parse::<GamepadMap>().is_err());
+        assert!("G = a".parse::<GamepadMap>().is_err());
+        assert!("5 a".parse::<GamepadMap>().is_err());
+    }
+
+    #[test]
+    fn button_names()
+    {
+        assert_eq!(button_from_name("dpup"), Some(Button::DPadUp));
+        assert_eq!(button_from_name("LeftShoulder"), Some(Button::LeftShoulder));
+        assert_eq!(button_from_name("start"), Some(Button::Start));
+        assert_eq!(button_from_name("triggerleft"), None);
+    }
+
+    #[test]
+    fn held_buttons_press_keys()
+    {
+        let gamepad_map = GamepadMap::default();
+        let mut pressed = [0; 16];
+        pressed[0xA] = 1;
+        press_buttons(&gamepad_map, |button| button == Button::DPadDown || button == Button::A, &mut pressed);
+        let mut expected = [0; 16];
+        expected[0x8] = 1;
+        expected[0x5] = 1;
+        expected[0xA] = 1;
+        assert_eq!(pressed, expected);
+    }
+}
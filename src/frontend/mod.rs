@@ -0,0 +1,61 @@
+//!
+//! Frontends of the emulator: the SDL window, audio, game controller and overlays with the sdl feature,
+//! and the terminal one with the tui feature
+//!
+
+#[cfg(feature = "sdl")]
+pub const BG_COLOR: (u8, u8, u8) = (74, 74, 74);
+
+// if GRADIENT_DISPLAY is off
+#[cfg(feature = "sdl")]
+pub const PIXEL_COLOR: (u8, u8, u8) = (255, 205, 230);
+
+// XO-CHIP pixels lit on the second plane only, and on both planes
+#[cfg(feature = "sdl")]
+const PLANE2_COLOR: (u8, u8, u8) = (120, 200, 255);
+#[cfg(feature = "sdl")]
+const BOTH_PLANES_COLOR: (u8, u8, u8) = (255, 255, 255);
+
+// if GRADIENT_DISPLAY is on
+#[cfg(feature = "sdl")]
+const GRADIENT_SATURATION: f32 = 0.2;
+#[cfg(feature = "sdl")]
+const GRADIENT_VALUE: f32 = 1.0;
+
+// borders around the display when the window aspect ratio differs
+#[cfg(feature = "sdl")]
+const LETTERBOX_COLOR: (u8, u8, u8) = (0, 0, 0);
+
+// intensity lost every frame by a turned off pixel, out of 255, if fading is on
+#[cfg(feature = "sdl")]
+const FADE_DECAY: u8 = 64;
+
+// debug overlay text, over a translucent backdrop
+#[cfg(feature = "sdl")]
+const OVERLAY_TEXT_COLOR: (u8, u8, u8) = (255, 255, 255);
+#[cfg(feature = "sdl")]
+const OVERLAY_BACKDROP_COLOR: (u8, u8, u8, u8) = (0, 0, 0, 160);
+
+// hue offset applied to pixels while beeping, if color cycling on beep is on
+#[cfg(feature = "sdl")]
+const BEEP_HUE_SHIFT: u32 = 180;
+
+#[cfg(feature = "sdl")]
+mod screen;
+#[cfg(feature = "sdl")]
+mod beeper;
+#[cfg(feature = "sdl")]
+mod gamepad;
+#[cfg(feature = "sdl")]
+mod overlay;
+#[cfg(all(feature = "tui", unix))]
+pub mod terminal;
+
+#[cfg(feature = "sdl")]
+pub use screen::{Screen, parse_color};
+#[cfg(feature = "sdl")]
+pub use beeper::Beeper;
+#[cfg(feature = "sdl")]
+pub use gamepad::{GamepadInput, GamepadMap};
+#[cfg(feature = "sdl")]
+pub use overlay::{DebugOverlay, HelpOverlay};
@@ -0,0 +1,305 @@
+//!
+//! Overlays drawn over the display: the cpu state for debugging and the key bindings help
+//!
+
+use sdl2::video::Window;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+use fish_n_chip::{Cpu, KeyMap, SPRITES, key_name};
+
+use super::OVERLAY_BACKDROP_COLOR;
+use super::OVERLAY_TEXT_COLOR;
+
+// glyphs are 4x5 pixels, like the chip-8 font, with a pixel of spacing around them
+const GLYPH_WIDTH: i32 = 5;
+const GLYPH_HEIGHT: i32 = 6;
+// space between the window corner and the text, in glyph pixels
+const MARGIN: i32 = 2;
+
+// Letters of the labels missing from the chip-8 hexadecimal font
+fn letter_glyph(letter: char) -> Option<[u8; 5]>
+{
+    match letter {
+        'G' => Some([0xF0, 0x80, 0xB0, 0x90, 0xF0]),
+        'H' => Some([0x90, 0x90, 0xF0, 0x90, 0x90]),
+        'I' => Some([0x70, 0x20, 0x20, 0x20, 0x70]),
+        'J' => Some([0x10, 0x10, 0x10, 0x90, 0x60]),
+        'K' => Some([0x90, 0xA0, 0xC0, 0xA0, 0x90]),
+        'L' => Some([0x80, 0x80, 0x80, 0x80, 0xF0]),
+        'M' => Some([0x90, 0xF0, 0xF0, 0x90, 0x90]),
+        'N' => Some([0x90, 0xD0, 0xB0, 0x90, 0x90]),
+        'O' => Some([0xF0, 0x90, 0x90, 0x90, 0xF0]),
+        'P' => Some([0xF0, 0x90, 0xF0, 0x80, 0x80]),
+        'Q' => Some([0xF0, 0x90, 0x90, 0xB0, 0xF0]),
+        'R' => Some([0xE0, 0x90, 0xE0, 0xA0, 0x90]),
+        'S' => Some([0xF0, 0x80, 0xF0, 0x10, 0xF0]),
+        'T' => Some([0xF0, 0x40, 0x40, 0x40, 0x40]),
+        'U' => Some([0x90, 0x90, 0x90, 0x90, 0xF0]),
+        'V' => Some([0x90, 0x90, 0x90, 0x90, 0x60]),
+        'W' => Some([0x90, 0x90, 0xF0, 0xF0, 0x90]),
+        'X' => Some([0x90, 0x90, 0x60, 0x90, 0x90]),
+        'Y' => Some([0x90, 0x90, 0x70, 0x10, 0xE0]),
+        'Z' => Some([0xF0, 0x10, 0x60, 0x80, 0xF0]),
+        _ => None,
+    }
+}
+
+// Rows of a character, the hexadecimal digits reusing the chip-8 font, unknown characters being blank
+fn glyph(character: char) -> [u8; 5]
+{
+    if let Some(digit) = character.to_digit(16).filter(|_| !character.is_ascii_lowercase()) {
+        let mut rows = [0; 5];
+        rows.copy_from_slice(&SPRITES[digit as usize * 5..digit as usize * 5 + 5]);
+        return rows;
+    }
+    letter_glyph(character).unwrap_or([0; 5])
+}
+
+// Text of the overlay, one string per line
+fn lines(cpu: &Cpu) -> Vec<String>
+{
+    let registers = cpu.registers();
+    let register_line = |range: std::ops::Range<usize>| range
+        .map(|index| format!("V{:X} {:02X}", index, registers[index]))
+        .collect::<Vec<_>>()
+        .join("  ");
+    vec![
+        format!("PC {:03X}  OP {:04X}  I {:03X}", cpu.program_counter(), cpu.opcode(), cpu.index()),
+        format!("DT {:02X}  ST {:02X}  SP {:X}", cpu.delay_timer(), cpu.sound_timer(), cpu.stack_snapshot().len()),
+        register_line(0..8),
+        register_line(8..16),
+    ]
+}
+
+// chip-8 keys as laid out on the keypad
+const KEYPAD: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+// Text of the help, the keypad with the name of the physical key bound to every chip-8 key, in aligned columns
+fn help_lines(names: &[String]) -> Vec<String>
+{
+    let width = names.iter().map(|name| name.chars().count()).max().unwrap_or(0) + 2;
+    let mut lines = vec![String::from("KEYS"), String::new()];
+    for row in KEYPAD.iter() {
+        let cells: Vec<String> = row.iter()
+            .map(|&nibble| format!("{:X} {:<width$}", nibble, names[nibble as usize], width = width))
+            .collect();
+        lines.push(cells.concat().trim_end().to_string());
+    }
+    lines
+}
+
+// Names of the physical keys bound to the chip-8 keys, in the glyphs case
+fn bound_key_names(keymap: &KeyMap) -> Vec<String>
+{
+    (0..16).map(|nibble| keymap.key_for(nibble).map(key_name).unwrap_or_default().to_ascii_uppercase()).collect()
+}
+
+// Rectangles of the lit pixels of the text, each glyph pixel being scale window pixels wide
+fn text_rects(lines: &[String], scale: i32) -> Vec<Rect>
+{
+    let mut rects = Vec::new();
+    for (line_index, line) in lines.iter().enumerate() {
+        for (column, character) in line.chars().enumerate() {
+            let left = MARGIN + column as i32 * GLYPH_WIDTH;
+            let top = MARGIN + line_index as i32 * GLYPH_HEIGHT;
+            for (y, row) in glyph(character).iter().enumerate() {
+                for x in 0..4 {
+                    if row & (0x80 >> x) != 0 {
+                        rects.push(Rect::new((left + x) * scale, (top + y as i32) * scale, scale as u32, scale as u32));
+                    }
+                }
+            }
+        }
+    }
+    rects
+}
+
+// Size of the area covered by the text and its margins, in glyph pixels
+fn text_size(lines: &[String]) -> (i32, i32)
+{
+    let columns = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0) as i32;
+    (2 * MARGIN + columns * GLYPH_WIDTH, 2 * MARGIN + lines.len() as i32 * GLYPH_HEIGHT)
+}
+
+pub struct DebugOverlay
+{
+    pub visible: bool,
+    // window pixels per glyph pixel
+    pub scale: i32,
+}
+
+impl DebugOverlay
+{
+    pub fn new() -> DebugOverlay
+    {
+        DebugOverlay { visible: true, scale: 2 }
+    }
+
+    pub fn toggle(&mut self)
+    {
+        self.visible = !self.visible;
+    }
+
+    // Draw the cpu state in the top left corner of the window, over a translucent backdrop
+    pub fn draw(&self, cpu: &Cpu, canvas: &mut Canvas<Window>)
+    {
+        if !self.visible {
+            return;
+        }
+        let lines = lines(cpu);
+        let (width, height) = text_size(&lines);
+        let (r, g, b, a) = OVERLAY_BACKDROP_COLOR;
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(r, g, b, a));
+        canvas.fill_rect(Rect::new(0, 0, (width * self.scale) as u32, (height * self.scale) as u32)).unwrap();
+        canvas.set_blend_mode(BlendMode::None);
+        let (r, g, b) = OVERLAY_TEXT_COLOR;
+        canvas.set_draw_color(Color::RGB(r, g, b));
+        canvas.fill_rects(&text_rects(&lines, self.scale)).unwrap();
+    }
+}
+
+// Key bindings shown in the middle of the window, the cpu being paused meanwhile
+pub struct HelpOverlay
+{
+    pub visible: bool,
+    // window pixels per glyph pixel
+    pub scale: i32,
+    // the cpu was running when the help was shown, it resumes once hidden
+    paused_cpu: bool,
+}
+
+impl HelpOverlay
+{
+    pub fn new() -> HelpOverlay
+    {
+        HelpOverlay { visible: false, scale: 3, paused_cpu: false }
+    }
+
+    pub fn toggle(&mut self, cpu: &mut Cpu)
+    {
+        self.visible = !self.visible;
+        if self.visible && !cpu.is_paused() {
+            cpu.pause();
+            self.paused_cpu = true;
+        } else if !self.visible && self.paused_cpu {
+            cpu.resume();
+            self.paused_cpu = false;
+        }
+    }
+
+    pub fn draw(&self, keymap: &KeyMap, canvas: &mut Canvas<Window>)
+    {
+        if !self.visible {
+            return;
+        }
+        let lines = help_lines(&bound_key_names(keymap));
+        let (width, height) = text_size(&lines);
+        let (width, height) = (width * self.scale, height * self.scale);
+        let (win_w, win_h) = canvas.output_size().unwrap();
+        let (left, top) = ((win_w as i32 - width) / 2, (win_h as i32 - height) / 2);
+        let (r, g, b, a) = OVERLAY_BACKDROP_COLOR;
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(r, g, b, a));
+        canvas.fill_rect(Rect::new(left, top, width as u32, height as u32)).unwrap();
+        canvas.set_blend_mode(BlendMode::None);
+        let (r, g, b) = OVERLAY_TEXT_COLOR;
+        canvas.set_draw_color(Color::RGB(r, g, b));
+        let mut rects = text_rects(&lines, self.scale);
+        for rect in rects.iter_mut() {
+            rect.offset(left, top);
+        }
+        canvas.fill_rects(&rects).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use fish_n_chip::{Memory, Keyboard, Layout};
+
+    #[test]
+    fn overlay_lines()
+    {
+        let mut cpu = Cpu::new();
+        let mut memory = Memory::new();
+        let keyboard = Keyboard::new();
+        cpu.execute_raw(0x6A2B, &mut memory, &keyboard);
+        cpu.execute_raw(0xA123, &mut memory, &keyboard);
+        let lines = lines(&cpu);
+        assert_eq!(lines[0], "PC 204  OP A123  I 123");
+        assert_eq!(lines[1], "DT 00  ST 00  SP 0");
+        assert_eq!(lines[2], "V0 00  V1 00  V2 00  V3 00  V4 00  V5 00  V6 00  V7 00");
+        assert_eq!(lines[3], "V8 00  V9 00  VA 2B  VB 00  VC 00  VD 00  VE 00  VF 00");
+    }
+
+    #[test]
+    fn help_layout()
+    {
+        let mut names = bound_key_names(&KeyMap::from_layout(Layout::Qwerty));
+        assert_eq!(help_lines(&names), [
+            "KEYS",
+            "",
+            "1 1  2 2  3 3  C 4",
+            "4 Q  5 W  6 E  D R",
+            "7 A  8 S  9 D  E F",
+            "A Z  0 X  B C  F V",
+        ]);
+
+        // the columns widen to the longest key name
+        names[0x0] = String::from("KEYPAD 0");
+        let lines = help_lines(&names);
+        assert_eq!(lines[5], "A Z         0 KEYPAD 0  B C         F V");
+        assert_eq!(lines[2], "1 1         2 2         3 3         C 4");
+    }
+
+    #[test]
+    fn help_pauses_the_cpu()
+    {
+        let mut help = HelpOverlay::new();
+        let mut cpu = Cpu::new();
+        help.toggle(&mut cpu);
+        assert!(help.visible);
+        assert!(cpu.is_paused());
+        help.toggle(&mut cpu);
+        assert!(!cpu.is_paused());
+
+        // a cpu paused beforehand stays paused
+        cpu.pause();
+        help.toggle(&mut cpu);
+        help.toggle(&mut cpu);
+        assert!(cpu.is_paused());
+    }
+
+    #[test]
+    fn glyphs()
+    {
+        assert_eq!(glyph('0'), [0xF0, 0x90, 0x90, 0x90, 0xF0]);
+        assert_eq!(glyph('F'), [0xF0, 0x80, 0xF0, 0x80, 0x80]);
+        assert_eq!(glyph('V'), [0x90, 0x90, 0x90, 0x90, 0x60]);
+        assert_eq!(glyph(' '), [0; 5]);
+        assert_eq!(glyph('f'), [0; 5]);
+    }
+
+    #[test]
+    fn overlay_layout()
+    {
+        let lines = vec![String::from("1"), String::from(" I")];
+        let rects = text_rects(&lines, 3);
+        // the top row of the 1 is a single pixel, in the third column of the glyph
+        assert_eq!(rects[0], Rect::new((MARGIN + 2) * 3, MARGIN * 3, 3, 3));
+        // the I sits in the second column of the second line
+        let i_rects: Vec<_> = rects.iter().filter(|rect| rect.y() >= (MARGIN + GLYPH_HEIGHT) * 3).collect();
+        assert_eq!(i_rects[0], &Rect::new((MARGIN + GLYPH_WIDTH + 1) * 3, (MARGIN + GLYPH_HEIGHT) * 3, 3, 3));
+        assert_eq!(i_rects.len(), 3 + 1 + 1 + 1 + 3);
+        assert_eq!(text_size(&lines), (2 * MARGIN + 2 * GLYPH_WIDTH, 2 * MARGIN + 2 * GLYPH_HEIGHT));
+    }
+}
@@ -0,0 +1,347 @@
+//!
+//! Screen handling using sdl2
+//!
+
+use sdl2::video::{Window, WindowContext};
+use sdl2::render::{Canvas, TextureCreator, Texture};
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+
+use fish_n_chip::{Display, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+#[cfg(test)]
+use super::BG_COLOR;
+#[cfg(test)]
+use super::PIXEL_COLOR;
+use super::PLANE2_COLOR;
+use super::BOTH_PLANES_COLOR;
+
+use super::GRADIENT_SATURATION;
+use super::GRADIENT_VALUE;
+
+use super::BEEP_HUE_SHIFT;
+
+use super::FADE_DECAY;
+
+use super::LETTERBOX_COLOR;
+
+fn rgb_from_hsv(hue: u32, saturation: f32, value: f32) -> (u8, u8, u8)
+{
+    let hue = hue % 360;
+    let c = value * saturation;
+    let x = c * (1.0 - ((hue as f32 / 60.0) % 2.0 - 1.0).abs()) as f32;
+    let m = value - c;
+
+    // float errors may push a channel slightly past 255
+    let channel = |level: f32| ((level + m) * 255.0).clamp(0.0, 255.0) as u8;
+    let rgb = |r, g, b| { (channel(r), channel(g), channel(b)) };
+    let (r, g, b) = match hue {
+        0..=59 => rgb(c, x, 0.0),
+        60..=119 => rgb(x, c, 0.0),
+        120..=179 => rgb(0.0, c, x),
+        180..=239 => rgb(0.0, x, c),
+        240..=299 => rgb(x, 0.0, c),
+        _ => rgb(c, 0.0, x),
+    };
+    (r, g, b)
+}
+
+// Parse a RRGGBB or #RRGGBB hexadecimal color
+pub fn parse_color(hex: &str) -> Result<(u8, u8, u8), String>
+{
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+    if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid color '{}', expected RRGGBB or #RRGGBB", hex));
+    }
+    let component = |index: usize| u8::from_str_radix(&digits[index..index + 2], 16).unwrap();
+    Ok((component(0), component(2), component(4)))
+}
+
+fn pixel_color(hue: u32, use_gradient: bool, beep_shift: bool, saturation: f32, value: f32, solid: (u8, u8, u8)) -> (u8, u8, u8)
+{
+    match (use_gradient, beep_shift) {
+        (false, false) => solid,
+        (true, false) => rgb_from_hsv(hue, saturation, value),
+        (_, true) => rgb_from_hsv((hue + BEEP_HUE_SHIFT) % 360, saturation, value),
+    }
+}
+
+// Color of a 2-bit XO-CHIP pixel, plane 1 alone using the regular pixel color
+fn plane_color(pixel: u8, pixel_color: (u8, u8, u8)) -> Option<(u8, u8, u8)>
+{
+    match pixel {
+        0b01 => Some(pixel_color),
+        0b10 => Some(PLANE2_COLOR),
+        0b11 => Some(BOTH_PLANES_COLOR),
+        _ => None,
+    }
+}
+
+// Lit pixels get the full intensity, the others lose decay every frame
+fn update_intensity(intensity: &mut [u8], pixels: &[u8], decay: u8)
+{
+    for (level, &pixel) in intensity.iter_mut().zip(pixels.iter()) {
+        *level = if pixel != 0 { 255 } else { level.saturating_sub(decay) };
+    }
+}
+
+// Blend a color over the background according to an intensity out of 255
+fn fade_color(color: (u8, u8, u8), background: (u8, u8, u8), intensity: u8) -> (u8, u8, u8)
+{
+    let blend = |fg: u8, bg: u8| (bg as i32 + (fg as i32 - bg as i32) * intensity as i32 / 255) as u8;
+    (blend(color.0, background.0), blend(color.1, background.1), blend(color.2, background.2))
+}
+
+// Centered area of the window showing the display with its aspect ratio kept,
+// scaled by a whole factor whenever the window is large enough
+fn fit_rect(win_w: u32, win_h: u32, disp_w: u32, disp_h: u32) -> Rect
+{
+    let scale = (win_w / disp_w).min(win_h / disp_h);
+    let (width, height) = if scale >= 1 {
+        (disp_w * scale, disp_h * scale)
+    } else if win_w * disp_h <= win_h * disp_w {
+        (win_w, win_w * disp_h / disp_w)
+    } else {
+        (win_h * disp_w / disp_h, win_h)
+    };
+    Rect::new(((win_w - width) / 2) as i32, ((win_h - height) / 2) as i32, width.max(1), height.max(1))
+}
+
+fn create_texture<'r>(texture_creator: &'r TextureCreator<WindowContext>, (width, height): (usize, usize)) -> Texture<'r>
+{
+    texture_creator
+        .create_texture_target(texture_creator.default_pixel_format(), width as u32, height as u32)
+        .unwrap()
+}
+
+pub struct Screen<'r>
+{
+    texture_creator: &'r TextureCreator<WindowContext>,
+    texture: Texture<'r>,
+    texture_sizes: (usize, usize),
+    hue: u32,
+    use_gradient: bool,
+    fg_color: (u8, u8, u8),
+    bg_color: (u8, u8, u8),
+    intensity: Vec<u8>,
+    // lit pixels color of the last drawn frame
+    color: (u8, u8, u8),
+    // display last drawn on the texture, to only redraw the pixels changed since
+    drawn: Option<Display>,
+    pub color_cycle_on_beep: bool,
+    // turned off pixels fade out over a few frames instead of disappearing, to reduce flicker
+    pub fade: bool,
+    pub fade_decay: u8,
+    // hue degrees added every frame, and the gradient colors saturation and value from 0.0 to 1.0
+    pub gradient_speed: u32,
+    pub gradient_saturation: f32,
+    pub gradient_value: f32,
+    // color of the borders around the display when the window does not match its aspect ratio
+    pub letterbox_color: (u8, u8, u8),
+}
+
+impl<'r> Screen<'r>
+{
+    // Screen drawing lit pixels with fg_color over bg_color, unless use_gradient is on
+    pub fn new(texture_creator: &'r TextureCreator<WindowContext>, use_gradient: bool, fg_color: (u8, u8, u8), bg_color: (u8, u8, u8)) -> Screen<'r>
+    {
+        Screen {
+            texture_creator,
+            texture: create_texture(texture_creator, (DISPLAY_WIDTH, DISPLAY_HEIGHT)),
+            texture_sizes: (DISPLAY_WIDTH, DISPLAY_HEIGHT),
+            hue: 0,
+            use_gradient: use_gradient,
+            fg_color,
+            bg_color,
+            intensity: vec![0; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            color: fg_color,
+            drawn: None,
+            color_cycle_on_beep: false,
+            fade: false,
+            fade_decay: FADE_DECAY,
+            gradient_speed: 1,
+            gradient_saturation: GRADIENT_SATURATION,
+            gradient_value: GRADIENT_VALUE,
+            letterbox_color: LETTERBOX_COLOR,
+        }
+    }
+
+    // Colors of the lit pixels and of the background as last drawn, gradient hue included
+    pub fn colors(&self) -> ((u8, u8, u8), (u8, u8, u8))
+    {
+        (self.color, self.bg_color)
+    }
+
+    pub fn draw(&mut self, display_memory: &Display, canvas: &mut Canvas<Window>, beeping: bool)
+    {
+        let beep_shift = beeping && self.color_cycle_on_beep;
+        if self.use_gradient || beep_shift {
+            self.hue = (self.hue + self.gradient_speed % 360) % 360;
+        }
+        let color = pixel_color(self.hue, self.use_gradient, beep_shift, self.gradient_saturation, self.gradient_value, self.fg_color);
+        let recolored = color != self.color;
+        self.color = color;
+        let (width, height) = display_memory.get_sizes();
+        let resized = self.texture_sizes != (width, height);
+        if resized {
+            self.texture = create_texture(self.texture_creator, (width, height));
+            self.texture_sizes = (width, height);
+            self.intensity = vec![0; width * height];
+        }
+        if self.fade {
+            update_intensity(&mut self.intensity, display_memory.pixels(), self.fade_decay);
+        }
+        // fading pixels and color changes need the whole texture redrawn
+        let changed = match self.drawn.as_ref() {
+            Some(drawn) if !self.fade && !recolored && !resized => Some(display_memory.diff(drawn)),
+            _ => None,
+        };
+        let (fade, intensity, background) = (self.fade, &self.intensity, self.bg_color);
+        canvas.with_texture_canvas(&mut self.texture, |texture_canvas| {
+            if let Some(changed) = changed {
+                for (x, y, pixel) in changed {
+                    let (r, g, b) = plane_color(pixel, color).unwrap_or(background);
+                    texture_canvas.set_draw_color(Color::RGB(r, g, b));
+                    texture_canvas.draw_point(Point::new(x as i32, y as i32)).unwrap();
+                }
+                return;
+            }
+            texture_canvas.set_draw_color(Color::RGB(background.0, background.1, background.2));
+            texture_canvas.clear();
+            for y in 0..height {
+                for x in 0..width {
+                    let pixel_color = match plane_color(display_memory[[x, y]], color) {
+                        Some(pixel_color) => pixel_color,
+                        None if fade && intensity[y * width + x] > 0 => fade_color(color, background, intensity[y * width + x]),
+                        None => continue,
+                    };
+                    let (r, g, b) = pixel_color;
+                    texture_canvas.set_draw_color(Color::RGB(r, g, b));
+                    texture_canvas.draw_point(Point::new(x as i32, y as i32)).unwrap();
+                }
+            }
+        }).unwrap();
+        self.drawn = Some(display_memory.clone());
+        let (win_w, win_h) = canvas.output_size().unwrap();
+        let (r, g, b) = self.letterbox_color;
+        canvas.set_draw_color(Color::RGB(r, g, b));
+        canvas.clear();
+        canvas.copy(&self.texture, None, fit_rect(win_w, win_h, width as u32, height as u32)).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn beep_shifts_pixel_color()
+    {
+        let color = |hue, use_gradient, beep_shift| pixel_color(hue, use_gradient, beep_shift, GRADIENT_SATURATION, GRADIENT_VALUE, PIXEL_COLOR);
+        // solid color
+        assert_eq!(color(0, false, false), PIXEL_COLOR);
+        assert_ne!(color(0, false, true), PIXEL_COLOR);
+        // gradient
+        assert_eq!(color(10, true, false), rgb_from_hsv(10, GRADIENT_SATURATION, GRADIENT_VALUE));
+        assert_ne!(color(10, true, true), color(10, true, false));
+        assert_eq!(color(10, true, true), color(190, true, false));
+        assert_eq!(color(300, true, true), color(120, true, false));
+    }
+
+    #[test]
+    fn rgb_from_hsv_boundaries()
+    {
+        assert_eq!(rgb_from_hsv(0, 1.0, 1.0), (255, 0, 0));
+        assert_eq!(rgb_from_hsv(60, 1.0, 1.0), (255, 255, 0));
+        assert_eq!(rgb_from_hsv(180, 1.0, 1.0), (0, 255, 255));
+        assert_eq!(rgb_from_hsv(359, 1.0, 1.0), (255, 0, 4));
+        // no saturation gives a gray of the given value
+        assert_eq!(rgb_from_hsv(180, 0.0, 1.0), (255, 255, 255));
+        assert_eq!(rgb_from_hsv(180, 0.0, 0.0), (0, 0, 0));
+        // the gradient settings applied to the pixel color
+        assert_eq!(pixel_color(0, true, false, 1.0, 1.0, PIXEL_COLOR), (255, 0, 0));
+        assert_eq!(pixel_color(0, true, false, 0.0, 1.0, PIXEL_COLOR), (255, 255, 255));
+    }
+
+    #[test]
+    fn rgb_from_hsv_stays_in_range()
+    {
+        assert_eq!(rgb_from_hsv(360, 1.0, 1.0), rgb_from_hsv(0, 1.0, 1.0));
+        assert_eq!(rgb_from_hsv(420, 1.0, 1.0), rgb_from_hsv(60, 1.0, 1.0));
+        // the brightest channel is always at value and the darkest one at value - chroma,
+        // a channel overflowing 255 would wrap around to a dark one
+        for &(saturation, value) in [(GRADIENT_SATURATION, GRADIENT_VALUE), (1.0, 1.0)].iter() {
+            for hue in 0..=360 {
+                let (r, g, b) = rgb_from_hsv(hue, saturation, value);
+                assert_eq!(r.max(g).max(b), (value * 255.0) as u8, "hue {}", hue);
+                assert_eq!(r.min(g).min(b), ((value - value * saturation) * 255.0) as u8, "hue {}", hue);
+            }
+        }
+    }
+
+    #[test]
+    fn fade_decays_turned_off_pixels()
+    {
+        let mut intensity = vec![0; 3];
+        update_intensity(&mut intensity, &[1, 0, 3], 64);
+        assert_eq!(intensity, [255, 0, 255]);
+
+        // the first pixel is turned off, it decays over 4 frames
+        let expected = [[191, 0, 255], [127, 0, 255], [63, 0, 255], [0, 0, 255], [0, 0, 255]];
+        for frame in expected.iter() {
+            update_intensity(&mut intensity, &[0, 0, 2], 64);
+            assert_eq!(&intensity, frame);
+        }
+
+        // lighting it again restores the full intensity
+        update_intensity(&mut intensity, &[1, 0, 2], 64);
+        assert_eq!(intensity, [255, 0, 255]);
+
+        assert_eq!(fade_color(PIXEL_COLOR, BG_COLOR, 255), PIXEL_COLOR);
+        assert_eq!(fade_color(PIXEL_COLOR, BG_COLOR, 0), BG_COLOR);
+        assert_eq!(fade_color((200, 0, 0), (0, 0, 0), 0), (0, 0, 0));
+    }
+
+    #[test]
+    fn parse_hex_colors()
+    {
+        assert_eq!(parse_color("FFCDE6"), Ok((255, 205, 230)));
+        assert_eq!(parse_color("#4a4a4a"), Ok((74, 74, 74)));
+        assert_eq!(parse_color("#000000"), Ok((0, 0, 0)));
+        assert!(parse_color("").is_err());
+        assert!(parse_color("#").is_err());
+        assert!(parse_color("FFF").is_err());
+        assert!(parse_color("FFCDE6FF").is_err());
+        assert!(parse_color("GGCDE6").is_err());
+        assert!(parse_color("##FFCDE6").is_err());
+        assert!(parse_color("+1CDE6").is_err());
+    }
+
+    #[test]
+    fn fit_rect_keeps_aspect_ratio()
+    {
+        // default window, exact integer scale
+        assert_eq!(fit_rect(1280, 640, 64, 32), Rect::new(0, 0, 1280, 640));
+        // wider and taller windows are letterboxed
+        assert_eq!(fit_rect(1920, 640, 64, 32), Rect::new(320, 0, 1280, 640));
+        assert_eq!(fit_rect(640, 640, 64, 32), Rect::new(0, 160, 640, 320));
+        // the largest whole scale is used, leaving borders on both sides
+        assert_eq!(fit_rect(1000, 700, 64, 32), Rect::new(20, 110, 960, 480));
+        // high resolution mode
+        assert_eq!(fit_rect(1280, 640, 128, 64), Rect::new(0, 0, 1280, 640));
+        // windows smaller than the display fall back to a fractional scale
+        assert_eq!(fit_rect(48, 48, 64, 32), Rect::new(0, 12, 48, 24));
+        assert_eq!(fit_rect(100, 20, 64, 32), Rect::new(30, 0, 40, 20));
+    }
+
+    #[test]
+    fn planes_map_to_four_colors()
+    {
+        assert_eq!(plane_color(0b00, PIXEL_COLOR), None);
+        assert_eq!(plane_color(0b01, PIXEL_COLOR), Some(PIXEL_COLOR));
+        assert_eq!(plane_color(0b01, (1, 2, 3)), Some((1, 2, 3)));
+        assert_eq!(plane_color(0b10, PIXEL_COLOR), Some(PLANE2_COLOR));
+        assert_eq!(plane_color(0b11, PIXEL_COLOR), Some(BOTH_PLANES_COLOR));
+    }
+}
@@ -0,0 +1,186 @@
+//!
+//! Terminal frontend: the display drawn with half block characters, the keys read from stdin
+//!
+
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::Instant;
+
+use fish_n_chip::{Cpu, CycleOutcome, Keyboard, Memory};
+
+use crate::timing::{Ticker, TIMER_FREQUENCY};
+
+// Characters typed on the left of a QWERTY keyboard and the chip-8 keys they stand for, laid out like the keypad
+const KEYPAD_CHARS: [(u8, usize); 16] = [
+    (b'1', 0x1), (b'2', 0x2), (b'3', 0x3), (b'4', 0xC),
+    (b'q', 0x4), (b'w', 0x5), (b'e', 0x6), (b'r', 0xD),
+    (b'a', 0x7), (b's', 0x8), (b'd', 0x9), (b'f', 0xE),
+    (b'z', 0xA), (b'x', 0x0), (b'c', 0xB), (b'v', 0xF),
+];
+
+// Terminals only report key presses, repeated while the key is held, so a key stays pressed this many frames
+const KEY_HOLD_FRAMES: u32 = 10;
+
+const ESCAPE: u8 = 0x1B;
+const CTRL_C: u8 = 0x03;
+
+// Chip-8 keys typed in the bytes read from the terminal, and whether Escape or Ctrl-C asked to quit.
+// Escape sequences, e.g. the arrow keys, start with an escape followed by '[' and are skipped.
+fn parse_input(bytes: &[u8]) -> ([u8; 16], bool)
+{
+    let mut pressed = [0; 16];
+    let mut index = 0;
+    while index < bytes.len() {
+        let byte = bytes[index];
+        index += 1;
+        if byte == CTRL_C || (byte == ESCAPE && bytes.get(index) != Some(&b'[')) {
+            return (pressed, true);
+        }
+        if byte == ESCAPE {
+            // up to the final byte of the sequence
+            index += 1;
+            while index < bytes.len() && !(0x40..=0x7E).contains(&bytes[index]) {
+                index += 1;
+            }
+            index += 1;
+        } else if let Some(&(_, key)) = KEYPAD_CHARS.iter().find(|&&(c, _)| c == byte.to_ascii_lowercase()) {
+            pressed[key] = 1;
+        }
+    }
+    (pressed, false)
+}
+
+// Terminal without line buffering, echo and signals, with non blocking reads, restored when dropped
+struct RawTerminal
+{
+    original: libc::termios,
+}
+
+impl RawTerminal
+{
+    fn enable() -> io::Result<RawTerminal>
+    {
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let original = termios;
+            termios.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+            termios.c_cc[libc::VMIN] = 0;
+            termios.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(RawTerminal { original })
+        }
+    }
+}
+
+impl Drop for RawTerminal
+{
+    fn drop(&mut self)
+    {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+        // show the cursor again
+        println!("\x1b[?25h");
+        let _ = io::stdout().flush();
+    }
+}
+
+// Run the emulator in the terminal until Escape or Ctrl-C, executing cycles_per_frame cpu cycles per frame,
+// returns the number of frames rendered
+pub fn run(cpu: &mut Cpu, memory: &mut Memory, keyboard: &mut Keyboard, cycles_per_frame: u32, fps: f32) -> io::Result<u64>
+{
+    let _raw_terminal = RawTerminal::enable()?;
+    let mut stdin = io::stdin();
+    let mut stdout = io::stdout();
+    keyboard.hold_frames = keyboard.hold_frames.max(KEY_HOLD_FRAMES);
+    // clear the screen and hide the cursor
+    write!(stdout, "\x1b[2J\x1b[?25l")?;
+
+    let start = Instant::now();
+    let mut render_ticker = Ticker::with_frequency(fps as f64, start);
+    let mut timer_ticker = Ticker::with_frequency(TIMER_FREQUENCY, start);
+    let mut drawn = String::new();
+    let mut frames: u64 = 0;
+    // keys typed since the last frame
+    let mut typed = [0; 16];
+    loop {
+        let mut input = Vec::new();
+        let mut buffer = [0; 64];
+        loop {
+            let read = stdin.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            input.extend_from_slice(&buffer[..read]);
+        }
+        let (pressed, quit) = parse_input(&input);
+        if quit {
+            return Ok(frames);
+        }
+        for (typed, pressed) in typed.iter_mut().zip(pressed.iter()) {
+            *typed |= pressed;
+        }
+
+        let now = Instant::now();
+        for _ in 0..timer_ticker.due(now) {
+            let _ = cpu.update_timers();
+        }
+        if render_ticker.due(now) > 0 {
+            keyboard.update(typed);
+            typed = [0; 16];
+            if !cpu.is_halted(memory) {
+                for _ in 0..cycles_per_frame {
+                    if cpu.cycle(memory, keyboard) == CycleOutcome::LimitReached {
+                        return Ok(frames);
+                    }
+                }
+            }
+            memory.display.present();
+            let text = memory.display.front().to_half_blocks();
+            if text != drawn {
+                write!(stdout, "\x1b[H{}", text)?;
+                stdout.flush()?;
+                drawn = text;
+            }
+            keyboard.next_frame();
+            frames += 1;
+        }
+
+        let deadline = render_ticker.deadline().min(timer_ticker.deadline());
+        let now = Instant::now();
+        if deadline > now {
+            thread::sleep(deadline - now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn terminal_input()
+    {
+        let (pressed, quit) = parse_input(b"1vX");
+        assert!(!quit);
+        assert_eq!(pressed[0x1], 1);
+        assert_eq!(pressed[0xF], 1);
+        // upper case typed with caps lock
+        assert_eq!(pressed[0x0], 1);
+        assert_eq!(pressed.iter().sum::<u8>(), 3);
+
+        assert!(parse_input(&[b'q', ESCAPE]).1);
+        assert!(parse_input(&[CTRL_C]).1);
+        // an arrow key then 'e'
+        let (pressed, quit) = parse_input(&[ESCAPE, b'[', b'A', b'e']);
+        assert!(!quit);
+        assert_eq!(pressed.iter().sum::<u8>(), 1);
+        assert_eq!(pressed[0x6], 1);
+    }
+}
@@ -1,63 +1,70 @@
 //!
-//! Audio emulator
+//! XO-CHIP audio pattern
 //!
 
-use sdl2::AudioSubsystem;
-use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioDevice};
-
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32
+// XO-CHIP audio: a 128-bit pattern played one bit per sample at a rate set by the pitch register
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioBuffer
+{
+    pub pattern: [u8; 16],
+    pub pitch: u8,
 }
 
-impl AudioCallback for SquareWave {
-    type Channel = f32;
+impl AudioBuffer
+{
+    // Pattern bits played per second, 4000Hz at the default pitch of 64
+    pub fn playback_rate(&self) -> f32
+    {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
 
-    fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
+    // Fill out with the pattern bits, phase being the position in bits within the pattern
+    pub fn fill(&self, out: &mut [f32], phase: &mut f32, sample_rate: f32, volume: f32)
+    {
+        let phase_inc = self.playback_rate() / sample_rate;
         for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
+            let bit = *phase as usize % 128;
+            *x = if self.pattern[bit / 8] >> (7 - bit % 8) & 1 == 1 {
+                volume
             } else {
-                -self.volume
+                -volume
             };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+            *phase = (*phase + phase_inc) % 128.0;
         }
     }
 }
 
-pub struct Beeper {
-    device: AudioDevice<SquareWave>
-}
-
-impl Beeper
+#[cfg(test)]
+mod tests
 {
-    pub fn new(audio_subsystem: &AudioSubsystem, freq: f32) -> Beeper
-    {
-        let desired_spec = AudioSpecDesired {
-            freq: Some(44100),
-            channels: Some(1),  // mono
-            samples: None       // default sample size
-        };
-
-        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-            SquareWave {
-                phase_inc: freq / spec.freq as f32,
-                phase: 0.0,
-                volume: 0.25
-            }
-        }).unwrap();
-        Beeper { device: device }
-    }
+    use super::*;
 
-    pub fn beep(&self)
+    #[test]
+    fn audio_buffer_samples()
     {
-        self.device.resume();
-    }
+        let mut pattern = [0; 16];
+        pattern[0] = 0b1010_0000;
+        let buffer = AudioBuffer { pattern, pitch: 64 };
+        assert_eq!(buffer.playback_rate(), 4000.0);
+        assert_eq!(AudioBuffer { pattern, pitch: 112 }.playback_rate(), 8000.0);
 
-    pub fn pause_beep(&self)
-    {
-        self.device.pause();
+        // one bit per sample when the sample rate matches the playback rate
+        let mut out = [0.0; 4];
+        let mut phase = 0.0;
+        buffer.fill(&mut out, &mut phase, 4000.0, 0.5);
+        assert_eq!(out, [0.5, -0.5, 0.5, -0.5]);
+        assert_eq!(phase, 4.0);
+
+        // two samples per bit at twice the playback rate
+        let mut out = [0.0; 4];
+        let mut phase = 0.0;
+        buffer.fill(&mut out, &mut phase, 8000.0, 0.5);
+        assert_eq!(out, [0.5, 0.5, -0.5, -0.5]);
+
+        // the pattern loops after 128 bits
+        let mut out = [0.0; 2];
+        let mut phase = 127.0;
+        buffer.fill(&mut out, &mut phase, 4000.0, 0.5);
+        assert_eq!(out, [-0.5, 0.5]);
     }
 }
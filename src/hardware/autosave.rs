@@ -0,0 +1,107 @@
+//!
+//! Periodic save states, rotating between two files
+//!
+
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::snapshot::Snapshot;
+
+// Save states of a ROM written in turn to two files, so that a crash while writing one
+// leaves the other intact and the disk usage stays bounded
+pub struct Autosave
+{
+    paths: [PathBuf; 2],
+    // slot overwritten by the next save
+    next: usize,
+}
+
+impl Autosave
+{
+    // Autosaves next to the ROM, e.g. game.autosave1.state and game.autosave2.state for game.ch8.
+    // Saving carries on from the files left by a previous run.
+    pub fn new<P: AsRef<Path>>(rom_filepath: P) -> Autosave
+    {
+        let rom_filepath = rom_filepath.as_ref();
+        let paths = [rom_filepath.with_extension("autosave1.state"), rom_filepath.with_extension("autosave2.state")];
+        let next = next_slot(modified(&paths));
+        Autosave { paths, next }
+    }
+
+    // The most recently written autosave, None when there is none yet
+    pub fn latest(&self) -> Option<&Path>
+    {
+        latest_slot(modified(&self.paths)).map(|slot| self.paths[slot].as_path())
+    }
+
+    // Write the snapshot over the older autosave, returns the path written
+    pub fn save(&mut self, snapshot: &Snapshot) -> Result<&Path, io::Error>
+    {
+        let slot = self.next;
+        snapshot.save(&self.paths[slot])?;
+        self.next = 1 - slot;
+        Ok(&self.paths[slot])
+    }
+}
+
+fn modified(paths: &[PathBuf; 2]) -> [Option<SystemTime>; 2]
+{
+    let modified = |path: &PathBuf| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    [modified(&paths[0]), modified(&paths[1])]
+}
+
+// Slot to write next from the modification times of the files: a missing one, else the oldest
+fn next_slot(modified: [Option<SystemTime>; 2]) -> usize
+{
+    match modified {
+        [None, _] => 0,
+        [_, None] => 1,
+        [Some(first), Some(second)] => if second < first { 1 } else { 0 },
+    }
+}
+
+// Slot written last, None when neither file exists
+fn latest_slot(modified: [Option<SystemTime>; 2]) -> Option<usize>
+{
+    match modified {
+        [None, None] => None,
+        [Some(_), None] => Some(0),
+        [None, Some(_)] => Some(1),
+        [Some(first), Some(second)] => Some(if second > first { 1 } else { 0 }),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn autosave_rotation()
+    {
+        let older = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let newer = older + Duration::from_secs(30);
+
+        // the missing files are written first
+        assert_eq!(next_slot([None, None]), 0);
+        assert_eq!(next_slot([Some(newer), None]), 1);
+        assert_eq!(next_slot([None, Some(newer)]), 0);
+        // then the oldest one is overwritten
+        assert_eq!(next_slot([Some(older), Some(newer)]), 0);
+        assert_eq!(next_slot([Some(newer), Some(older)]), 1);
+
+        assert_eq!(latest_slot([None, None]), None);
+        assert_eq!(latest_slot([Some(older), None]), Some(0));
+        assert_eq!(latest_slot([Some(older), Some(newer)]), Some(1));
+        assert_eq!(latest_slot([Some(newer), Some(older)]), Some(0));
+
+        let autosave = Autosave::new("roms/pong.ch8");
+        assert_eq!(autosave.paths[0], Path::new("roms/pong.autosave1.state"));
+        assert_eq!(autosave.paths[1], Path::new("roms/pong.autosave2.state"));
+        assert_eq!(autosave.next, 0);
+        assert_eq!(autosave.latest(), None);
+    }
+}
@@ -2,17 +2,33 @@
 //! CPU emulator
 //!
 
-use rand::Rng;
-use super::memory::{Memory, Display};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+#[cfg(test)]
+use super::RAM_SIZE;
+use super::PROGRAM_START_ADDRESS;
+use super::memory::{Memory, Display, SMALL_FONT_ADDRESS, BIG_FONT_ADDRESS};
 use super::keyboard::Keyboard;
+use super::snapshot::Snapshot;
+use super::audio::AudioBuffer;
+use super::disasm::disassemble;
 
 const STACK_SIZE: usize = 16;
+// SUPER-CHIP RPL user flags
+const FLAG_REGISTERS: usize = 8;
 
 #[derive(Debug)]
 struct Stack
 {
     stack : [u16; STACK_SIZE],
     stack_pointer: usize,
+    peak_depth: usize,
 }
 
 impl Stack
@@ -22,32 +38,56 @@ impl Stack
         Stack {
             stack: [0; STACK_SIZE],
             stack_pointer: 0,
+            peak_depth: 0,
         }
     }
 
-    pub fn push(&mut self, address: u16)
+    pub fn push(&mut self, address: u16) -> Result<(), StackError>
     {
         if self.stack_pointer >= STACK_SIZE {
-            panic!("ERROR: cpu stack overflow, too many nested subroutines: {:#?}", self);
+            return Err(StackError::Overflow);
         }
         self.stack[self.stack_pointer] = address;
         self.stack_pointer += 1;
+        self.peak_depth = self.peak_depth.max(self.stack_pointer);
+        Ok(())
     }
 
-    pub fn top(&self) -> u16 { self.stack[self.stack_pointer] }
+    // Most recently pushed address, None when the stack is empty
+    pub fn top(&self) -> Option<u16>
+    {
+        self.stack_pointer.checked_sub(1).map(|top| self.stack[top])
+    }
 
-    pub fn pop(&mut self) -> u16
+    pub fn pop(&mut self) -> Result<u16, StackError>
     {
-        if self.stack_pointer == 0 {
-            panic!("ERROR: cpu stack underflow: {:#?}", self);
-        }
+        let address = self.top().ok_or(StackError::Underflow)?;
         self.stack_pointer -= 1;
-        let address = self.top();
-        address
+        Ok(address)
+    }
+}
+
+// CALL with all the stack levels in use, or RET outside of any subroutine
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StackError
+{
+    Overflow,
+    Underflow,
+}
+
+impl fmt::Display for StackError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self {
+            StackError::Overflow => write!(f, "cpu stack overflow, too many nested subroutines"),
+            StackError::Underflow => write!(f, "cpu stack underflow, return outside of a subroutine"),
+        }
     }
 }
 
-const PROGRAM_START_ADDRESS: usize = 0x200;
+// trace lines kept until they are taken, the oldest ones being dropped first
+const TRACE_CAPACITY: usize = 1024;
 const OPCODE_SIZE: usize = 2;
 
 enum ProgramCounter
@@ -69,6 +109,313 @@ impl ProgramCounter
     }
 }
 
+// Source register of the 8xy6 and 8xye shift instructions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShiftQuirk
+{
+    Vx, // SUPER-CHIP: shift Vx in place
+    Vy, // CHIP-8: Vx = Vy shifted
+}
+
+impl FromStr for ShiftQuirk
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s {
+            "vx" => Ok(ShiftQuirk::Vx),
+            "vy" => Ok(ShiftQuirk::Vy),
+            _ => Err(format!("unknown shift quirk '{}', expected vx or vy", s)),
+        }
+    }
+}
+
+// How I is left after the Fx55 and Fx65 load/store instructions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoadStoreQuirk
+{
+    Increment,         // COSMAC VIP: I = I + x + 1
+    IncrementMinusOne, // CHIP-48: I = I + x
+    Unchanged,         // SUPER-CHIP and modern interpreters
+}
+
+impl FromStr for LoadStoreQuirk
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s {
+            "increment" => Ok(LoadStoreQuirk::Increment),
+            "increment-minus-one" => Ok(LoadStoreQuirk::IncrementMinusOne),
+            "none" => Ok(LoadStoreQuirk::Unchanged),
+            _ => Err(format!("unknown load/store quirk '{}', expected increment, increment-minus-one or none", s)),
+        }
+    }
+}
+
+// Register added to the address of the Bnnn jump
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JumpQuirk
+{
+    V0, // CHIP-8: jump to nnn + V0
+    Vx, // SUPER-CHIP: jump to xnn + Vx, x being the high nibble of nnn
+}
+
+impl FromStr for JumpQuirk
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s {
+            "v0" => Ok(JumpQuirk::V0),
+            "vx" => Ok(JumpQuirk::Vx),
+            _ => Err(format!("unknown jump quirk '{}', expected v0 or vx", s)),
+        }
+    }
+}
+
+// What happens to the sprite pixels drawn by Dxyn past the display edges
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WrapQuirk
+{
+    Wrap, // pixels wrap around to the opposite edge
+    Clip, // pixels past the right and bottom edges are dropped, only the starting coordinate wraps
+}
+
+impl FromStr for WrapQuirk
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s {
+            "wrap" => Ok(WrapQuirk::Wrap),
+            "clip" => Ok(WrapQuirk::Clip),
+            _ => Err(format!("unknown wrap quirk '{}', expected wrap or clip", s)),
+        }
+    }
+}
+
+// A coherent set of quirks, matching what the ROMs written for an interpreter expect
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks
+{
+    pub shift: ShiftQuirk,
+    pub load_store: LoadStoreQuirk,
+    pub jump: JumpQuirk,
+    pub wrap: WrapQuirk,
+    pub vblank: bool,
+    pub index_overflow: bool,
+    pub logic: bool,
+}
+
+// The defaults run most ROMs, modern ones included
+impl Default for Quirks
+{
+    fn default() -> Quirks
+    {
+        Quirks {
+            shift: ShiftQuirk::Vx,
+            load_store: LoadStoreQuirk::Unchanged,
+            jump: JumpQuirk::V0,
+            wrap: WrapQuirk::Wrap,
+            vblank: false,
+            index_overflow: false,
+            logic: false,
+        }
+    }
+}
+
+impl Quirks
+{
+    // Instructions per second of the COSMAC VIP interpreter, on average as their timings vary
+    pub const COSMAC_CLOCK_RATE: f32 = 540.0;
+
+    // The original COSMAC VIP interpreter
+    pub fn chip8() -> Quirks
+    {
+        Quirks {
+            shift: ShiftQuirk::Vy,
+            load_store: LoadStoreQuirk::Increment,
+            jump: JumpQuirk::V0,
+            wrap: WrapQuirk::Clip,
+            vblank: true,
+            index_overflow: false,
+            logic: false,
+        }
+    }
+
+    // The COSMAC VIP interpreter down to 8xy1, 8xy2 and 8xy3 resetting VF, to run at COSMAC_CLOCK_RATE
+    pub fn cosmac() -> Quirks
+    {
+        Quirks {
+            logic: true,
+            ..Quirks::chip8()
+        }
+    }
+
+    // SUPER-CHIP 1.1 on the HP48
+    pub fn schip() -> Quirks
+    {
+        Quirks {
+            shift: ShiftQuirk::Vx,
+            load_store: LoadStoreQuirk::Unchanged,
+            jump: JumpQuirk::Vx,
+            wrap: WrapQuirk::Clip,
+            vblank: false,
+            index_overflow: false,
+            logic: false,
+        }
+    }
+
+    // XO-CHIP, as implemented by Octo
+    pub fn xochip() -> Quirks
+    {
+        Quirks {
+            shift: ShiftQuirk::Vy,
+            load_store: LoadStoreQuirk::Increment,
+            jump: JumpQuirk::V0,
+            wrap: WrapQuirk::Wrap,
+            vblank: false,
+            index_overflow: false,
+            logic: false,
+        }
+    }
+}
+
+impl FromStr for Quirks
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s {
+            "chip8" => Ok(Quirks::chip8()),
+            "schip" => Ok(Quirks::schip()),
+            "xochip" => Ok(Quirks::xochip()),
+            _ => Err(format!("unknown quirks preset '{}', expected chip8, schip or xochip", s)),
+        }
+    }
+}
+
+// Instruction that matches none of the supported opcodes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnknownOpcode
+{
+    pub opcode: u16,
+    pub pc: usize,
+}
+
+impl fmt::Display for UnknownOpcode
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "unknown opcode {:04X} at {:#05X}", self.opcode, self.pc)
+    }
+}
+
+// What the cpu does when it runs into an unknown opcode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnknownOpcodePolicy
+{
+    Ignore, // skip the instruction, like the original interpreters
+    Pause,  // report it and pause the cpu on the instruction
+}
+
+impl FromStr for UnknownOpcodePolicy
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s {
+            "ignore" => Ok(UnknownOpcodePolicy::Ignore),
+            "pause" => Ok(UnknownOpcodePolicy::Pause),
+            _ => Err(format!("unknown opcode policy '{}', expected ignore or pause", s)),
+        }
+    }
+}
+
+// What a cpu cycle did, for the frontends driving the cpu
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CycleOutcome
+{
+    Paused,                              // the cpu is paused and nothing ran, or the instruction paused it
+    Breakpoint,                          // a breakpoint paused the cpu before the instruction
+    Watchpoint { address: usize, old: u8, new: u8 }, // the instruction changed a watched byte and paused the cpu
+    LimitReached,                        // max_instructions were executed, nothing ran
+    StackError { error: StackError, pc: usize }, // the CALL or RET at pc failed and paused the cpu on it
+    WaitingForInput,                     // Fx0A is waiting for a key to be released
+    WaitingForVblank,                    // a draw is waiting for the next timer tick (vblank quirk)
+    Drew { opcode: u16, pc: usize },     // the instruction changed the display
+    Beeped { opcode: u16, pc: usize },   // the instruction started the beep
+    Halted { pc: usize },                // the instruction jumped to itself, the program ended
+    Executed { opcode: u16, pc: usize }, // any other instruction
+}
+
+// Relative cost of an instruction in cpu cycles, the plain ones costing 1.
+// Clearing or scrolling the screen and drawing a sprite, one more cycle per row, are the slow ones
+// along with the memory transfers and the decimal conversion.
+pub fn cost(opcode: u16) -> u32
+{
+    let x = ((opcode & 0x0F00) >> 8) as u32;
+    let n = (opcode & 0x000F) as u32;
+    match opcode & 0xF000 {
+        0x0000 if matches!(opcode, 0x00E0 | 0x00FB | 0x00FC) || opcode & 0xFFF0 == 0x00C0 => 4,
+        0xD000 if n == 0 => 2 + 16,
+        0xD000 => 2 + n,
+        0xF000 => match opcode & 0x00FF {
+            0x33 => 3,
+            0x55 | 0x65 => 1 + (x + 2) / 2,
+            _ => 1,
+        },
+        _ => 1,
+    }
+}
+
+impl CycleOutcome
+{
+    // Cpu cycles the outcome took, waiting and pausing costing a single cycle
+    pub fn cost(&self) -> u32
+    {
+        match *self {
+            CycleOutcome::Drew { opcode, .. }
+            | CycleOutcome::Beeped { opcode, .. }
+            | CycleOutcome::Executed { opcode, .. } => cost(opcode),
+            _ => 1,
+        }
+    }
+}
+
+// Bytes taken by the instruction at address, the XO-CHIP F000 nnnn long load takes 4
+fn instruction_size(memory: &Memory, address: usize) -> usize
+{
+    if memory[address % memory.len()] == 0xF0 && memory[(address + 1) % memory.len()] == 0x00 {
+        OPCODE_SIZE * 2
+    } else {
+        OPCODE_SIZE
+    }
+}
+
+// Instructions changing the display: clear, scrolls, resolution changes and sprites
+fn changes_display(opcode: u16) -> bool
+{
+    matches!(opcode, 0x00E0 | 0x00FB | 0x00FC | 0x00FE | 0x00FF) || opcode & 0xFFF0 == 0x00C0 || opcode & 0xF000 == 0xD000
+}
+
+// Describe an instruction that took longer than the allowed threshold to execute
+fn slow_report(opcode: u16, pc: usize, duration: Duration, threshold: Duration) -> Option<String>
+{
+    if duration <= threshold {
+        return None;
+    }
+    Some(format!("slow instruction {:04X} at {:#05X}: took {}us (threshold {}us)",
+        opcode, pc, duration.as_micros(), threshold.as_micros()))
+}
+
 pub struct Cpu
 {
     v_registers: [u8; 16],
@@ -77,42 +424,242 @@ pub struct Cpu
     sound_timer_register: u8,
 
     pc : usize,
+    start_address: usize,
 
     stack : Stack,
 
     opcode: u16,
 
     waiting_for_input: bool,
+    waiting_for_vblank: bool,
     input_register: usize,
+    // keys seen pressed since Fx0A started waiting, one bit per key
+    input_pressed: u16,
+
+    // XO-CHIP drawing planes bitmask
+    selected_planes: u8,
+
+    // XO-CHIP audio pattern and pitch
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+
+    // Fx75 and Fx85 flags, kept across resets like the HP48 did
+    flags: [u8; FLAG_REGISTERS],
+
+    cycles: u64,
+    // executed instructions, unlike the cycles not reset with the statistics
+    instructions: u64,
+    collisions: u64,
+    paused: bool,
+    breakpoints: HashSet<u16>,
+    // lets the instruction at a breakpoint run once the cpu is resumed on it
+    skip_breakpoint: bool,
+    watchpoints: HashSet<u16>,
+    // first watched byte changed by the current instruction: address, old and new values
+    watch_hit: Option<(usize, u8, u8)>,
+    // stack overflow or underflow of the current instruction
+    stack_error: Option<StackError>,
+    trace_log: VecDeque<String>,
+    opcode_histogram: [u64; 16],
+    // Cxkk random numbers, seeded for reproducible runs
+    rng: StdRng,
+    // addresses of the unknown opcodes already logged
+    warned_unknown: HashSet<usize>,
 
     pub beeping: bool,
+    pub timers_frozen: bool,
+    pub slow_threshold: Option<Duration>,
+    pub shift_quirk: ShiftQuirk,
+    pub load_store_quirk: LoadStoreQuirk,
+    pub jump_quirk: JumpQuirk,
+    pub wrap_quirk: WrapQuirk,
+    // COSMAC VIP: Dxyn waits for the next frame before the cpu goes on
+    pub vblank_quirk: bool,
+    // Amiga interpreter: Fx1E sets VF when I goes past 0xFFF
+    pub index_overflow_quirk: bool,
+    // COSMAC VIP: 8xy1, 8xy2 and 8xy3 reset VF
+    pub logic_quirk: bool,
+    pub unknown_opcode: UnknownOpcodePolicy,
+    // log skipped unknown opcodes to stderr, once per address
+    pub warn_unknown: bool,
+    pub trace: bool,
+    // trace the disassembly with the values of the operands read instead of the registers
+    pub trace_disasm: bool,
+    // count the executed instructions by opcode class
+    pub profile: bool,
+    // stop executing instructions after this many, e.g. for a ROM stuck in a loop in headless runs
+    pub max_instructions: Option<u64>,
+    // panic on stack overflows and underflows instead of pausing the cpu
+    pub strict_stack: bool,
+}
+
+impl Default for Cpu
+{
+    fn default() -> Cpu
+    {
+        Cpu::new()
+    }
 }
 
 impl Cpu
 {
     pub fn new() -> Cpu
+    {
+        Cpu::new_with_start(PROGRAM_START_ADDRESS)
+    }
+
+    // Cpu starting the execution at start_address, where the ROM was loaded
+    pub fn new_with_start(start_address: usize) -> Cpu
+    {
+        Cpu::new_with_quirks(start_address, Quirks::default())
+    }
+
+    // Cpu starting at start_address and behaving as the interpreter the quirks come from
+    pub fn new_with_quirks(start_address: usize, quirks: Quirks) -> Cpu
     {
         Cpu {
             v_registers: [0; 16],
             i_register: 0,
             delay_timer_register: 0,
             sound_timer_register: 0,
-            pc: PROGRAM_START_ADDRESS,
+            pc: start_address,
+            start_address,
             stack: Stack::new(),
             opcode: 0,
             waiting_for_input: false,
+            waiting_for_vblank: false,
             input_register: 0,
+            input_pressed: 0,
+            selected_planes: 0x01,
+            audio_pattern: None,
+            pitch: 64,
+            flags: [0; FLAG_REGISTERS],
+            cycles: 0,
+            instructions: 0,
+            collisions: 0,
+            paused: false,
+            breakpoints: HashSet::new(),
+            skip_breakpoint: false,
+            watchpoints: HashSet::new(),
+            watch_hit: None,
+            stack_error: None,
+            trace_log: VecDeque::new(),
+            opcode_histogram: [0; 16],
+            rng: StdRng::from_entropy(),
+            warned_unknown: HashSet::new(),
             beeping: false,
+            timers_frozen: false,
+            slow_threshold: None,
+            shift_quirk: quirks.shift,
+            load_store_quirk: quirks.load_store,
+            jump_quirk: quirks.jump,
+            wrap_quirk: quirks.wrap,
+            vblank_quirk: quirks.vblank,
+            index_overflow_quirk: quirks.index_overflow,
+            logic_quirk: quirks.logic,
+            unknown_opcode: UnknownOpcodePolicy::Ignore,
+            warn_unknown: false,
+            trace: false,
+            trace_disasm: false,
+            profile: false,
+            max_instructions: None,
+            strict_stack: false,
+        }
+    }
+
+    // Back to the power-on state, keeping the configuration, pause state and statistics
+    pub fn reset(&mut self)
+    {
+        *self = Cpu {
+            cycles: self.cycles,
+            instructions: self.instructions,
+            collisions: self.collisions,
+            paused: self.paused,
+            breakpoints: self.breakpoints.clone(),
+            watchpoints: self.watchpoints.clone(),
+            timers_frozen: self.timers_frozen,
+            slow_threshold: self.slow_threshold,
+            shift_quirk: self.shift_quirk,
+            load_store_quirk: self.load_store_quirk,
+            jump_quirk: self.jump_quirk,
+            wrap_quirk: self.wrap_quirk,
+            vblank_quirk: self.vblank_quirk,
+            index_overflow_quirk: self.index_overflow_quirk,
+            logic_quirk: self.logic_quirk,
+            unknown_opcode: self.unknown_opcode,
+            warn_unknown: self.warn_unknown,
+            warned_unknown: self.warned_unknown.clone(),
+            trace: self.trace,
+            trace_disasm: self.trace_disasm,
+            profile: self.profile,
+            max_instructions: self.max_instructions,
+            strict_stack: self.strict_stack,
+            flags: self.flags,
+            opcode_histogram: self.opcode_histogram,
+            rng: self.rng.clone(),
+            ..Cpu::new_with_start(self.start_address)
+        };
+    }
+
+    // Write the opcodes at the start address and point pc at the first one, fails when they do not fit
+    pub fn load_program(&mut self, memory: &mut Memory, opcodes: &[u16]) -> Result<(), io::Error>
+    {
+        memory.write_opcodes(self.start_address, opcodes)?;
+        self.pc = self.start_address;
+        Ok(())
+    }
+
+    // The quirks currently in effect
+    pub fn quirks(&self) -> Quirks
+    {
+        Quirks {
+            shift: self.shift_quirk,
+            load_store: self.load_store_quirk,
+            jump: self.jump_quirk,
+            wrap: self.wrap_quirk,
+            vblank: self.vblank_quirk,
+            index_overflow: self.index_overflow_quirk,
+            logic: self.logic_quirk,
         }
     }
 
+    // Fetch the opcode at pc, 0x0000 when pc is outside of the RAM
     pub fn fetch_opcode(&mut self, memory: &Memory) -> u16
     {
-        self.opcode = (memory[self.pc] as u16) << 8 | memory[self.pc + 1] as u16;
+        self.opcode = if self.pc_in_range(memory) {
+            (memory[self.pc] as u16) << 8 | memory[self.pc + 1] as u16
+        } else {
+            0x0000
+        };
         self.opcode
     }
 
+    fn pc_in_range(&self, memory: &Memory) -> bool
+    {
+        self.pc + 1 < memory.len()
+    }
+
+    // Execute the current opcode, unknown ones being handled as the unknown_opcode policy says
     pub fn execute_opcode(&mut self, memory: &mut Memory, keyboard: &Keyboard)
+    {
+        if let Err(error) = self.try_execute_opcode(memory, keyboard) {
+            match self.unknown_opcode {
+                UnknownOpcodePolicy::Ignore => {
+                    if self.warn_unknown && self.warned_unknown.insert(error.pc) {
+                        eprintln!("{}, skipping it", error);
+                    }
+                    self.pc += OPCODE_SIZE;
+                },
+                UnknownOpcodePolicy::Pause => {
+                    eprintln!("{}, pausing the cpu", error);
+                    self.paused = true;
+                },
+            }
+        }
+    }
+
+    // Execute the current opcode, leaving pc on it when the opcode is unknown
+    pub fn try_execute_opcode(&mut self, memory: &mut Memory, keyboard: &Keyboard) -> Result<(), UnknownOpcode>
     {
         let splitted_opcode = (
             ((self.opcode & 0xF000) >> 12) as u8,
@@ -125,10 +672,25 @@ impl Cpu
         let x = splitted_opcode.1 as usize;
         let y = splitted_opcode.2 as usize;
         let n = splitted_opcode.3 as usize;
+        let pc = self.pc;
+        // the operands must be read before the instruction overwrites them
+        let operands = if self.trace && self.trace_disasm {
+            self.read_operands(&disassemble(self.opcode))
+        } else {
+            Vec::new()
+        };
+        if self.profile {
+            self.opcode_histogram[splitted_opcode.0 as usize] += 1;
+        }
 
         let program_counter_next_operation = match splitted_opcode {
+            (0x00, 0x00, 0x0c, _) => self.op_00cn(n, &mut memory.display),
             (0x00, 0x00, 0x0e, 0x00) => self.op_00e0(&mut memory.display),
             (0x00, 0x00, 0x0e, 0x0e) => self.op_00ee(),
+            (0x00, 0x00, 0x0f, 0x0b) => self.op_00fb(&mut memory.display),
+            (0x00, 0x00, 0x0f, 0x0c) => self.op_00fc(&mut memory.display),
+            (0x00, 0x00, 0x0f, 0x0e) => self.op_00fe(&mut memory.display),
+            (0x00, 0x00, 0x0f, 0x0f) => self.op_00ff(&mut memory.display),
             (0x01, _, _, _) => self.op_1nnn(nnn),
             (0x02, _, _, _) => self.op_2nnn(nnn),
             (0x03, _, _, _) => self.op_3xkk(x, kk),
@@ -147,309 +709,1547 @@ impl Cpu
             (0x08, _, _, 0x0E) => self.op_8xye(x, y),
             (0x09, _, _, 0x00) => self.op_9xy0(x, y),
             (0x0A, _, _, _) => self.op_annn(nnn),
-            (0x0B, _, _, _) => self.op_bnnn(nnn),
+            (0x0B, _, _, _) => self.op_bnnn(x, nnn),
             (0x0C, _, _, _) => self.op_cxkk(x, kk),
             (0x0d, _, _, _) => self.op_dxyn(x, y, n, memory),
             (0x0e, _, 0x09, 0x0e) => self.op_ex9e(x, keyboard),
             (0x0e, _, 0x0a, 0x01) => self.op_exa1(x, keyboard),
+            (0x0f, _, 0x00, 0x01) => self.op_fn01(x),
+            (0x0f, 0x00, 0x00, 0x00) => self.op_f000(memory),
+            (0x0f, 0x00, 0x00, 0x02) => self.op_f002(memory),
             (0x0f, _, 0x00, 0x07) => self.op_fx07(x),
             (0x0f, _, 0x00, 0x0a) => self.op_fx0a(x),
             (0x0f, _, 0x01, 0x05) => self.op_fx15(x),
             (0x0f, _, 0x01, 0x08) => self.op_fx18(x),
+            (0x0f, _, 0x03, 0x0a) => self.op_fx3a(x),
             (0x0f, _, 0x01, 0x0e) => self.op_fx1e(x),
             (0x0f, _, 0x02, 0x09) => self.op_fx29(x),
+            (0x0f, _, 0x03, 0x00) => self.op_fx30(x),
             (0x0f, _, 0x03, 0x03) => self.op_fx33(x, memory),
             (0x0f, _, 0x05, 0x05) => self.op_fx55(x, memory),
             (0x0f, _, 0x06, 0x05) => self.op_fx65(x, memory),
-            _ => ProgramCounter::NEXT,
+            (0x0f, _, 0x07, 0x05) => self.op_fx75(x),
+            (0x0f, _, 0x08, 0x05) => self.op_fx85(x),
+            _ => return Err(UnknownOpcode { opcode: self.opcode, pc }),
         };
         match program_counter_next_operation {
             ProgramCounter::NEXT => self.pc += OPCODE_SIZE,
-            ProgramCounter::SKIP => self.pc += OPCODE_SIZE * 2,
+            ProgramCounter::SKIP => self.pc += OPCODE_SIZE + instruction_size(memory, self.pc + OPCODE_SIZE),
             ProgramCounter::JUMP(address) => self.pc = address as usize,
         }
+        if self.trace {
+            self.trace_instruction(pc, x, y, &operands);
+        }
+        Ok(())
     }
 
-    pub fn update_timers(&mut self) -> Result<(), ()>
+    // Log the instruction executed at pc with the registers it may have used or changed
+    fn trace_instruction(&mut self, pc: usize, x: usize, y: usize, operands: &[String])
     {
-        if !self.waiting_for_input {
-            if self.delay_timer_register > 0 {
-                self.delay_timer_register -= 1;
-            }
-            if self.sound_timer_register > 0 {
-                self.sound_timer_register -= 1;
-            }
-            return Ok(())
+        if self.trace_log.len() == TRACE_CAPACITY {
+            self.trace_log.pop_front();
         }
-        return Err(());
+        let line = if !self.trace_disasm {
+            format!("{:#05X}: {:04X} {:<18} V{:X}={:02X} V{:X}={:02X} VF={:02X} I={:#05X}",
+                pc, self.opcode, disassemble(self.opcode),
+                x, self.v_registers[x], y, self.v_registers[y], self.v_registers[0xF], self.i_register)
+        } else if operands.is_empty() {
+            format!("{:#05X}: {:04X}  {}", pc, self.opcode, disassemble(self.opcode))
+        } else {
+            format!("{:#05X}: {:04X}  {:<18} ; {}", pc, self.opcode, disassemble(self.opcode), operands.join(" "))
+        };
+        self.trace_log.push_back(line);
     }
 
-    pub fn do_cycle(&mut self, memory: &mut Memory, keyboard: &Keyboard)
+    // Values of the registers an instruction reads, e.g. ["VA=05", "VB=07"] for SUB VA, VB.
+    // The destination of a load or a random number is only written, except [I] which reads I.
+    fn read_operands(&self, mnemonic: &str) -> Vec<String>
     {
-        if self.waiting_for_input && keyboard.iter().any(|x| *x == 1) {
-            self.waiting_for_input = false;
-            self.v_registers[self.input_register] = keyboard.iter().position(|x| *x == 1 as u8).unwrap() as u8;
-        }
-        if !self.waiting_for_input {
-            // execute new instruction
-            self.fetch_opcode(memory);
-            self.execute_opcode(memory, keyboard);
+        let mut parts = mnemonic.splitn(2, ' ');
+        let name = parts.next().unwrap_or("");
+        let operands = parts.next().unwrap_or("");
+        let written_only = |index: usize, operand: &str| index == 0 && operand != "[I]" && (name == "LD" || name == "RND");
+        let mut values: Vec<String> = operands.split(", ").enumerate()
+            .filter(|&(index, operand)| !written_only(index, operand))
+            .filter_map(|(_, operand)| match operand {
+                "I" | "[I]" => Some(format!("I={:#05X}", self.i_register)),
+                _ if operand.len() == 2 && operand.starts_with('V') => usize::from_str_radix(&operand[1..], 16).ok()
+                    .map(|register| format!("{}={:02X}", operand, self.v_registers[register])),
+                _ => None,
+            })
+            .collect();
+        // e.g. ADD VA, VA
+        values.dedup();
+        values
+    }
 
-            if self.sound_timer_register > 0 {
-                self.beeping = true;
-            } else {
-                self.beeping = false;
-            }
-        }
+    // Take the trace lines logged since the last call
+    pub fn take_trace(&mut self) -> Vec<String>
+    {
+        self.trace_log.drain(..).collect()
     }
 
-    // opcode instructions:
-    //
-    // variables meanings
-    // nnn or addr - A 12-bit value, the lowest 12 bits of the instruction
-    // n or nibble - A 4-bit value, the lowest 4 bits of the instruction
-    // x - A 4-bit value, the lower 4 bits of the high byte of the instruction
-    // y - A 4-bit value, the upper 4 bits of the low byte of the instruction
-    // kk or byte - An 8-bit value, the lowest 8 bits of the instruction
-    //
-    // (notation come from [Cowgod's Chip-8 technical documentation](http://devernay.free.fr/hacks/chip8/C8TECH10.HTM))
-    //
+    // Run a single given instruction as if it had just been fetched, for tests and tools
+    pub fn execute_raw(&mut self, opcode: u16, memory: &mut Memory, keyboard: &Keyboard)
+    {
+        self.opcode = opcode;
+        self.execute_opcode(memory, keyboard);
+    }
 
-    fn op_00e0(&self, display: &mut Display) -> ProgramCounter // CLS - clear the display
+    // Draw the Cxkk random numbers from a generator seeded with seed, making the run reproducible
+    pub fn set_seed(&mut self, seed: u64)
     {
-        display.clear();
-        ProgramCounter::NEXT
+        self.rng = StdRng::seed_from_u64(seed);
     }
 
-    fn op_00ee(&mut self) -> ProgramCounter // RET - return from a subroutine
+    // RPL user flags stored by Fx75, e.g. to save them for the next run
+    pub fn flags(&self) -> [u8; FLAG_REGISTERS]
     {
-        ProgramCounter::JUMP(self.stack.pop())
+        self.flags
     }
 
-    fn op_1nnn(&mut self, nnn: u16) -> ProgramCounter // JP addr - Jump at location nnn
+    // Set the first RPL user flags, e.g. saved by a previous run, the extra bytes being ignored
+    pub fn set_flags(&mut self, flags: &[u8])
     {
-        ProgramCounter::JUMP(nnn)
+        let count = flags.len().min(FLAG_REGISTERS);
+        self.flags[..count].copy_from_slice(&flags[..count]);
     }
 
-    fn op_2nnn(&mut self, nnn: u16) -> ProgramCounter // CALL addr - Call subroutine at location nnn
+    // Number of instructions executed so far
+    pub fn cycles(&self) -> u64
     {
-        self.stack.push((self.pc + OPCODE_SIZE) as u16);
-        ProgramCounter::JUMP(nnn)
+        self.cycles
     }
 
-    fn op_3xkk(&mut self, x: usize, kk: u8) -> ProgramCounter // SE Vx, byte - Skip next instruction if Vx = kk
+    // Deepest level of nested subroutines reached so far
+    pub fn peak_stack_depth(&self) -> usize
     {
-        ProgramCounter::skip_if(self.v_registers[x] == kk)
+        self.stack.peak_depth
     }
 
-    fn op_4xkk(&mut self, x: usize, kk: u8) -> ProgramCounter // SNE Vx, byte - Skip next instruction if Vx != kk
+    // Number of Dxyn instructions that set VF
+    pub fn collision_count(&self) -> u64
     {
-        ProgramCounter::skip_if(self.v_registers[x] != kk)
+        self.collisions
     }
 
-    fn op_5xy0(&mut self, x: usize, y: usize) -> ProgramCounter // SE Vx, Vy - Skip next instruction if Vx = Vy
+    // Executed instructions counted by the high nibble of their opcode, when profiling
+    pub fn opcode_histogram(&self) -> [u64; 16]
     {
-        ProgramCounter::skip_if(self.v_registers[x] == self.v_registers[y])
+        self.opcode_histogram
     }
 
-    fn op_6xkk(&mut self, x: usize, kk: u8) -> ProgramCounter // LD Vx, byte - Set Vx = kk
+    pub fn reset_histogram(&mut self)
     {
-        self.v_registers[x] = kk;
-        ProgramCounter::NEXT
+        self.opcode_histogram = [0; 16];
     }
 
-    fn op_7xkk(&mut self, x: usize, kk: u8) -> ProgramCounter // ADD Vx, byte - Set Vx = Vx + kk.
+    // Reset the cycle, collision and peak stack depth statistics
+    pub fn reset_stats(&mut self)
     {
-        self.v_registers[x] = self.v_registers[x].wrapping_add(kk);
-        ProgramCounter::NEXT
+        self.cycles = 0;
+        self.collisions = 0;
+        self.stack.peak_depth = self.stack.stack_pointer;
     }
 
-    fn op_8xy0(&mut self, x: usize, y: usize) -> ProgramCounter // LD Vx, Vy- Set Vx = Vy.
+    // XO-CHIP audio to play while the sound timer runs, None until a pattern is loaded with F002
+    pub fn audio_buffer(&self) -> Option<AudioBuffer>
     {
-        self.v_registers[x] = self.v_registers[y];
-        ProgramCounter::NEXT
+        self.audio_pattern.map(|pattern| AudioBuffer { pattern, pitch: self.pitch })
     }
 
-    fn op_8xy1(&mut self, x: usize, y: usize) -> ProgramCounter // OR Vx, Vy - Set Vx = Vx OR Vy.
+    // Capture the cpu, memory and display state
+    pub fn snapshot(&self, memory: &Memory) -> Snapshot
     {
-        self.v_registers[x] = self.v_registers[x] | self.v_registers[y];
-        ProgramCounter::NEXT
+        Snapshot {
+            v_registers: self.v_registers,
+            i_register: self.i_register,
+            delay_timer_register: self.delay_timer_register,
+            sound_timer_register: self.sound_timer_register,
+            pc: self.pc as u16,
+            stack: self.stack.stack,
+            stack_pointer: self.stack.stack_pointer as u8,
+            opcode: self.opcode,
+            waiting_for_input: self.waiting_for_input,
+            input_register: self.input_register as u8,
+            selected_planes: self.selected_planes,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            memory: memory.memory.to_vec(),
+            display_sizes: memory.display.get_sizes(),
+            display: memory.display.pixels().to_vec(),
+        }
     }
 
-    fn op_8xy2(&mut self, x: usize, y: usize) -> ProgramCounter // AND Vx, Vy - Set Vx = Vx AND Vy.
+    // Restore a state captured by snapshot, the display must be in the same resolution
+    pub fn restore(&mut self, memory: &mut Memory, snapshot: &Snapshot) -> Result<(), String>
     {
-        self.v_registers[x] = self.v_registers[x] & self.v_registers[y];
-        ProgramCounter::NEXT
+        if snapshot.display_sizes != memory.display.get_sizes() {
+            return Err(format!("save state display is {}x{}, current display is {}x{}",
+                snapshot.display_sizes.0, snapshot.display_sizes.1,
+                memory.display.get_sizes().0, memory.display.get_sizes().1));
+        }
+        if snapshot.memory.len() != memory.memory.len() {
+            return Err(format!("save state memory is {} bytes, current memory is {} bytes",
+                snapshot.memory.len(), memory.memory.len()));
+        }
+        self.v_registers = snapshot.v_registers;
+        self.i_register = snapshot.i_register;
+        self.delay_timer_register = snapshot.delay_timer_register;
+        self.sound_timer_register = snapshot.sound_timer_register;
+        self.beeping = self.sound_timer_register > 0;
+        self.pc = snapshot.pc as usize;
+        self.stack.stack = snapshot.stack;
+        self.stack.stack_pointer = snapshot.stack_pointer as usize;
+        self.opcode = snapshot.opcode;
+        self.waiting_for_input = snapshot.waiting_for_input;
+        self.input_register = snapshot.input_register as usize;
+        // neither is saved, a key held or a vblank wait from before the restore no longer applies
+        self.input_pressed = 0;
+        self.waiting_for_vblank = false;
+        self.selected_planes = snapshot.selected_planes;
+        self.audio_pattern = snapshot.audio_pattern;
+        self.pitch = snapshot.pitch;
+        memory.memory.copy_from_slice(&snapshot.memory);
+        memory.display.pixels_mut().copy_from_slice(&snapshot.display);
+        Ok(())
+    }
+
+    // Called once per frame, also ends the wait of a Dxyn in vblank quirk mode
+    #[allow(clippy::result_unit_err)]
+    pub fn update_timers(&mut self) -> Result<(), ()>
+    {
+        self.waiting_for_vblank = false;
+        if self.timers_frozen {
+            // debugging aid: timers hold their value but the cpu keeps running
+            return Ok(())
+        }
+        if !self.waiting_for_input {
+            if self.delay_timer_register > 0 {
+                self.delay_timer_register -= 1;
+            }
+            if self.sound_timer_register > 0 {
+                self.sound_timer_register -= 1;
+                // the beep started by Fx18 lasts until the timer runs out, whatever the clock rate
+                if self.sound_timer_register == 0 {
+                    self.beeping = false;
+                }
+            }
+            return Ok(())
+        }
+        return Err(());
     }
 
-    fn op_8xy3(&mut self, x: usize, y: usize) -> ProgramCounter // XOR Vx, Vy - Set Vx = Vx XOR Vy.
+    pub fn pause(&mut self)
     {
-        self.v_registers[x] = self.v_registers[x] ^ self.v_registers[y];
-        ProgramCounter::NEXT
+        self.paused = true;
     }
 
-    fn op_8xy4(&mut self, x: usize, y: usize) -> ProgramCounter // ADD Vx, Vy - Set Vx = Vx + Vy, set VF = carry.
+    pub fn resume(&mut self)
     {
-        let vx = self.v_registers[x] as u16;
-        let vy = self.v_registers[y] as u16;
-        let vx = vx + vy;
-        self.v_registers[0x0F] = if vx > 255 { 1 } else { 0 };
-        self.v_registers[x] = vx as u8;
-        ProgramCounter::NEXT
+        self.paused = false;
+        self.skip_breakpoint = true;
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16)
+    {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16)
+    {
+        self.breakpoints.remove(&address);
+    }
+
+    // Pause the cpu after an instruction changing the byte at address
+    pub fn add_watchpoint(&mut self, address: u16)
+    {
+        self.watchpoints.insert(address);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: u16)
+    {
+        self.watchpoints.remove(&address);
+    }
+
+    pub fn is_paused(&self) -> bool
+    {
+        self.paused
+    }
+
+    // Execute a single cycle regardless of the pause state.
+    // A pending Fx0A key wait is honored: stepping without any key pressed
+    // leaves the cpu waiting on the same instruction instead of skipping it.
+    pub fn step(&mut self, memory: &mut Memory, keyboard: &Keyboard) -> CycleOutcome
+    {
+        self.run_cycle(memory, keyboard)
+    }
+
+    // Execute a cycle, unless the cpu is paused. Timers are not affected by the pause.
+    // Reaching a breakpoint pauses the cpu before the instruction runs and returns true.
+    pub fn do_cycle(&mut self, memory: &mut Memory, keyboard: &Keyboard) -> bool
+    {
+        self.cycle(memory, keyboard) == CycleOutcome::Breakpoint
+    }
+
+    // Execute a cycle, unless the cpu is paused, and describe what it did
+    pub fn cycle(&mut self, memory: &mut Memory, keyboard: &Keyboard) -> CycleOutcome
+    {
+        if self.paused {
+            return CycleOutcome::Paused;
+        }
+        if !self.skip_breakpoint && self.breakpoints.contains(&(self.pc as u16)) {
+            self.paused = true;
+            return CycleOutcome::Breakpoint;
+        }
+        self.skip_breakpoint = false;
+        if self.instruction_limit_reached() {
+            return CycleOutcome::LimitReached;
+        }
+        self.run_cycle(memory, keyboard)
+    }
+
+    pub fn instruction_limit_reached(&self) -> bool
+    {
+        matches!(self.max_instructions, Some(max) if self.instructions >= max)
+    }
+
+    // The program ended on the usual 1nnn jump to itself, running it further changes nothing
+    pub fn is_halted(&self, memory: &Memory) -> bool
+    {
+        self.pc_in_range(memory) && memory[self.pc] >> 4 == 0x1
+            && ((memory[self.pc] as usize & 0x0F) << 8 | memory[self.pc + 1] as usize) == self.pc
+    }
+
+    fn run_cycle(&mut self, memory: &mut Memory, keyboard: &Keyboard) -> CycleOutcome
+    {
+        if self.waiting_for_vblank {
+            return CycleOutcome::WaitingForVblank;
+        }
+        if self.waiting_for_input {
+            self.wait_for_key_release(keyboard);
+            if self.waiting_for_input {
+                return CycleOutcome::WaitingForInput;
+            }
+        }
+        if !self.pc_in_range(memory) {
+            eprintln!("program counter {:#05X} is out of memory, pausing the cpu", self.pc);
+            self.paused = true;
+            return CycleOutcome::Paused;
+        }
+        // execute new instruction, stepping a paused cpu
+        let pc = self.pc;
+        let was_paused = self.paused;
+        let was_beeping = self.beeping;
+        self.fetch_opcode(memory);
+        match self.slow_threshold {
+            None => self.execute_opcode(memory, keyboard),
+            Some(threshold) => {
+                let start = Instant::now();
+                self.execute_opcode(memory, keyboard);
+                if let Some(report) = slow_report(self.opcode, pc, start.elapsed(), threshold) {
+                    eprintln!("{}", report);
+                }
+            },
+        }
+        self.cycles += 1;
+        self.instructions += 1;
+
+        let opcode = self.opcode;
+        if let Some(error) = self.stack_error.take() {
+            CycleOutcome::StackError { error, pc }
+        } else if let Some((address, old, new)) = self.watch_hit.take() {
+            self.paused = true;
+            CycleOutcome::Watchpoint { address, old, new }
+        } else if self.paused && !was_paused {
+            CycleOutcome::Paused
+        } else if self.waiting_for_input {
+            CycleOutcome::WaitingForInput
+        } else if changes_display(opcode) {
+            CycleOutcome::Drew { opcode, pc }
+        } else if self.beeping && !was_beeping {
+            CycleOutcome::Beeped { opcode, pc }
+        } else if opcode == 0x1000 | pc as u16 {
+            CycleOutcome::Halted { pc }
+        } else {
+            CycleOutcome::Executed { opcode, pc }
+        }
+    }
+
+    // Fx0A completes once a key pressed during the wait is released, a key held
+    // from before the wait has to be released after being seen pressed as well
+    fn wait_for_key_release(&mut self, keyboard: &Keyboard)
+    {
+        let released = (0..16).find(|&i| self.input_pressed & 1 << i != 0 && keyboard[i] == 0);
+        if let Some(key) = released {
+            self.waiting_for_input = false;
+            self.input_pressed = 0;
+            self.v_registers[self.input_register] = key as u8;
+            return;
+        }
+        for (i, &pressed) in keyboard.iter().enumerate() {
+            if pressed == 1 {
+                self.input_pressed |= 1 << i;
+            }
+        }
+    }
+
+    // opcode instructions:
+    //
+    // variables meanings
+    // nnn or addr - A 12-bit value, the lowest 12 bits of the instruction
+    // n or nibble - A 4-bit value, the lowest 4 bits of the instruction
+    // x - A 4-bit value, the lower 4 bits of the high byte of the instruction
+    // y - A 4-bit value, the upper 4 bits of the low byte of the instruction
+    // kk or byte - An 8-bit value, the lowest 8 bits of the instruction
+    //
+    // (notation come from [Cowgod's Chip-8 technical documentation](http://devernay.free.fr/hacks/chip8/C8TECH10.HTM))
+    //
+
+    fn op_00cn(&self, n: usize, display: &mut Display) -> ProgramCounter // SCD n - Scroll the display down n rows (SUPER-CHIP)
+    {
+        display.scroll_down(n);
+        ProgramCounter::NEXT
+    }
+
+    fn op_00fb(&self, display: &mut Display) -> ProgramCounter // SCR - Scroll the display right 4 columns (SUPER-CHIP)
+    {
+        display.scroll_right();
+        ProgramCounter::NEXT
+    }
+
+    fn op_00fc(&self, display: &mut Display) -> ProgramCounter // SCL - Scroll the display left 4 columns (SUPER-CHIP)
+    {
+        display.scroll_left();
+        ProgramCounter::NEXT
+    }
+
+    fn op_00e0(&self, display: &mut Display) -> ProgramCounter // CLS - clear the display
+    // With XO-CHIP planes, only the selected planes are cleared.
+    {
+        display.clear_planes(self.selected_planes);
+        ProgramCounter::NEXT
+    }
+
+    fn op_00ee(&mut self) -> ProgramCounter // RET - return from a subroutine
+    // The stack holds the address of the CALL, execution resumes on the instruction following it.
+    {
+        match self.stack.pop() {
+            Ok(address) => {
+                self.pc = address as usize;
+                ProgramCounter::NEXT
+            },
+            Err(error) => self.stack_failure(error),
+        }
+    }
+
+    fn op_00fe(&self, display: &mut Display) -> ProgramCounter // LOW - Disable high resolution mode (SUPER-CHIP)
+    {
+        display.set_resolution(false);
+        ProgramCounter::NEXT
+    }
+
+    fn op_00ff(&self, display: &mut Display) -> ProgramCounter // HIGH - Enable 128x64 high resolution mode (SUPER-CHIP)
+    {
+        display.set_resolution(true);
+        ProgramCounter::NEXT
+    }
+
+    fn op_1nnn(&mut self, nnn: u16) -> ProgramCounter // JP addr - Jump at location nnn
+    {
+        ProgramCounter::JUMP(nnn)
+    }
+
+    fn op_2nnn(&mut self, nnn: u16) -> ProgramCounter // CALL addr - Call subroutine at location nnn
+    // The address of the CALL itself is pushed, RET moves past it like any other instruction.
+    {
+        match self.stack.push(self.pc as u16) {
+            Ok(()) => ProgramCounter::JUMP(nnn),
+            Err(error) => self.stack_failure(error),
+        }
+    }
+
+    // Pause the cpu on the failed CALL or RET, or panic in strict mode
+    fn stack_failure(&mut self, error: StackError) -> ProgramCounter
+    {
+        if self.strict_stack {
+            panic!("ERROR: {} at {:#05X}: {:#?}", error, self.pc, self.stack);
+        }
+        self.stack_error = Some(error);
+        self.paused = true;
+        ProgramCounter::JUMP(self.pc as u16)
+    }
+
+    fn op_3xkk(&mut self, x: usize, kk: u8) -> ProgramCounter // SE Vx, byte - Skip next instruction if Vx = kk
+    {
+        ProgramCounter::skip_if(self.v_registers[x] == kk)
+    }
+
+    fn op_4xkk(&mut self, x: usize, kk: u8) -> ProgramCounter // SNE Vx, byte - Skip next instruction if Vx != kk
+    {
+        ProgramCounter::skip_if(self.v_registers[x] != kk)
+    }
+
+    fn op_5xy0(&mut self, x: usize, y: usize) -> ProgramCounter // SE Vx, Vy - Skip next instruction if Vx = Vy
+    {
+        ProgramCounter::skip_if(self.v_registers[x] == self.v_registers[y])
+    }
+
+    fn op_6xkk(&mut self, x: usize, kk: u8) -> ProgramCounter // LD Vx, byte - Set Vx = kk
+    {
+        self.v_registers[x] = kk;
+        ProgramCounter::NEXT
+    }
+
+    fn op_7xkk(&mut self, x: usize, kk: u8) -> ProgramCounter // ADD Vx, byte - Set Vx = Vx + kk.
+    {
+        self.v_registers[x] = self.v_registers[x].wrapping_add(kk);
+        ProgramCounter::NEXT
+    }
+
+    fn op_8xy0(&mut self, x: usize, y: usize) -> ProgramCounter // LD Vx, Vy- Set Vx = Vy.
+    {
+        self.v_registers[x] = self.v_registers[y];
+        ProgramCounter::NEXT
+    }
+
+    fn op_8xy1(&mut self, x: usize, y: usize) -> ProgramCounter // OR Vx, Vy - Set Vx = Vx OR Vy.
+    // With the logic quirk, VF = 0.
+    {
+        self.v_registers[x] = self.v_registers[x] | self.v_registers[y];
+        if self.logic_quirk {
+            self.v_registers[0xF] = 0;
+        }
+        ProgramCounter::NEXT
+    }
+
+    fn op_8xy2(&mut self, x: usize, y: usize) -> ProgramCounter // AND Vx, Vy - Set Vx = Vx AND Vy.
+    // With the logic quirk, VF = 0.
+    {
+        self.v_registers[x] = self.v_registers[x] & self.v_registers[y];
+        if self.logic_quirk {
+            self.v_registers[0xF] = 0;
+        }
+        ProgramCounter::NEXT
+    }
+
+    fn op_8xy3(&mut self, x: usize, y: usize) -> ProgramCounter // XOR Vx, Vy - Set Vx = Vx XOR Vy.
+    // With the logic quirk, VF = 0.
+    {
+        self.v_registers[x] = self.v_registers[x] ^ self.v_registers[y];
+        if self.logic_quirk {
+            self.v_registers[0xF] = 0;
+        }
+        ProgramCounter::NEXT
+    }
+
+    fn op_8xy4(&mut self, x: usize, y: usize) -> ProgramCounter // ADD Vx, Vy - Set Vx = Vx + Vy, set VF = carry.
+    {
+        let vx = self.v_registers[x] as u16;
+        let vy = self.v_registers[y] as u16;
+        let vx = vx + vy;
+        self.v_registers[0x0F] = if vx > 255 { 1 } else { 0 };
+        self.v_registers[x] = vx as u8;
+        ProgramCounter::NEXT
+    }
+
+    fn op_8xy5(&mut self, x: usize, y: usize) -> ProgramCounter // SUB Vx, Vy - Set Vx = Vx - Vy, set VF = NOT borrow.
+    {
+        self.v_registers[0x0F] = if self.v_registers[x] >= self.v_registers[y] { 1 } else { 0 };
+        self.v_registers[x] = self.v_registers[x].wrapping_sub(self.v_registers[y]);
+        ProgramCounter::NEXT
+    }
+
+    fn shift_source(&self, x: usize, y: usize) -> u8
+    {
+        match self.shift_quirk {
+            ShiftQuirk::Vx => self.v_registers[x],
+            ShiftQuirk::Vy => self.v_registers[y],
+        }
+    }
+
+    fn op_8xy6(&mut self, x: usize, y: usize) -> ProgramCounter // SHR Vx {, Vy} - Set Vx = Vx SHR 1 (or Vy SHR 1).
+    {
+        let value = self.shift_source(x, y);
+        self.v_registers[0x0F] = value & 1;
+        self.v_registers[x] = value >> 1;
+        ProgramCounter::NEXT
+    }
+
+    fn op_8xy7(&mut self, x: usize, y: usize) -> ProgramCounter // SUBN Vx, Vy - Set Vx = Vy - Vx, set VF = NOT borrow.
+    {
+        self.v_registers[0x0F] = if self.v_registers[y] >= self.v_registers[x] { 1 } else { 0 };
+        self.v_registers[x] = self.v_registers[y].wrapping_sub(self.v_registers[x]);
+        ProgramCounter::NEXT
+    }
+
+    fn op_8xye(&mut self, x: usize, y: usize) -> ProgramCounter // SHL Vx {, Vy} - Set Vx = Vx SHL 1 (or Vy SHL 1).
+    {
+        let value = self.shift_source(x, y);
+        self.v_registers[0x0F] = (value & 0b10000000) >> 7;
+        self.v_registers[x] = value << 1;
+        ProgramCounter::NEXT
+    }
+
+    fn op_9xy0(&mut self, x: usize, y: usize) -> ProgramCounter // SNE Vx, Vy - Skip next instruction if Vx != Vy.
+    {
+        ProgramCounter::skip_if(self.v_registers[x] != self.v_registers[y])
+    }
+
+    fn op_annn(&mut self, nnn: u16) -> ProgramCounter // LD I, addr - Set I = nnn.
+    {
+        self.i_register = nnn;
+        ProgramCounter::NEXT
+    }
+
+    fn op_bnnn(&mut self, x: usize, nnn: u16) -> ProgramCounter // JP V0, addr - Jump to location nnn + V0.
+    // With the SUPER-CHIP jump quirk, jump to location xnn + Vx instead.
+    {
+        let offset = match self.jump_quirk {
+            JumpQuirk::V0 => self.v_registers[0],
+            JumpQuirk::Vx => self.v_registers[x],
+        };
+        ProgramCounter::JUMP(nnn.wrapping_add(offset as u16) & 0x0FFF)
+    }
+
+    fn op_cxkk(&mut self, x: usize, kk: u8) -> ProgramCounter // RND Vx, byte - Set Vx = random byte AND kk.
+    {
+        let random = self.rng.gen::<u8>();
+        self.v_registers[x] = random & kk;
+        ProgramCounter::NEXT
+    }
+
+    fn op_dxyn(&mut self, x: usize, y: usize, n: usize, memory: &mut Memory) -> ProgramCounter // DRW Vx, Vy, nibble - Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
+    // With n = 0 (SUPER-CHIP), draw a 16x16 sprite made of 16 rows of 2 bytes.
+    // With XO-CHIP planes, the sprite is drawn on every selected plane, the data of each plane following the previous one.
+    // With the clip quirk, the pixels past the right and bottom edges are not drawn.
+    {
+        self.v_registers[0x0F] = 0;
+        let (width, height) = memory.display.get_sizes();
+        let (start_x, start_y) = (self.v_registers[x] as usize % width, self.v_registers[y] as usize % height);
+        let clip = self.wrap_quirk == WrapQuirk::Clip;
+        let (rows, bytes_per_row) = if n == 0 { (16, 2) } else { (n, 1) };
+        let mut offset = 0;
+        for plane in 0..2 {
+            if self.selected_planes & (1 << plane) == 0 {
+                continue;
+            }
+            for row in 0..rows {
+                if clip && start_y + row >= height {
+                    break;
+                }
+                let y = (start_y + row) % height;
+                for bit in 0..bytes_per_row * 8 {
+                    if clip && start_x + bit >= width {
+                        break;
+                    }
+                    let x = (start_x + bit) % width;
+                    let byte = memory[self.address(memory, offset + row * bytes_per_row + bit / 8)];
+                    let pixel = (byte >> (7 - bit % 8)) & 1;
+                    let lit = memory.display.get(x, y).unwrap_or(0);
+                    self.v_registers[0x0F] |= pixel & (lit >> plane);
+                    memory.display.set(x, y, lit ^ (pixel << plane));
+                }
+            }
+            offset += rows * bytes_per_row;
+        }
+        self.waiting_for_vblank = self.vblank_quirk;
+        self.collisions += self.v_registers[0x0F] as u64;
+        ProgramCounter::NEXT
+    }
+
+    fn op_ex9e(&mut self, x: usize, keyboard: &Keyboard) -> ProgramCounter // SKP Vx - Skip next instruction if key with the value of Vx is pressed.
+    {
+        ProgramCounter::skip_if(keyboard[self.v_registers[x] as usize] == 1)
+    }
+
+    fn op_exa1(&mut self, x: usize, keyboard: &Keyboard) -> ProgramCounter // SKNP Vx - Skip next instruction if key with the value of Vx is not pressed.
+    {
+        ProgramCounter::skip_if(keyboard[self.v_registers[x] as usize] == 0)
+    }
+
+    fn op_fn01(&mut self, n: usize) -> ProgramCounter // PLANE n - Select the XO-CHIP drawing planes from the bitmask n.
+    {
+        self.selected_planes = n as u8 & 0x03;
+        ProgramCounter::NEXT
+    }
+
+    fn op_f000(&mut self, memory: &Memory) -> ProgramCounter // LD I, long nnnn - Set I = the 16 bits address following the opcode (XO-CHIP).
+    {
+        self.i_register = (memory[(self.pc + 2) % memory.len()] as u16) << 8 | memory[(self.pc + 3) % memory.len()] as u16;
+        ProgramCounter::JUMP((self.pc + instruction_size(memory, self.pc)) as u16)
+    }
+
+    fn op_f002(&mut self, memory: &Memory) -> ProgramCounter // AUDIO - Load the XO-CHIP 16 bytes audio pattern from memory starting at location I.
+    {
+        let mut pattern = [0; 16];
+        for (index, byte) in pattern.iter_mut().enumerate() {
+            *byte = memory[self.address(memory, index)];
+        }
+        self.audio_pattern = Some(pattern);
+        ProgramCounter::NEXT
+    }
+
+    fn op_fx3a(&mut self, x: usize) -> ProgramCounter // PITCH Vx - Set the XO-CHIP audio pitch = Vx.
+    {
+        self.pitch = self.v_registers[x];
+        ProgramCounter::NEXT
+    }
+
+    fn op_fx07(&mut self, x: usize) -> ProgramCounter // LD Vx, DT - Set Vx = delay timer value.
+    {
+        self.v_registers[x] = self.delay_timer_register;
+        ProgramCounter::NEXT
+    }
+
+    fn op_fx0a(&mut self, x: usize) -> ProgramCounter // LD Vx, K - Wait for a key press, store the value of the key in Vx.
+    {
+        self.waiting_for_input = true;
+        self.input_register = x;
+        self.input_pressed = 0;
+        ProgramCounter::NEXT
+    }
+
+    fn op_fx15(&mut self, x: usize) -> ProgramCounter // LD DT, Vx - Set delay timer = Vx.
+    {
+        self.delay_timer_register = self.v_registers[x];
+        ProgramCounter::NEXT
+    }
+
+    fn op_fx18(&mut self, x: usize) -> ProgramCounter // LD ST, Vx - Set sound timer = Vx.
+    // A non zero value starts the beep until update_timers brings the timer down to 0, a value of 0 stops it.
+    {
+        self.sound_timer_register = self.v_registers[x];
+        self.beeping = self.sound_timer_register > 0;
+        ProgramCounter::NEXT
+    }
+
+    fn op_fx1e(&mut self, x: usize) -> ProgramCounter // ADD I, Vx - Set I = I + Vx.
+    // With the index overflow quirk, VF = 1 when I goes past the 12-bit address space.
+    {
+        let sum = self.i_register as u32 + self.v_registers[x] as u32;
+        self.i_register = sum as u16;
+        if self.index_overflow_quirk && sum > 0x0FFF {
+            self.v_registers[0xF] = 1;
+        }
+        ProgramCounter::NEXT
+    }
+
+    fn op_fx29(&mut self, x: usize) -> ProgramCounter // LD F, Vx - Set I = location of sprite for digit Vx.
+    {
+        self.i_register = (SMALL_FONT_ADDRESS + self.v_registers[x] as usize * 5) as u16;
+        ProgramCounter::NEXT
+    }
+
+    fn op_fx30(&mut self, x: usize) -> ProgramCounter // LD HF, Vx - Set I = location of the SUPER-CHIP big sprite for digit Vx.
+    {
+        self.i_register = (BIG_FONT_ADDRESS + self.v_registers[x] as usize * 10) as u16;
+        ProgramCounter::NEXT
+    }
+
+    fn op_fx33(&mut self, x: usize, memory: &mut Memory) -> ProgramCounter // LD B, Vx - Store BCD representation of Vx in memory locations I, I+1, and I+2.
+     {
+        let digits = [self.v_registers[x] / 100, self.v_registers[x] % 100 / 10, self.v_registers[x] % 10];
+        for (offset, &digit) in digits.iter().enumerate() {
+            let address = self.address(memory, offset);
+            self.store(memory, address, digit);
+        }
+        ProgramCounter::NEXT
+    }
+
+    fn op_fx55(&mut self, x: usize, memory: &mut Memory) -> ProgramCounter // LD [I], Vx - Store registers V0 through Vx in memory starting at location I.
+    {
+        for index in 0..x + 1 {
+            let address = self.address(memory, index);
+            self.store(memory, address, self.v_registers[index]);
+        }
+        self.load_store_increment(x);
+        ProgramCounter::NEXT
+    }
+
+    fn op_fx65(&mut self, x: usize, memory: &Memory) -> ProgramCounter // LD Vx, [I] - Read registers V0 through Vx from memory starting at location I.
+    // The interpreter reads values from memory starting at location I into registers V0 through Vx.
+    {
+        for index in 0..x + 1 {
+             self.v_registers[index] = memory[self.address(memory, index)];
+        }
+        self.load_store_increment(x);
+        ProgramCounter::NEXT
+    }
+
+    fn op_fx75(&mut self, x: usize) -> ProgramCounter // LD R, Vx - Store V0 through Vx in the RPL user flags, x up to 7 (SUPER-CHIP)
+    {
+        let count = x.min(FLAG_REGISTERS - 1) + 1;
+        self.flags[..count].copy_from_slice(&self.v_registers[..count]);
+        ProgramCounter::NEXT
+    }
+
+    fn op_fx85(&mut self, x: usize) -> ProgramCounter // LD Vx, R - Read V0 through Vx from the RPL user flags, x up to 7 (SUPER-CHIP)
+    {
+        let count = x.min(FLAG_REGISTERS - 1) + 1;
+        self.v_registers[..count].copy_from_slice(&self.flags[..count]);
+        ProgramCounter::NEXT
+    }
+
+    // Write a byte for the program, a write protected address is reported and left unchanged,
+    // a watched one changing is recorded for the cycle to pause the cpu
+    fn store(&mut self, memory: &mut Memory, address: usize, value: u8)
+    {
+        let old = memory[address];
+        if let Err(error) = memory.write(address, value) {
+            eprintln!("{} by the instruction at {:#05X}, skipping it", error, self.pc);
+        } else if old != value && self.watch_hit.is_none() && self.watchpoints.contains(&(address as u16)) {
+            self.watch_hit = Some((address, old, value));
+        }
+    }
+
+    // Memory address at offset from I, wrapping around the end of the RAM
+    fn address(&self, memory: &Memory, offset: usize) -> usize
+    {
+        (self.i_register as usize + offset) % memory.len()
+    }
+
+    fn load_store_increment(&mut self, x: usize)
+    {
+        self.i_register = match self.load_store_quirk {
+            LoadStoreQuirk::Increment => self.i_register.wrapping_add(x as u16 + 1),
+            LoadStoreQuirk::IncrementMinusOne => self.i_register.wrapping_add(x as u16),
+            LoadStoreQuirk::Unchanged => self.i_register,
+        };
+    }
+}
+
+// Read-only view of the cpu state, for debuggers and tests
+impl Cpu
+{
+    pub fn registers(&self) -> &[u8; 16]
+    {
+        &self.v_registers
+    }
+
+    pub fn index(&self) -> u16
+    {
+        self.i_register
+    }
+
+    pub fn program_counter(&self) -> usize
+    {
+        self.pc
+    }
+
+    pub fn opcode(&self) -> u16
+    {
+        self.opcode
+    }
+
+    pub fn delay_timer(&self) -> u8
+    {
+        self.delay_timer_register
+    }
+
+    pub fn sound_timer(&self) -> u8
+    {
+        self.sound_timer_register
+    }
+
+    // Addresses of the pending CALL instructions, the most recent one last
+    pub fn stack_snapshot(&self) -> &[u16]
+    {
+        &self.stack.stack[..self.stack.stack_pointer]
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use super::super::memory::{SPRITES, BIG_SPRITES};
+    #[test]
+    fn cpu_initial_state()
+    {
+        let cpu = Cpu::new();
+        assert_eq!(cpu.pc, 0x200);
+        assert_eq!(cpu.stack.stack_pointer, 0);
+        assert_eq!(cpu.stack.stack, [0; 16]);
+    }
+
+    #[test]
+    fn quirks_presets()
+    {
+        let chip8 = Quirks::chip8();
+        assert_eq!(chip8.shift, ShiftQuirk::Vy);
+        assert_eq!(chip8.load_store, LoadStoreQuirk::Increment);
+        assert_eq!(chip8.jump, JumpQuirk::V0);
+        assert_eq!(chip8.wrap, WrapQuirk::Clip);
+        assert!(chip8.vblank);
+
+        let schip = Quirks::schip();
+        assert_eq!(schip.shift, ShiftQuirk::Vx);
+        assert_eq!(schip.load_store, LoadStoreQuirk::Unchanged);
+        assert_eq!(schip.jump, JumpQuirk::Vx);
+        assert_eq!(schip.wrap, WrapQuirk::Clip);
+        assert!(!schip.vblank);
+
+        let xochip = Quirks::xochip();
+        assert_eq!(xochip.shift, ShiftQuirk::Vy);
+        assert_eq!(xochip.load_store, LoadStoreQuirk::Increment);
+        assert_eq!(xochip.jump, JumpQuirk::V0);
+        assert_eq!(xochip.wrap, WrapQuirk::Wrap);
+        assert!(!xochip.vblank);
+
+        // the documented COSMAC VIP configuration of --cosmac
+        let cosmac = Quirks::cosmac();
+        assert_eq!(cosmac.shift, ShiftQuirk::Vy);
+        assert_eq!(cosmac.load_store, LoadStoreQuirk::Increment);
+        assert_eq!(cosmac.jump, JumpQuirk::V0);
+        assert_eq!(cosmac.wrap, WrapQuirk::Clip);
+        assert!(cosmac.vblank);
+        assert!(!cosmac.index_overflow);
+        assert!(cosmac.logic);
+        assert_eq!(Quirks::COSMAC_CLOCK_RATE, 540.0);
+        assert!(!chip8.logic && !schip.logic && !xochip.logic);
+
+        assert_eq!("schip".parse::<Quirks>(), Ok(schip));
+        assert!("chip48".parse::<Quirks>().is_err());
+
+        let cpu = Cpu::new_with_quirks(0x600, chip8);
+        assert_eq!(cpu.pc, 0x600);
+        assert_eq!(cpu.shift_quirk, ShiftQuirk::Vy);
+        assert_eq!(cpu.quirks(), chip8);
+        assert!(Cpu::new_with_quirks(0x200, cosmac).logic_quirk);
+        assert_eq!(Cpu::new().quirks(), Quirks::default());
+    }
+
+    #[test]
+    fn pc_out_of_memory()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+
+        cpu.pc = RAM_SIZE - 1;
+        assert_eq!(cpu.fetch_opcode(&mem), 0x0000);
+        cpu.do_cycle(&mut mem, &key);
+        assert!(cpu.is_paused());
+        assert_eq!(cpu.pc, RAM_SIZE - 1);
+        assert_eq!(cpu.cycles(), 0);
+
+        // JP 0xFFF
+        cpu.resume();
+        cpu.pc = 0x200;
+        cpu.execute_raw(0x1FFF, &mut mem, &key);
+        cpu.step(&mut mem, &key);
+        assert!(cpu.is_paused());
+        assert_eq!(cpu.pc, 0xFFF);
+    }
+
+    #[test]
+    fn trace_instructions()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+
+        cpu.execute_raw(0x6A05, &mut mem, &key);
+        assert!(cpu.take_trace().is_empty());
+
+        cpu.trace = true;
+        cpu.execute_raw(0x6A05, &mut mem, &key);
+        cpu.execute_raw(0x6B07, &mut mem, &key);
+        cpu.execute_raw(0x8AB5, &mut mem, &key);
+        cpu.execute_raw(0xA456, &mut mem, &key);
+        assert_eq!(cpu.take_trace(), vec![
+            "0x202: 6A05 LD VA, 0x05        VA=05 V0=00 VF=00 I=0x000",
+            "0x204: 6B07 LD VB, 0x07        VB=07 V0=00 VF=00 I=0x000",
+            "0x206: 8AB5 SUB VA, VB         VA=FE VB=07 VF=00 I=0x000",
+            "0x208: A456 LD I, 0x456        V4=00 V5=00 VF=00 I=0x456",
+        ]);
+        assert!(cpu.take_trace().is_empty());
+
+        for _ in 0..TRACE_CAPACITY + 1 {
+            cpu.execute_raw(0x6A05, &mut mem, &key);
+        }
+        let trace = cpu.take_trace();
+        assert_eq!(trace.len(), TRACE_CAPACITY);
+        assert!(trace[0].starts_with("0x20C: 6A05"));
     }
 
-    fn op_8xy5(&mut self, x: usize, y: usize) -> ProgramCounter // SUB Vx, Vy - Set Vx = Vx - Vy, set VF = NOT borrow.
+    #[test]
+    fn trace_disassembly()
     {
-        self.v_registers[0x0F] = if self.v_registers[x] > self.v_registers[y] { 1 } else { 0 };
-        self.v_registers[x] = self.v_registers[x].wrapping_sub(self.v_registers[y]);
-        ProgramCounter::NEXT
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+
+        cpu.trace = true;
+        cpu.trace_disasm = true;
+        cpu.pc = 0x2A6;
+        cpu.execute_raw(0x6A05, &mut mem, &key);
+        cpu.execute_raw(0x6B07, &mut mem, &key);
+        cpu.execute_raw(0x8AB5, &mut mem, &key);
+        cpu.execute_raw(0xA456, &mut mem, &key);
+        cpu.execute_raw(0xFA55, &mut mem, &key);
+        assert_eq!(cpu.take_trace(), vec![
+            "0x2A6: 6A05  LD VA, 0x05",
+            "0x2A8: 6B07  LD VB, 0x07",
+            "0x2AA: 8AB5  SUB VA, VB         ; VA=05 VB=07",
+            "0x2AC: A456  LD I, 0x456",
+            "0x2AE: FA55  LD [I], VA         ; I=0x456 VA=FE",
+        ]);
     }
 
-    fn op_8xy6(&mut self, x: usize, _y: usize) -> ProgramCounter // SHR Vx {, Vy} - Set Vx = Vx SHR 1.
+    #[test]
+    fn breakpoints()
     {
-        self.v_registers[0x0F] = self.v_registers[x] & 1;
-        self.v_registers[x] >>= 1;
-        ProgramCounter::NEXT
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        // LD V0, 0x01 ; LD V0, 0x02 ; LD V0, 0x03 ; JP 0x200
+        mem.memory[0x200..0x208].copy_from_slice(&[0x60, 0x01, 0x60, 0x02, 0x60, 0x03, 0x12, 0x00]);
+        cpu.add_breakpoint(0x202);
+
+        assert!(!cpu.do_cycle(&mut mem, &key));
+        assert!(cpu.do_cycle(&mut mem, &key));
+        assert!(cpu.is_paused());
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(cpu.v_registers[0], 0x01);
+        assert!(!cpu.do_cycle(&mut mem, &key));
+        assert_eq!(cpu.pc, 0x202);
+
+        // resuming runs the instruction at the breakpoint, then stops on it again next loop
+        cpu.resume();
+        for _ in 0..3 {
+            assert!(!cpu.do_cycle(&mut mem, &key));
+        }
+        assert_eq!(cpu.v_registers[0], 0x03);
+        assert!(!cpu.do_cycle(&mut mem, &key));
+        assert!(cpu.do_cycle(&mut mem, &key));
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(cpu.v_registers[0], 0x01);
+
+        cpu.remove_breakpoint(0x202);
+        cpu.resume();
+        for _ in 0..4 {
+            assert!(!cpu.do_cycle(&mut mem, &key));
+        }
+        assert!(!cpu.is_paused());
     }
 
-    fn op_8xy7(&mut self, x: usize, y: usize) -> ProgramCounter // SUBN Vx, Vy - Set Vx = Vy - Vx, set VF = NOT borrow.
+    #[test]
+    fn cpu_reset()
     {
-        self.v_registers[0x0F] = if self.v_registers[x] > self.v_registers[y] { 1 } else { 0 };
-        self.v_registers[x] = self.v_registers[y].wrapping_sub(self.v_registers[x]);
-        ProgramCounter::NEXT
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        cpu.shift_quirk = ShiftQuirk::Vy;
+
+        // LD VA, 0x05 ; LD I, 0x456 ; LD DT, VA ; LD ST, VA ; CALL 0x300 ; PLANE 3 ; LD V0, K
+        for &opcode in [0x6A05, 0xA456, 0xFA15, 0xFA18, 0x2300, 0xF301, 0xF00A].iter() {
+            cpu.execute_raw(opcode, &mut mem, &key);
+        }
+        cpu.beeping = true;
+        cpu.reset();
+
+        assert_eq!(cpu.v_registers, [0; 16]);
+        assert_eq!(cpu.i_register, 0);
+        assert_eq!(cpu.delay_timer_register, 0);
+        assert_eq!(cpu.sound_timer_register, 0);
+        assert_eq!(cpu.pc, 0x200);
+        assert_eq!(cpu.stack.stack_pointer, 0);
+        assert_eq!(cpu.stack.stack, [0; 16]);
+        assert_eq!(cpu.opcode, 0);
+        assert_eq!(cpu.selected_planes, 0x01);
+        assert!(!cpu.waiting_for_input);
+        assert!(!cpu.beeping);
+        assert_eq!(cpu.shift_quirk, ShiftQuirk::Vy);
     }
 
-    fn op_8xye(&mut self, x: usize, _y: usize) -> ProgramCounter // SHL Vx {, Vy} - Set Vx = Vx SHL 1.
+    #[test]
+    fn cpu_start_address()
     {
-        self.v_registers[0x0F] = (self.v_registers[x] & 0b10000000) >> 7;
-        self.v_registers[x] <<= 1;
-        ProgramCounter::NEXT
+        let mut cpu = Cpu::new_with_start(0x600);
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        mem.load_bytes(&[0x60, 0x42], 0x600).unwrap();
+        assert_eq!(mem[0x200], 0);
+        assert_eq!(mem[0x600], 0x60);
+        assert_eq!(mem[0x601], 0x42);
+
+        assert_eq!(cpu.pc, 0x600);
+        cpu.step(&mut mem, &key);
+        assert_eq!(cpu.v_registers[0], 0x42);
+        cpu.reset();
+        assert_eq!(cpu.pc, 0x600);
     }
 
-    fn op_9xy0(&mut self, x: usize, y: usize) -> ProgramCounter // SNE Vx, Vy - Skip next instruction if Vx != Vy.
+    #[test]
+    fn cpu_state_accessors()
     {
-        ProgramCounter::skip_if(self.v_registers[x] != self.v_registers[y])
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+
+        cpu.execute_raw(0x6A05, &mut mem, &key);
+        cpu.execute_raw(0xA456, &mut mem, &key);
+        cpu.execute_raw(0xFA15, &mut mem, &key);
+        cpu.execute_raw(0xFA18, &mut mem, &key);
+        cpu.execute_raw(0x2300, &mut mem, &key);
+        cpu.execute_raw(0x2400, &mut mem, &key);
+
+        assert_eq!(cpu.registers()[0x0A], 0x05);
+        assert_eq!(cpu.index(), 0x456);
+        assert_eq!(cpu.delay_timer(), 0x05);
+        assert_eq!(cpu.sound_timer(), 0x05);
+        assert_eq!(cpu.opcode(), 0x2400);
+        assert_eq!(cpu.program_counter(), 0x400);
+        assert_eq!(cpu.stack_snapshot(), &[0x208, 0x300]);
     }
 
-    fn op_annn(&mut self, nnn: u16) -> ProgramCounter // LD I, addr - Set I = nnn.
+    #[test]
+    fn snapshot_round_trip()
     {
-        self.i_register = nnn;
-        ProgramCounter::NEXT
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        // CALL 0x300 ; LD V4, 0x40 ; LD I, 0x456 ; LD DT, V4 ; DRW V4, V4, 5 ; LD V2, K
+        cpu.execute_raw(0x2300, &mut mem, &key);
+        cpu.execute_raw(0x6440, &mut mem, &key);
+        cpu.execute_raw(0xA000, &mut mem, &key);
+        cpu.execute_raw(0xF415, &mut mem, &key);
+        cpu.execute_raw(0xD445, &mut mem, &key);
+        cpu.execute_raw(0xF20A, &mut mem, &key);
+        mem[0x456] = 0x42;
+
+        let snapshot = Snapshot::from_bytes(&cpu.snapshot(&mem).to_bytes()).unwrap();
+        assert_eq!(snapshot, cpu.snapshot(&mem));
+
+        let mut restored_cpu = Cpu::new();
+        let mut restored_mem = Memory::new();
+        // stale wait state of the cpu restored into
+        restored_cpu.input_pressed = 1 << 0x5;
+        restored_cpu.waiting_for_vblank = true;
+        restored_cpu.restore(&mut restored_mem, &snapshot).unwrap();
+        assert_eq!(restored_cpu.v_registers, cpu.v_registers);
+        assert_eq!(restored_cpu.i_register, cpu.i_register);
+        assert_eq!(restored_cpu.delay_timer_register, 0x40);
+        assert_eq!(restored_cpu.pc, cpu.pc);
+        assert_eq!(restored_cpu.stack.stack_pointer, 1);
+        assert_eq!(restored_cpu.stack_snapshot(), &[0x200]);
+        assert!(restored_cpu.waiting_for_input);
+        assert_eq!(restored_cpu.input_register, 2);
+        assert_eq!(restored_cpu.input_pressed, 0);
+        assert!(!restored_cpu.waiting_for_vblank);
+        assert_eq!(restored_mem[0x456], 0x42);
+        assert_eq!(restored_mem.display.pixels(), mem.display.pixels());
+        assert_eq!(restored_mem.display[[0x40 % 64, 0x40 % 32]], 1);
     }
 
-    fn op_bnnn(&mut self, nnn: u16) -> ProgramCounter // JP V0, addr - Jump to location nnn + V0.
+    #[test]
+    fn snapshot_rejects_invalid_states()
     {
-        ProgramCounter::JUMP(nnn + self.v_registers[0] as u16)
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let snapshot = cpu.snapshot(&mem);
+
+        // display resolution mismatch
+        mem.display.set_resolution(true);
+        assert!(cpu.restore(&mut mem, &snapshot).is_err());
+
+        // corrupted or truncated data
+        let bytes = snapshot.to_bytes();
+        assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Snapshot::from_bytes(&bytes[1..]).is_err());
+        assert!(Snapshot::from_bytes(&[]).is_err());
     }
 
-    fn op_cxkk(&mut self, x: usize, kk: u8) -> ProgramCounter // RND Vx, byte - Set Vx = random byte AND kk.
+    #[test]
+    fn stack_top()
     {
-        let mut rng = rand::thread_rng();
-        let random = rng.gen_range(0, 255);
-        self.v_registers[x] = random & kk;
-        ProgramCounter::NEXT
+        let mut stack = Stack::new();
+        assert_eq!(stack.top(), None);
+        stack.push(0x202).unwrap();
+        stack.push(0x346).unwrap();
+        assert_eq!(stack.top(), Some(0x346));
+        assert_eq!(stack.pop(), Ok(0x346));
+        assert_eq!(stack.top(), Some(0x202));
+        assert_eq!(stack.pop(), Ok(0x202));
+        assert_eq!(stack.top(), None);
     }
 
-    fn op_dxyn(&mut self, x: usize, y: usize, n: usize, memory: &mut Memory) -> ProgramCounter // DRW Vx, Vy, nibble - Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
-    {
-        self.v_registers[0x0F] = 0;
-        let (width, height) = memory.display.get_sizes();
-        for byte in 0..n {
-            let y: usize = (self.v_registers[y].wrapping_add(byte as u8)) as usize % height;
-            for bit in 0..8 {
-                let x: usize = (self.v_registers[x].wrapping_add(bit as u8)) as usize % width;
-                let pixel = (memory[self.i_register as usize + byte] >> (7 - bit)) & 1;
-                self.v_registers[0x0F] |= pixel & memory.display[[x,y]];
-                memory.display[[x,y]] ^= pixel;
+    #[test]
+    fn stack_peak_depth()
+    {
+        let mut stack = Stack::new();
+        assert_eq!(stack.peak_depth, 0);
+
+        stack.push(0x200).unwrap();
+        stack.push(0x300).unwrap();
+        stack.push(0x400).unwrap();
+        assert_eq!(stack.peak_depth, 3);
+        stack.pop().unwrap();
+        stack.pop().unwrap();
+        assert_eq!(stack.peak_depth, 3);
+        stack.push(0x500).unwrap();
+        assert_eq!(stack.peak_depth, 3);
+        stack.push(0x600).unwrap();
+        stack.push(0x700).unwrap();
+        assert_eq!(stack.peak_depth, 4);
+        stack.pop().unwrap();
+        stack.pop().unwrap();
+        stack.pop().unwrap();
+        stack.pop().unwrap();
+        assert_eq!(stack.stack_pointer, 0);
+        assert_eq!(stack.peak_depth, 4);
+    }
+
+    #[test]
+    fn beep_latch()
+    {
+        let mem_and_cpu = || {
+            let mut cpu = Cpu::new();
+            let mut mem = Memory::new();
+            // LD V1, 0x03 ; LD ST, V1 ; JP 0x204
+            cpu.load_program(&mut mem, &[0x6103, 0xF118, 0x1204]).unwrap();
+            (cpu, mem)
+        };
+        let key = Keyboard::new();
+        // the beep lasts 3 timer ticks at any clock rate
+        for &cycles_per_tick in [1, 16, 500].iter() {
+            let (mut cpu, mut mem) = mem_and_cpu();
+            let mut transitions = Vec::new();
+            let mut beeping = false;
+            for tick in 0..10 {
+                for _ in 0..cycles_per_tick {
+                    cpu.cycle(&mut mem, &key);
+                    if cpu.beeping != beeping {
+                        beeping = cpu.beeping;
+                        transitions.push((tick, beeping));
+                    }
+                }
+                cpu.update_timers().unwrap();
+                if cpu.beeping != beeping {
+                    beeping = cpu.beeping;
+                    transitions.push((tick, beeping));
+                }
             }
+            let start = if cycles_per_tick == 1 { 1 } else { 0 };
+            assert_eq!(transitions, [(start, true), (start + 2, false)]);
         }
-        ProgramCounter::NEXT
+
+        // a paused cpu stops beeping once the timer runs out
+        let (mut cpu, mut mem) = mem_and_cpu();
+        cpu.cycle(&mut mem, &key);
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Beeped { opcode: 0xF118, pc: 0x202 });
+        cpu.pause();
+        for _ in 0..3 {
+            assert!(cpu.beeping);
+            cpu.update_timers().unwrap();
+        }
+        assert!(!cpu.beeping);
+
+        // setting the timer to 0 stops the beep
+        cpu.execute_raw(0xF118, &mut mem, &key);
+        assert!(cpu.beeping);
+        cpu.execute_raw(0xF018, &mut mem, &key);
+        assert!(!cpu.beeping);
     }
 
-    fn op_ex9e(&mut self, x: usize, keyboard: &Keyboard) -> ProgramCounter // SKP Vx - Skip next instruction if key with the value of Vx is pressed.
+    #[test]
+    fn frozen_timers()
     {
-        ProgramCounter::skip_if(keyboard[self.v_registers[x] as usize] == 1)
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        // LD V4, 0x40
+        mem[0x200] = 0x64;
+        mem[0x201] = 0x40;
+
+        cpu.delay_timer_register = 10;
+        cpu.sound_timer_register = 5;
+        cpu.timers_frozen = true;
+        cpu.update_timers().unwrap();
+        assert_eq!(cpu.delay_timer_register, 10);
+        assert_eq!(cpu.sound_timer_register, 5);
+
+        cpu.do_cycle(&mut mem, &key);
+        assert_eq!(cpu.v_registers[4], 0x40);
+        assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
+
+        cpu.timers_frozen = false;
+        cpu.update_timers().unwrap();
+        assert_eq!(cpu.delay_timer_register, 9);
+        assert_eq!(cpu.sound_timer_register, 4);
     }
 
-    fn op_exa1(&mut self, x: usize, keyboard: &Keyboard) -> ProgramCounter // SKNP Vx - Skip next instruction if key with the value of Vx is not pressed.
+    #[test]
+    fn slow_instruction_report()
     {
-        ProgramCounter::skip_if(keyboard[self.v_registers[x] as usize] == 0)
+        let threshold = Duration::from_micros(500);
+
+        assert_eq!(slow_report(0xD455, 0x2A6, Duration::from_micros(100), threshold), None);
+        assert_eq!(slow_report(0xD455, 0x2A6, threshold, threshold), None);
+        assert_eq!(
+            slow_report(0xD455, 0x2A6, Duration::from_micros(1200), threshold),
+            Some(String::from("slow instruction D455 at 0x2A6: took 1200us (threshold 500us)"))
+        );
     }
 
-    fn op_fx07(&mut self, x: usize) -> ProgramCounter // LD Vx, DT - Set Vx = delay timer value.
+    #[test]
+    fn watchpoints()
     {
-        self.v_registers[x] = self.delay_timer_register;
-        ProgramCounter::NEXT
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        // LD V0, 0x42 ; LD I, 0x300 ; LD [I], V0 ; LD [I], V0 ; LD V1, 0x03 ; LD B, V1
+        let program = [0x60, 0x42, 0xA3, 0x00, 0xF0, 0x55, 0xF0, 0x55, 0x61, 0x03, 0xF1, 0x33];
+        for (i, &byte) in program.iter().enumerate() {
+            mem[0x200 + i] = byte;
+        }
+        cpu.load_store_quirk = LoadStoreQuirk::Unchanged;
+        cpu.add_watchpoint(0x300);
+        cpu.add_watchpoint(0x302);
+
+        cpu.cycle(&mut mem, &key);
+        cpu.cycle(&mut mem, &key);
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Watchpoint { address: 0x300, old: 0x00, new: 0x42 });
+        assert!(cpu.is_paused());
+        // the instruction completed before the pause
+        assert_eq!(cpu.pc, 0x206);
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Paused);
+
+        // writing the same value again is not a change
+        cpu.resume();
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Executed { opcode: 0xF055, pc: 0x206 });
+        cpu.cycle(&mut mem, &key);
+        // the first watched byte changed is reported
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Watchpoint { address: 0x300, old: 0x42, new: 0x00 });
+        assert_eq!(&mem[0x300..0x303], &[0, 0, 3]);
+
+        cpu.remove_watchpoint(0x300);
+        cpu.resume();
+        cpu.execute_raw(0xF055, &mut mem, &key);
+        assert!(cpu.watch_hit.is_none());
     }
 
-    fn op_fx0a(&mut self, x: usize) -> ProgramCounter // LD Vx, K - Wait for a key press, store the value of the key in Vx.
+    #[test]
+    fn load_program()
     {
-        self.waiting_for_input = true;
-        self.input_register = x;
-        ProgramCounter::NEXT
+        let mut cpu = Cpu::new_with_start(0x600);
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        cpu.pc = 0x200;
+        // LD V3, 0x21 ; ADD V3, 0x01 ; LD I, 0x345
+        cpu.load_program(&mut mem, &[0x6321, 0x7301, 0xA345]).unwrap();
+        assert_eq!(cpu.pc, 0x600);
+
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Executed { opcode: 0x6321, pc: 0x600 });
+        assert_eq!(cpu.v_registers[3], 0x21);
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Executed { opcode: 0x7301, pc: 0x602 });
+        assert_eq!(cpu.v_registers[3], 0x22);
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Executed { opcode: 0xA345, pc: 0x604 });
+        assert_eq!(cpu.i_register, 0x345);
+        assert_eq!(cpu.pc, 0x606);
+
+        // a program past the end of the memory is refused and pc left alone
+        let mut cpu = Cpu::new_with_start(RAM_SIZE - 2);
+        assert!(cpu.load_program(&mut mem, &[0x6321, 0x7301]).is_err());
+        assert_eq!(cpu.pc, RAM_SIZE - 2);
     }
 
-    fn op_fx15(&mut self, x: usize) -> ProgramCounter // LD DT, Vx - Set delay timer = Vx.
+    #[test]
+    fn start_paused()
     {
-        self.delay_timer_register = self.v_registers[x];
-        ProgramCounter::NEXT
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        // LD V0, 0x01 ; LD V1, 0x02
+        cpu.load_program(&mut mem, &[0x6001, 0x6102]).unwrap();
+        cpu.pause();
+        for _ in 0..10 {
+            assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Paused);
+            assert_eq!(cpu.update_timers(), Ok(()));
+        }
+        assert_eq!(cpu.cycles(), 0);
+        assert_eq!(cpu.pc, 0x200);
+        assert_eq!(cpu.v_registers[0], 0);
+        assert_eq!((cpu.delay_timer_register, cpu.sound_timer_register), (0, 0));
+
+        assert_eq!(cpu.step(&mut mem, &key), CycleOutcome::Executed { opcode: 0x6001, pc: 0x200 });
+        assert!(cpu.is_paused());
+        cpu.resume();
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Executed { opcode: 0x6102, pc: 0x202 });
+        assert_eq!(cpu.v_registers[..2], [0x01, 0x02]);
     }
 
-    fn op_fx18(&mut self, x: usize) -> ProgramCounter // LD ST, Vx - Set sound timer = Vx.
+    #[test]
+    fn stack_errors()
     {
-        self.sound_timer_register = self.v_registers[x];
-        ProgramCounter::NEXT
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        // CALL 0x200, calling itself until the stack is full
+        cpu.load_program(&mut mem, &[0x2200]).unwrap();
+        for _ in 0..STACK_SIZE {
+            assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Executed { opcode: 0x2200, pc: 0x200 });
+        }
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::StackError { error: StackError::Overflow, pc: 0x200 });
+        assert!(cpu.is_paused());
+        assert_eq!(cpu.pc, 0x200);
+        assert_eq!(cpu.stack_snapshot().len(), STACK_SIZE);
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Paused);
+
+        // RET
+        cpu.reset();
+        cpu.resume();
+        cpu.load_program(&mut mem, &[0x00EE]).unwrap();
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::StackError { error: StackError::Underflow, pc: 0x200 });
+        assert!(cpu.is_paused());
+        assert_eq!(cpu.pc, 0x200);
     }
 
-    fn op_fx1e(&mut self, x: usize) -> ProgramCounter // ADD I, Vx - Set I = I + Vx.
+    #[test]
+    #[should_panic(expected = "cpu stack underflow")]
+    fn strict_stack_panics()
     {
-        self.i_register += self.v_registers[x] as u16;
-        ProgramCounter::NEXT
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        cpu.strict_stack = true;
+        cpu.load_program(&mut mem, &[0x00EE]).unwrap();
+        cpu.cycle(&mut mem, &key);
     }
 
-    fn op_fx29(&mut self, x: usize) -> ProgramCounter // LD F, Vx - Set I = location of sprite for digit Vx.
+    #[test]
+    fn halt_and_instruction_limit()
     {
-        self.i_register = self.v_registers[x] as u16 * 5;
-        ProgramCounter::NEXT
-    }
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        // LD V0, 0x01 ; JP 0x202
+        let program = [0x60, 0x01, 0x12, 0x02];
+        for (i, &byte) in program.iter().enumerate() {
+            mem[0x200 + i] = byte;
+        }
 
-    fn op_fx33(&mut self, x: usize, memory: &mut Memory) -> ProgramCounter // LD B, Vx - Store BCD representation of Vx in memory locations I, I+1, and I+2.
-     {
-        memory[self.i_register as usize] = self.v_registers[x] / 100;
-        memory[self.i_register as usize + 1] = self.v_registers[x] % 100 / 10;
-        memory[self.i_register as usize + 2] = self.v_registers[x] % 10;
-        ProgramCounter::NEXT
+        assert!(!cpu.is_halted(&mem));
+        cpu.cycle(&mut mem, &key);
+        assert!(cpu.is_halted(&mem));
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Halted { pc: 0x202 });
+        assert!(cpu.is_halted(&mem));
+
+        cpu.max_instructions = Some(4);
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Halted { pc: 0x202 });
+        assert!(!cpu.instruction_limit_reached());
+        cpu.cycle(&mut mem, &key);
+        assert!(cpu.instruction_limit_reached());
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::LimitReached);
+        assert_eq!(cpu.cycles(), 4);
+        // the limit survives the statistics and a reset
+        cpu.reset_stats();
+        cpu.reset();
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::LimitReached);
     }
 
-    fn op_fx55(&mut self, x: usize, memory: &mut Memory) -> ProgramCounter // LD [I], Vx - Store registers V0 through Vx in memory starting at location I.
+    #[test]
+    fn cycle_outcomes()
     {
-        for index in 0..x + 1 {
-            memory[self.i_register as usize + index] = self.v_registers[index];
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let mut key = Keyboard::new();
+        // ADD V1, 0x02 ; DRW V0, V0, 5 ; LD ST, V1 ; LD V6, K ; JP 0x208
+        let program = [0x71, 0x02, 0xD0, 0x05, 0xF1, 0x18, 0xF6, 0x0A, 0x12, 0x08];
+        for (i, &byte) in program.iter().enumerate() {
+            mem[0x200 + i] = byte;
         }
-        ProgramCounter::NEXT
+
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Executed { opcode: 0x7102, pc: 0x200 });
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Drew { opcode: 0xD005, pc: 0x202 });
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Beeped { opcode: 0xF118, pc: 0x204 });
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::WaitingForInput);
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::WaitingForInput);
+        key[0x3] = 1;
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::WaitingForInput);
+        key[0x3] = 0;
+        // the key release completes the wait and the next instruction runs in the same cycle
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Halted { pc: 0x208 });
+
+        cpu.pause();
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Paused);
+        cpu.add_breakpoint(0x208);
+        // resuming runs the instruction under the pc even if it has a breakpoint
+        cpu.resume();
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Halted { pc: 0x208 });
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Breakpoint);
+        assert_eq!(cpu.cycle(&mut mem, &key), CycleOutcome::Paused);
     }
 
-    fn op_fx65(&mut self, x: usize, memory: &Memory) -> ProgramCounter // LD Vx, [I] - Read registers V0 through Vx from memory starting at location I.
-    // The interpreter reads values from memory starting at location I into registers V0 through Vx.
-    {
-        for index in 0..x + 1 {
-             self.v_registers[index] = memory[self.i_register as usize + index];
-        }
-        ProgramCounter::NEXT
+    #[test]
+    fn instruction_costs()
+    {
+        assert_eq!(cost(0x7102), 1);
+        assert_eq!(cost(0x1208), 1);
+        assert_eq!(cost(0x00EE), 1);
+        assert_eq!(cost(0x00E0), 4);
+        assert_eq!(cost(0x00C2), 4);
+        assert_eq!(cost(0xD015), 7);
+        assert_eq!(cost(0xD011), 3);
+        // 16x16 sprite
+        assert_eq!(cost(0xD010), 18);
+        assert_eq!(cost(0xF133), 3);
+        assert_eq!(cost(0xF055), 2);
+        assert_eq!(cost(0xFF65), 9);
+        assert_eq!(cost(0xF51E), 1);
+
+        assert_eq!(CycleOutcome::Drew { opcode: 0xD005, pc: 0x202 }.cost(), 7);
+        assert_eq!(CycleOutcome::Executed { opcode: 0x7102, pc: 0x200 }.cost(), 1);
+        assert_eq!(CycleOutcome::WaitingForInput.cost(), 1);
+        assert_eq!(CycleOutcome::Paused.cost(), 1);
     }
-}
 
-#[cfg(test)]
-mod tests
-{
-    use super::*;
     #[test]
-    fn cpu_initial_state()
+    fn pause_and_step()
     {
-        let cpu = Cpu::new();
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let mut key = Keyboard::new();
+        // LD V4, 0x40 ; LD V5, 0x50 ; LD V6, K ; LD V7, 0x70
+        let program = [0x64, 0x40, 0x65, 0x50, 0xF6, 0x0A, 0x67, 0x70];
+        for (i, &byte) in program.iter().enumerate() {
+            mem[0x200 + i] = byte;
+        }
+
+        cpu.pause();
+        assert!(cpu.is_paused());
+        cpu.do_cycle(&mut mem, &key);
+        cpu.do_cycle(&mut mem, &key);
         assert_eq!(cpu.pc, 0x200);
-        assert_eq!(cpu.stack.stack_pointer, 0);
-        assert_eq!(cpu.stack.stack, [0; 16]);
+        assert_eq!(cpu.v_registers[4], 0);
+
+        cpu.step(&mut mem, &key);
+        assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
+        assert_eq!(cpu.v_registers[4], 0x40);
+        assert_eq!(cpu.v_registers[5], 0);
+        assert!(cpu.is_paused());
+
+        // stepping does not skip a pending key wait
+        cpu.step(&mut mem, &key);
+        cpu.step(&mut mem, &key);
+        assert!(cpu.waiting_for_input);
+        cpu.step(&mut mem, &key);
+        assert!(cpu.waiting_for_input);
+        assert_eq!(cpu.v_registers[7], 0);
+        key[0x0B] = 1;
+        cpu.step(&mut mem, &key);
+        assert!(cpu.waiting_for_input);
+        key[0x0B] = 0;
+        cpu.step(&mut mem, &key);
+        assert_eq!(cpu.v_registers[6], 0x0B);
+        assert_eq!(cpu.v_registers[7], 0x70);
+
+        cpu.resume();
+        assert!(!cpu.is_paused());
+        cpu.do_cycle(&mut mem, &key);
+        assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE * 5);
     }
 
     #[test]
@@ -481,7 +2281,58 @@ mod tests
         cpu.execute_opcode(&mut mem, &key);
 
         assert_eq!(cpu.stack.stack_pointer, 4);
-        assert_eq!(cpu.pc, 0x4444);
+        assert_eq!(cpu.pc, 0x4444 + OPCODE_SIZE);
+    }
+
+    #[test]
+    fn test_op00cn_op00fb_op00fc()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        mem.display[[20, 10]] = 1;
+
+        cpu.opcode = 0x00C5;
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(mem.display[[20, 10]], 0);
+        assert_eq!(mem.display[[20, 15]], 1);
+        assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
+
+        cpu.execute_raw(0x00FB, &mut mem, &key);
+        assert_eq!(mem.display[[24, 15]], 1);
+        cpu.execute_raw(0x00FC, &mut mem, &key);
+        cpu.execute_raw(0x00FC, &mut mem, &key);
+        assert_eq!(mem.display[[16, 15]], 1);
+        assert_eq!(mem.display.pixels().iter().filter(|&&pixel| pixel != 0).count(), 1);
+        assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE * 4);
+    }
+
+    #[test]
+    fn test_op00fe_op00ff()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+
+        cpu.opcode = 0x00FF;
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(mem.display.get_sizes(), (128, 64));
+        assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
+
+        // sprites wrap around the high resolution screen
+        cpu.opcode = 0xD455;
+        cpu.i_register = 0x00;
+        cpu.v_registers[4] = 126;
+        cpu.v_registers[5] = 62;
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(mem.display[[126, 62]], 1);
+        assert_eq!(mem.display[[1, 62]], 1);
+        assert_eq!(mem.display[[126, 2]], 1);
+
+        cpu.opcode = 0x00FE;
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(mem.display.get_sizes(), (64, 32));
+        assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE * 3);
     }
 
     #[test]
@@ -512,10 +2363,36 @@ mod tests
         cpu.execute_opcode(&mut mem, &key);
 
         assert_eq!(cpu.stack.stack_pointer, 3);
-        assert_eq!(cpu.stack.stack[2], 0x200 + OPCODE_SIZE as u16);
+        assert_eq!(cpu.stack.stack[2], 0x200);
         assert_eq!(cpu.pc, 0x300);
     }
 
+    #[test]
+    fn call_and_return()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        // 0x200: CALL 0x300 ; 0x202: LD V0, 0x01
+        // 0x300: CALL 0x400 ; 0x302: RET
+        // 0x400: RET
+        mem.memory[0x200..0x204].copy_from_slice(&[0x23, 0x00, 0x60, 0x01]);
+        mem.memory[0x300..0x304].copy_from_slice(&[0x24, 0x00, 0x00, 0xEE]);
+        mem.memory[0x400..0x402].copy_from_slice(&[0x00, 0xEE]);
+
+        cpu.step(&mut mem, &key);
+        cpu.step(&mut mem, &key);
+        assert_eq!(cpu.pc, 0x400);
+        assert_eq!(cpu.stack_snapshot(), &[0x200, 0x300]);
+        cpu.step(&mut mem, &key);
+        assert_eq!(cpu.pc, 0x302);
+        cpu.step(&mut mem, &key);
+        assert_eq!(cpu.pc, 0x202);
+        assert!(cpu.stack_snapshot().is_empty());
+        cpu.step(&mut mem, &key);
+        assert_eq!(cpu.v_registers[0], 0x01);
+    }
+
     #[test]
     fn test_op3xkk()
     {
@@ -607,6 +2484,19 @@ mod tests
         assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
     }
 
+    #[test]
+    fn test_execute_raw()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+
+        cpu.execute_raw(0x6A05, &mut mem, &key);
+        assert_eq!(cpu.v_registers[0x0A], 0x05);
+        assert_eq!(cpu.opcode, 0x6A05);
+        assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
+    }
+
     #[test]
     fn test_op7xkk()
     {
@@ -686,6 +2576,27 @@ mod tests
         assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
     }
 
+    #[test]
+    fn test_op8xy1_2_3_logic_quirk()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+
+        // VF is left alone by default
+        cpu.v_registers[0x0F] = 0x01;
+        cpu.execute_raw(0x8451, &mut mem, &key);
+        assert_eq!(cpu.v_registers[0x0F], 0x01);
+
+        // the logic quirk resets it
+        cpu.logic_quirk = true;
+        for &opcode in [0x8451, 0x8452, 0x8453].iter() {
+            cpu.v_registers[0x0F] = 0x01;
+            cpu.execute_raw(opcode, &mut mem, &key);
+            assert_eq!(cpu.v_registers[0x0F], 0x00);
+        }
+    }
+
     #[test]
     fn test_op8xy4()
     {
@@ -742,6 +2653,17 @@ mod tests
         assert_eq!(cpu.v_registers[0x04], 0xFF);
         assert_eq!(cpu.v_registers[0x0F], 0);
         assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
+
+        // Vx == Vy, no borrow
+        cpu.opcode = 0x8455;
+
+        cpu.pc = 0x200;
+        cpu.v_registers[0x04] = 0x05;
+        cpu.v_registers[0x05] = 0x05;
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(cpu.v_registers[0x04], 0);
+        assert_eq!(cpu.v_registers[0x0F], 1);
+        assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
     }
 
     #[test]
@@ -773,6 +2695,33 @@ mod tests
         assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
     }
 
+    #[test]
+    fn test_op8xy6_vy_quirk()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        cpu.opcode = 0x8456;
+
+        // Vx quirk ignores Vy
+        cpu.v_registers[0x04] = 0x08;
+        cpu.v_registers[0x05] = 0x05;
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(cpu.v_registers[0x04], 0x04);
+        assert_eq!(cpu.v_registers[0x0F], 0);
+
+        // Vy quirk shifts Vy into Vx
+        cpu.shift_quirk = ShiftQuirk::Vy;
+        cpu.pc = 0x200;
+        cpu.v_registers[0x04] = 0x08;
+        cpu.v_registers[0x05] = 0x05;
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(cpu.v_registers[0x04], 0x02);
+        assert_eq!(cpu.v_registers[0x05], 0x05);
+        assert_eq!(cpu.v_registers[0x0F], 1);
+        assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
+    }
+
     #[test]
     fn test_op8xy7()
     {
@@ -787,7 +2736,7 @@ mod tests
         cpu.v_registers[0x05] = 0x05;
         cpu.execute_opcode(&mut mem, &key);
         assert_eq!(cpu.v_registers[0x04], 0x01);
-        assert_eq!(cpu.v_registers[0x0F], 0);
+        assert_eq!(cpu.v_registers[0x0F], 1);
         assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
 
         // Vy < Vx
@@ -798,6 +2747,16 @@ mod tests
         cpu.v_registers[0x05] = 0x03;
         cpu.execute_opcode(&mut mem, &key);
         assert_eq!(cpu.v_registers[0x04], 0xFF);
+        assert_eq!(cpu.v_registers[0x0F], 0);
+
+        // Vy == Vx, no borrow
+        cpu.opcode = 0x8457;
+
+        cpu.pc = 0x200;
+        cpu.v_registers[0x04] = 0x05;
+        cpu.v_registers[0x05] = 0x05;
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(cpu.v_registers[0x04], 0);
         assert_eq!(cpu.v_registers[0x0F], 1);
     }
 
@@ -830,6 +2789,33 @@ mod tests
         assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
     }
 
+    #[test]
+    fn test_op8xye_vy_quirk()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        cpu.opcode = 0x845E;
+
+        // Vx quirk ignores Vy
+        cpu.v_registers[0x04] = 0x01;
+        cpu.v_registers[0x05] = 0x81;
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(cpu.v_registers[0x04], 0x02);
+        assert_eq!(cpu.v_registers[0x0F], 0);
+
+        // Vy quirk shifts Vy into Vx
+        cpu.shift_quirk = ShiftQuirk::Vy;
+        cpu.pc = 0x200;
+        cpu.v_registers[0x04] = 0x01;
+        cpu.v_registers[0x05] = 0x81;
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(cpu.v_registers[0x04], 0x02);
+        assert_eq!(cpu.v_registers[0x05], 0x81);
+        assert_eq!(cpu.v_registers[0x0F], 1);
+        assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
+    }
+
     #[test]
     fn test_op9xy0()
     {
@@ -882,6 +2868,24 @@ mod tests
         cpu.v_registers[0] = 2;
         cpu.execute_opcode(&mut mem, &key);
         assert_eq!(cpu.pc, 0x514);
+        // the address stays within 12 bits
+        cpu.v_registers[0] = 0xFF;
+        cpu.execute_raw(0xBFFF, &mut mem, &key);
+        assert_eq!(cpu.pc, 0x0FE);
+    }
+
+    #[test]
+    fn test_opbnnn_vx_quirk()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        cpu.jump_quirk = JumpQuirk::Vx;
+
+        cpu.v_registers[0] = 2;
+        cpu.v_registers[5] = 7;
+        cpu.execute_raw(0xB512, &mut mem, &key);
+        assert_eq!(cpu.pc, 0x519);
     }
 
     #[test]
@@ -915,6 +2919,45 @@ mod tests
         assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
     }
 
+    #[test]
+    fn seeded_cxkk_is_reproducible()
+    {
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        let mut sequences = Vec::new();
+        for _ in 0..2 {
+            let mut cpu = Cpu::new();
+            cpu.set_seed(0xC0FFEE);
+            let mut values = Vec::new();
+            for x in 0..16 {
+                cpu.execute_raw(0xC0FF | (x << 8), &mut mem, &key);
+                values.push(cpu.v_registers[x as usize]);
+            }
+            // the generator goes on across a reset
+            cpu.reset();
+            cpu.execute_raw(0xC0FF, &mut mem, &key);
+            values.push(cpu.v_registers[0]);
+            sequences.push(values);
+        }
+        assert_eq!(sequences[0], sequences[1]);
+        assert!(sequences[0].iter().any(|&value| value != sequences[0][0]));
+
+        let mut cpu = Cpu::new();
+        cpu.set_seed(0xBEEF);
+        for x in 0..16 {
+            cpu.execute_raw(0xC0FF | (x << 8), &mut mem, &key);
+        }
+        assert_ne!(cpu.v_registers.to_vec(), sequences[0][..16].to_vec());
+
+        // every byte can be drawn, 0xFF included
+        let mut drawn = [false; 256];
+        for _ in 0..4096 {
+            cpu.execute_raw(0xC0FF, &mut mem, &key);
+            drawn[cpu.v_registers[0] as usize] = true;
+        }
+        assert!(drawn.iter().all(|&drawn| drawn));
+    }
+
     #[test]
     fn test_opdxyn()
     {
@@ -972,6 +3015,53 @@ mod tests
         assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
     }
 
+    #[test]
+    fn test_opdxy0()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        // 16x16 hollow square with a vertical bar in the middle
+        for row in 0..16 {
+            let (left, right) = match row {
+                0 | 15 => (0xFF, 0xFF),
+                _ => (0x81, 0x81),
+            };
+            mem[0x300 + row * 2] = left;
+            mem[0x300 + row * 2 + 1] = right;
+        }
+        mem.display.set_resolution(true);
+        cpu.opcode = 0xD450;
+        cpu.i_register = 0x300;
+        cpu.v_registers[4] = 10;
+        cpu.v_registers[5] = 20;
+
+        cpu.execute_opcode(&mut mem, &key);
+        // top and bottom rows are fully lit
+        assert_eq!(mem.display[[10, 20]], 1);
+        assert_eq!(mem.display[[17, 20]], 1);
+        assert_eq!(mem.display[[18, 20]], 1);
+        assert_eq!(mem.display[[25, 20]], 1);
+        assert_eq!(mem.display[[25, 35]], 1);
+        assert_eq!(mem.display[[26, 35]], 0);
+        assert_eq!(mem.display[[10, 36]], 0);
+        // inner rows only have both edges and the middle bar
+        assert_eq!(mem.display[[10, 27]], 1);
+        assert_eq!(mem.display[[11, 27]], 0);
+        assert_eq!(mem.display[[17, 27]], 1);
+        assert_eq!(mem.display[[18, 27]], 1);
+        assert_eq!(mem.display[[19, 27]], 0);
+        assert_eq!(mem.display[[25, 27]], 1);
+        assert_eq!(cpu.v_registers[0x0F], 0);
+        assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
+
+        // drawing it again erases it and reports the collision
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(mem.display[[10, 20]], 0);
+        assert_eq!(mem.display[[25, 27]], 0);
+        assert_eq!(cpu.v_registers[0x0F], 1);
+    }
+
     #[test]
     fn test_opex9e()
     {
@@ -1048,6 +3138,48 @@ mod tests
         assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
     }
 
+    #[test]
+    fn fx0a_completes_on_key_release()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let mut key = Keyboard::new();
+        // LD V4, K ; LD V4, K
+        for (i, &byte) in [0xF4, 0x0A, 0xF4, 0x0A].iter().enumerate() {
+            mem[0x200 + i] = byte;
+        }
+        let mut pressed = [0; 16];
+
+        // a key held before the wait does not complete it
+        pressed[0x7] = 1;
+        key.update(pressed);
+        cpu.step(&mut mem, &key);
+        assert!(cpu.waiting_for_input);
+        cpu.step(&mut mem, &key);
+        assert!(cpu.waiting_for_input);
+        assert_eq!(cpu.v_registers[4], 0);
+
+        // pressing another key does not either, releasing one does
+        pressed[0x2] = 1;
+        key.update(pressed);
+        cpu.step(&mut mem, &key);
+        assert!(cpu.waiting_for_input);
+        pressed[0x2] = 0;
+        key.update(pressed);
+        cpu.step(&mut mem, &key);
+        assert_eq!(cpu.v_registers[4], 0x2);
+
+        // the second wait started in the same cycle, it needs a new press and release
+        assert!(cpu.waiting_for_input);
+        assert_eq!(cpu.pc, 0x204);
+        cpu.step(&mut mem, &key);
+        assert!(cpu.waiting_for_input);
+        key.update([0; 16]);
+        cpu.step(&mut mem, &key);
+        assert!(!cpu.waiting_for_input);
+        assert_eq!(cpu.v_registers[4], 0x7);
+    }
+
     #[test]
     fn test_opfx15()
     {
@@ -1092,6 +3224,348 @@ mod tests
         cpu.execute_opcode(&mut mem, &key);
         assert_eq!(cpu.i_register, 6);
         assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
+        // I wraps instead of overflowing
+        cpu.v_registers[4] = 0xFF;
+        cpu.i_register = 0xFFFF;
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(cpu.i_register, 0xFE);
+    }
+
+    #[test]
+    fn test_opfx1e_index_overflow_quirk()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        cpu.index_overflow_quirk = true;
+
+        // I + V4 stays within the address space, VF is untouched
+        cpu.v_registers[0xF] = 7;
+        cpu.v_registers[4] = 0x0F;
+        cpu.i_register = 0xFF0;
+        cpu.execute_raw(0xF41E, &mut mem, &key);
+        assert_eq!(cpu.i_register, 0xFFF);
+        assert_eq!(cpu.v_registers[0xF], 7);
+
+        // I + V4 goes past 0xFFF
+        cpu.execute_raw(0xF41E, &mut mem, &key);
+        assert_eq!(cpu.i_register, 0x100E);
+        assert_eq!(cpu.v_registers[0xF], 1);
+
+        // without the quirk VF is never set
+        cpu.index_overflow_quirk = false;
+        cpu.v_registers[0xF] = 0;
+        cpu.execute_raw(0xF41E, &mut mem, &key);
+        assert_eq!(cpu.i_register, 0x101D);
+        assert_eq!(cpu.v_registers[0xF], 0);
+    }
+
+    #[test]
+    fn test_opdxyn_wrap_quirk()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        mem[0x300] = 0xFF;
+        mem[0x301] = 0xFF;
+        cpu.i_register = 0x300;
+        cpu.v_registers[0] = 60;
+        cpu.v_registers[1] = 31;
+
+        // 8x2 sprite at (60, 31) wraps to the left and top edges
+        cpu.execute_raw(0xD012, &mut mem, &key);
+        assert_eq!(mem.display[[63, 31]], 1);
+        assert_eq!(mem.display[[0, 31]], 1);
+        assert_eq!(mem.display[[3, 31]], 1);
+        assert_eq!(mem.display[[4, 31]], 0);
+        assert_eq!(mem.display[[60, 0]], 1);
+        assert_eq!(mem.display[[3, 0]], 1);
+
+        // clipped at the right and bottom edges
+        mem.display.clear();
+        cpu.wrap_quirk = WrapQuirk::Clip;
+        cpu.execute_raw(0xD012, &mut mem, &key);
+        assert_eq!(mem.display[[60, 31]], 1);
+        assert_eq!(mem.display[[63, 31]], 1);
+        assert_eq!(mem.display[[0, 31]], 0);
+        assert_eq!(mem.display[[60, 0]], 0);
+        assert_eq!(mem.display.pixels().iter().filter(|&&pixel| pixel == 1).count(), 4);
+
+        // the starting coordinate still wraps
+        mem.display.clear();
+        cpu.v_registers[0] = 64 + 2;
+        cpu.v_registers[1] = 32 + 1;
+        cpu.execute_raw(0xD011, &mut mem, &key);
+        assert_eq!(mem.display[[2, 1]], 1);
+        assert_eq!(mem.display[[9, 1]], 1);
+    }
+
+    #[test]
+    fn opcode_histogram()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+
+        cpu.execute_raw(0x6005, &mut mem, &key);
+        assert_eq!(cpu.opcode_histogram(), [0; 16]);
+
+        cpu.profile = true;
+        for &opcode in [0x6005, 0x6107, 0x8014, 0xA300, 0xF01E, 0x00E0, 0x8FFF].iter() {
+            cpu.execute_raw(opcode, &mut mem, &key);
+        }
+        let histogram = cpu.opcode_histogram();
+        assert_eq!(histogram[0x6], 2);
+        assert_eq!(histogram[0x8], 2);
+        assert_eq!(histogram[0xA], 1);
+        assert_eq!(histogram[0xF], 1);
+        assert_eq!(histogram[0x0], 1);
+        assert_eq!(histogram.iter().sum::<u64>(), 7);
+
+        cpu.reset_histogram();
+        assert_eq!(cpu.opcode_histogram(), [0; 16]);
+    }
+
+    #[test]
+    fn unknown_opcode()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+
+        cpu.opcode = 0x8FFF;
+        assert_eq!(cpu.try_execute_opcode(&mut mem, &key), Err(UnknownOpcode { opcode: 0x8FFF, pc: 0x200 }));
+        assert_eq!(cpu.pc, 0x200);
+
+        // the default policy skips it like before
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(cpu.pc, 0x202);
+        assert!(!cpu.paused);
+
+        cpu.unknown_opcode = UnknownOpcodePolicy::Pause;
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(cpu.pc, 0x202);
+        assert!(cpu.paused);
+
+        cpu.opcode = 0x6005;
+        assert_eq!(cpu.try_execute_opcode(&mut mem, &key), Ok(()));
+    }
+
+    #[test]
+    fn warn_unknown_once_per_address()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        // 0x8FFF ; JP 0x200
+        mem[0x200] = 0x8F;
+        mem[0x201] = 0xFF;
+        mem[0x202] = 0x12;
+        mem[0x203] = 0x00;
+
+        cpu.execute_raw(0x8FFF, &mut mem, &key);
+        assert!(cpu.warned_unknown.is_empty());
+
+        cpu.warn_unknown = true;
+        cpu.pc = 0x200;
+        for _ in 0..10 {
+            cpu.step(&mut mem, &key);
+        }
+        assert_eq!(cpu.warned_unknown.len(), 1);
+        assert!(cpu.warned_unknown.contains(&0x200));
+
+        cpu.pc = 0x300;
+        cpu.execute_raw(0x8FFF, &mut mem, &key);
+        assert_eq!(cpu.warned_unknown.len(), 2);
+        assert!(cpu.warned_unknown.contains(&0x300));
+    }
+
+    #[test]
+    fn collision_count()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        // digit 0 sprite
+        cpu.i_register = 0;
+
+        // DRW V0, V1, 5 twice at the same place, then next to it without overlap
+        cpu.execute_raw(0xD015, &mut mem, &key);
+        assert_eq!(cpu.collision_count(), 0);
+        cpu.execute_raw(0xD015, &mut mem, &key);
+        assert_eq!(cpu.collision_count(), 1);
+        cpu.v_registers[0] = 8;
+        cpu.execute_raw(0xD015, &mut mem, &key);
+        assert_eq!(cpu.collision_count(), 1);
+        // overlapping by a column
+        cpu.v_registers[0] = 11;
+        cpu.execute_raw(0xD015, &mut mem, &key);
+        assert_eq!(cpu.collision_count(), 2);
+
+        cpu.reset_stats();
+        assert_eq!(cpu.collision_count(), 0);
+        assert_eq!(cpu.cycles(), 0);
+    }
+
+    #[test]
+    fn test_opdxyn_vblank_quirk()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        cpu.vblank_quirk = true;
+        // DRW V0, V0, 1 ; LD V1, 0x01
+        mem.memory[0x200..0x204].copy_from_slice(&[0xD0, 0x01, 0x61, 0x01]);
+
+        cpu.do_cycle(&mut mem, &key);
+        assert_eq!(cpu.pc, 0x202);
+        cpu.do_cycle(&mut mem, &key);
+        cpu.do_cycle(&mut mem, &key);
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(cpu.v_registers[1], 0);
+
+        // next frame
+        cpu.update_timers().unwrap();
+        cpu.do_cycle(&mut mem, &key);
+        assert_eq!(cpu.pc, 0x204);
+        assert_eq!(cpu.v_registers[1], 1);
+
+        // no wait without the quirk
+        cpu.vblank_quirk = false;
+        cpu.pc = 0x200;
+        cpu.do_cycle(&mut mem, &key);
+        cpu.do_cycle(&mut mem, &key);
+        assert_eq!(cpu.pc, 0x204);
+    }
+
+    #[test]
+    fn test_opfn01()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+
+        assert_eq!(cpu.selected_planes, 0x01);
+        cpu.execute_raw(0xF201, &mut mem, &key);
+        assert_eq!(cpu.selected_planes, 0x02);
+        cpu.execute_raw(0xF301, &mut mem, &key);
+        assert_eq!(cpu.selected_planes, 0x03);
+        cpu.execute_raw(0xF001, &mut mem, &key);
+        assert_eq!(cpu.selected_planes, 0x00);
+    }
+
+    #[test]
+    fn test_opdxyn_planes()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        mem[0x300] = 0b1100_0000;
+        mem[0x301] = 0b1010_0000;
+        cpu.i_register = 0x300;
+
+        // plane 2 only, reading the first sprite byte
+        cpu.execute_raw(0xF201, &mut mem, &key);
+        cpu.execute_raw(0xD001, &mut mem, &key);
+        assert_eq!(mem.display[[0, 0]], 0b10);
+        assert_eq!(mem.display[[1, 0]], 0b10);
+        assert_eq!(mem.display[[2, 0]], 0);
+        assert_eq!(cpu.v_registers[0xF], 0);
+
+        // both planes, plane 2 data follows plane 1 data
+        cpu.execute_raw(0xF301, &mut mem, &key);
+        cpu.execute_raw(0xD001, &mut mem, &key);
+        assert_eq!(mem.display[[0, 0]], 0b01);
+        assert_eq!(mem.display[[1, 0]], 0b11);
+        assert_eq!(mem.display[[2, 0]], 0b10);
+        assert_eq!(cpu.v_registers[0xF], 1);
+
+        // clearing plane 1 keeps plane 2
+        cpu.execute_raw(0xF101, &mut mem, &key);
+        cpu.execute_raw(0x00E0, &mut mem, &key);
+        assert_eq!(mem.display[[0, 0]], 0);
+        assert_eq!(mem.display[[1, 0]], 0b10);
+        assert_eq!(mem.display[[2, 0]], 0b10);
+    }
+
+    #[test]
+    fn test_opf002_opfx3a()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        assert_eq!(cpu.audio_buffer(), None);
+
+        for i in 0..16 {
+            mem[0x300 + i] = i as u8;
+        }
+        cpu.i_register = 0x300;
+        cpu.execute_raw(0xF002, &mut mem, &key);
+        let buffer = cpu.audio_buffer().unwrap();
+        assert_eq!(buffer.pattern[0], 0);
+        assert_eq!(buffer.pattern[15], 15);
+        assert_eq!(buffer.pitch, 64);
+
+        cpu.v_registers[5] = 112;
+        cpu.execute_raw(0xF53A, &mut mem, &key);
+        assert_eq!(cpu.audio_buffer().unwrap().pitch, 112);
+        assert_eq!(cpu.i_register, 0x300);
+    }
+
+    #[test]
+    fn memory_access_wraps_past_ram_end()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+
+        // LD B, V4 across the end of the RAM
+        cpu.v_registers[4] = 249;
+        cpu.i_register = 0x0FFF;
+        cpu.execute_raw(0xF433, &mut mem, &key);
+        assert_eq!(mem[0xFFF], 2);
+        assert_eq!(mem[0x000], 4);
+        assert_eq!(mem[0x001], 9);
+
+        // LD [I], V3 and LD V3, [I] with I past the RAM
+        cpu.v_registers[..4].copy_from_slice(&[1, 2, 3, 4]);
+        cpu.i_register = 0x1FFE;
+        cpu.execute_raw(0xF355, &mut mem, &key);
+        assert_eq!(&mem.memory[0xFFE..], &[1, 2]);
+        assert_eq!(&mem.memory[..2], &[3, 4]);
+        cpu.v_registers = [0; 16];
+        cpu.execute_raw(0xF365, &mut mem, &key);
+        assert_eq!(&cpu.v_registers[..4], &[1, 2, 3, 4]);
+
+        // DRW V0, V0, 5 with the sprite crossing the end of the RAM
+        cpu.v_registers[0] = 0;
+        cpu.i_register = 0xFFF;
+        cpu.execute_raw(0xD005, &mut mem, &key);
+        assert_eq!(mem.display[[0, 0]], mem[0xFFF] >> 7);
+        assert_eq!(mem.display[[6, 1]], (mem[0x000] >> 1) & 1);
+    }
+
+    #[test]
+    fn test_opf000()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        // LD I, LONG 0x0ABC ; SE V0, 0 ; LD I, LONG 0x0DEF ; LD V1, 0x01
+        let program = [0xF0, 0x00, 0x0A, 0xBC, 0x30, 0x00, 0xF0, 0x00, 0x0D, 0xEF, 0x61, 0x01];
+        for (i, &byte) in program.iter().enumerate() {
+            mem[0x200 + i] = byte;
+        }
+
+        cpu.cycle(&mut mem, &key);
+        assert_eq!(cpu.i_register, 0x0ABC);
+        assert_eq!(cpu.pc, 0x204);
+        // skips step over the whole long load
+        cpu.cycle(&mut mem, &key);
+        assert_eq!(cpu.pc, 0x20A);
+        assert_eq!(cpu.i_register, 0x0ABC);
+        cpu.cycle(&mut mem, &key);
+        assert_eq!(cpu.v_registers[1], 0x01);
     }
 
     #[test]
@@ -1120,6 +3594,61 @@ mod tests
         cpu.execute_opcode(&mut mem, &key);
         assert_eq!(cpu.i_register, 20);
         assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
+        assert_eq!(&mem[20..25], &SPRITES[20..25]);
+    }
+
+    #[test]
+    fn test_opfx30()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        cpu.opcode = 0xF430;
+
+        cpu.pc = 0x200;
+        cpu.v_registers[4] = 0;
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(cpu.i_register, 0x50);
+        assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
+        assert_eq!(&mem[0x50..0x5A], &BIG_SPRITES[..10]);
+
+        cpu.v_registers[4] = 7;
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(cpu.i_register, 0x50 + 70);
+        assert_eq!(&mem[0x50 + 70..0x50 + 80], &BIG_SPRITES[70..80]);
+
+        // the small font is still at the start of the RAM
+        cpu.execute_raw(0xF429, &mut mem, &key);
+        assert_eq!(cpu.i_register, 35);
+    }
+
+    #[test]
+    fn test_opfx75_opfx85()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        cpu.v_registers[..4].copy_from_slice(&[0x12, 0x34, 0x56, 0x78]);
+        cpu.execute_raw(0xF275, &mut mem, &key);
+        assert_eq!(cpu.flags(), [0x12, 0x34, 0x56, 0, 0, 0, 0, 0]);
+        assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
+
+        cpu.v_registers = [0xAA; 16];
+        cpu.execute_raw(0xF185, &mut mem, &key);
+        assert_eq!(cpu.v_registers[..3], [0x12, 0x34, 0xAA]);
+
+        // x is clamped to the 8 flags
+        cpu.execute_raw(0xFF75, &mut mem, &key);
+        assert_eq!(cpu.flags(), [0x12, 0x34, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA]);
+        cpu.v_registers = [0; 16];
+        cpu.execute_raw(0xFF85, &mut mem, &key);
+        assert_eq!(cpu.v_registers[..9], [0x12, 0x34, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0]);
+
+        // the flags survive a reset and can be restored from a previous run
+        cpu.reset();
+        assert_eq!(cpu.flags()[0], 0x12);
+        cpu.set_flags(&[1, 2]);
+        assert_eq!(cpu.flags(), [1, 2, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA]);
     }
 
     #[test]
@@ -1138,6 +3667,20 @@ mod tests
         assert_eq!(mem[0x661], 4);
         assert_eq!(mem[0x662], 9);
         assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
+
+        for &(value, digits) in [(0, [0, 0, 0]), (9, [0, 0, 9]), (10, [0, 1, 0]),
+                                 (99, [0, 9, 9]), (100, [1, 0, 0]), (255, [2, 5, 5])].iter() {
+            cpu.v_registers[4] = value;
+            cpu.execute_opcode(&mut mem, &key);
+            assert_eq!(&mem.memory[0x660..0x663], &digits, "BCD of {}", value);
+        }
+
+        // the highest I wraps to the start of the RAM instead of panicking
+        cpu.v_registers[4] = 255;
+        cpu.i_register = 0xFFFF;
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(mem[0xFFF], 2);
+        assert_eq!(&mem.memory[..2], &[5, 5]);
     }
 
     #[test]
@@ -1164,6 +3707,27 @@ mod tests
         assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
     }
 
+    #[test]
+    fn test_opfx55_write_protect()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        mem.protected = Some(0x000..0x050);
+        cpu.opcode = 0xF255;
+        cpu.v_registers[..3].copy_from_slice(&[7, 8, 9]);
+
+        // the write straddling the end of the font only reaches the unprotected byte
+        cpu.i_register = 0x04E;
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(&mem[0x04E..0x051], &[SPRITES[0x04E], SPRITES[0x04F], 9]);
+        assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
+
+        cpu.i_register = 0x300;
+        cpu.execute_opcode(&mut mem, &key);
+        assert_eq!(&mem[0x300..0x303], &[7, 8, 9]);
+    }
+
     #[test]
     fn test_opfx65()
     {
@@ -1187,4 +3751,76 @@ mod tests
         assert_eq!(cpu.v_registers[4], 244);
         assert_eq!(cpu.pc, 0x200 + OPCODE_SIZE);
     }
+
+    #[test]
+    fn test_opfx55_load_store_quirk()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        let modes = [
+            (LoadStoreQuirk::Unchanged, 0x660, 0x660),
+            (LoadStoreQuirk::Increment, 0x661, 0x670),
+            (LoadStoreQuirk::IncrementMinusOne, 0x660, 0x66F),
+        ];
+
+        for &(quirk, i_after_x0, i_after_x15) in modes.iter() {
+            cpu.load_store_quirk = quirk;
+            for (index, register) in cpu.v_registers.iter_mut().enumerate() {
+                *register = index as u8 + 1;
+            }
+
+            // x = 0
+            cpu.opcode = 0xF055;
+            cpu.i_register = 0x660;
+            cpu.execute_opcode(&mut mem, &key);
+            assert_eq!(mem[0x660], 1);
+            assert_eq!(cpu.i_register, i_after_x0);
+
+            // x = 15
+            cpu.opcode = 0xFF55;
+            cpu.i_register = 0x660;
+            cpu.execute_opcode(&mut mem, &key);
+            assert_eq!(mem[0x660], 1);
+            assert_eq!(mem[0x66F], 16);
+            assert_eq!(cpu.i_register, i_after_x15);
+        }
+    }
+
+    #[test]
+    fn test_opfx65_load_store_quirk()
+    {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let key = Keyboard::new();
+        let modes = [
+            (LoadStoreQuirk::Unchanged, 0x660, 0x660),
+            (LoadStoreQuirk::Increment, 0x661, 0x670),
+            (LoadStoreQuirk::IncrementMinusOne, 0x660, 0x66F),
+        ];
+        for index in 0..16 {
+            mem[0x660 + index] = index as u8 + 1;
+        }
+
+        for &(quirk, i_after_x0, i_after_x15) in modes.iter() {
+            cpu.load_store_quirk = quirk;
+            cpu.v_registers = [0; 16];
+
+            // x = 0
+            cpu.opcode = 0xF065;
+            cpu.i_register = 0x660;
+            cpu.execute_opcode(&mut mem, &key);
+            assert_eq!(cpu.v_registers[0], 1);
+            assert_eq!(cpu.v_registers[1], 0);
+            assert_eq!(cpu.i_register, i_after_x0);
+
+            // x = 15
+            cpu.opcode = 0xFF65;
+            cpu.i_register = 0x660;
+            cpu.execute_opcode(&mut mem, &key);
+            assert_eq!(cpu.v_registers[0], 1);
+            assert_eq!(cpu.v_registers[15], 16);
+            assert_eq!(cpu.i_register, i_after_x15);
+        }
+    }
 }
@@ -0,0 +1,147 @@
+//!
+//! Disassembler
+//!
+
+// Decode an opcode into its mnemonic, using the same notation as the cpu
+pub fn disassemble(opcode: u16) -> String
+{
+    let splitted_opcode = (
+        ((opcode & 0xF000) >> 12) as u8,
+        ((opcode & 0x0F00) >> 8) as u8,
+        ((opcode & 0x00F0) >> 4) as u8,
+        (opcode & 0x000F) as u8,
+    );
+    let nnn = opcode & 0x0FFF;
+    let kk = opcode & 0x00FF;
+    let x = splitted_opcode.1;
+    let y = splitted_opcode.2;
+    let n = splitted_opcode.3;
+
+    match splitted_opcode {
+        (0x00, 0x00, 0x0e, 0x00) => String::from("CLS"),
+        (0x00, 0x00, 0x0e, 0x0e) => String::from("RET"),
+        (0x00, 0x00, 0x0c, _) => format!("SCD {}", n),
+        (0x00, 0x00, 0x0f, 0x0b) => String::from("SCR"),
+        (0x00, 0x00, 0x0f, 0x0c) => String::from("SCL"),
+        (0x00, 0x00, 0x0f, 0x0e) => String::from("LOW"),
+        (0x00, 0x00, 0x0f, 0x0f) => String::from("HIGH"),
+        (0x01, _, _, _) => format!("JP {:#05X}", nnn),
+        (0x02, _, _, _) => format!("CALL {:#05X}", nnn),
+        (0x03, _, _, _) => format!("SE V{:X}, {:#04X}", x, kk),
+        (0x04, _, _, _) => format!("SNE V{:X}, {:#04X}", x, kk),
+        (0x05, _, _, 0x00) => format!("SE V{:X}, V{:X}", x, y),
+        (0x06, _, _, _) => format!("LD V{:X}, {:#04X}", x, kk),
+        (0x07, _, _, _) => format!("ADD V{:X}, {:#04X}", x, kk),
+        (0x08, _, _, 0x00) => format!("LD V{:X}, V{:X}", x, y),
+        (0x08, _, _, 0x01) => format!("OR V{:X}, V{:X}", x, y),
+        (0x08, _, _, 0x02) => format!("AND V{:X}, V{:X}", x, y),
+        (0x08, _, _, 0x03) => format!("XOR V{:X}, V{:X}", x, y),
+        (0x08, _, _, 0x04) => format!("ADD V{:X}, V{:X}", x, y),
+        (0x08, _, _, 0x05) => format!("SUB V{:X}, V{:X}", x, y),
+        (0x08, _, _, 0x06) => format!("SHR V{:X}, V{:X}", x, y),
+        (0x08, _, _, 0x07) => format!("SUBN V{:X}, V{:X}", x, y),
+        (0x08, _, _, 0x0E) => format!("SHL V{:X}, V{:X}", x, y),
+        (0x09, _, _, 0x00) => format!("SNE V{:X}, V{:X}", x, y),
+        (0x0A, _, _, _) => format!("LD I, {:#05X}", nnn),
+        (0x0B, _, _, _) => format!("JP V0, {:#05X}", nnn),
+        (0x0C, _, _, _) => format!("RND V{:X}, {:#04X}", x, kk),
+        (0x0d, _, _, _) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        (0x0e, _, 0x09, 0x0e) => format!("SKP V{:X}", x),
+        (0x0e, _, 0x0a, 0x01) => format!("SKNP V{:X}", x),
+        (0x0f, _, 0x00, 0x01) => format!("PLANE {}", x),
+        (0x0f, 0x00, 0x00, 0x00) => String::from("LD I, LONG"),
+        (0x0f, 0x00, 0x00, 0x02) => String::from("AUDIO"),
+        (0x0f, _, 0x00, 0x07) => format!("LD V{:X}, DT", x),
+        (0x0f, _, 0x00, 0x0a) => format!("LD V{:X}, K", x),
+        (0x0f, _, 0x01, 0x05) => format!("LD DT, V{:X}", x),
+        (0x0f, _, 0x01, 0x08) => format!("LD ST, V{:X}", x),
+        (0x0f, _, 0x01, 0x0e) => format!("ADD I, V{:X}", x),
+        (0x0f, _, 0x02, 0x09) => format!("LD F, V{:X}", x),
+        (0x0f, _, 0x03, 0x00) => format!("LD HF, V{:X}", x),
+        (0x0f, _, 0x07, 0x05) => format!("LD R, V{:X}", x),
+        (0x0f, _, 0x08, 0x05) => format!("LD V{:X}, R", x),
+        (0x0f, _, 0x03, 0x0a) => format!("PITCH V{:X}", x),
+        (0x0f, _, 0x03, 0x03) => format!("LD B, V{:X}", x),
+        (0x0f, _, 0x05, 0x05) => format!("LD [I], V{:X}", x),
+        (0x0f, _, 0x06, 0x05) => format!("LD V{:X}, [I]", x),
+        _ => format!("UNKNOWN {:#06X}", opcode),
+    }
+}
+
+// Listing of the len bytes of memory from start, one line per opcode with its address, bytes and mnemonic.
+// A trailing odd byte is listed as data.
+pub fn listing(memory: &[u8], start: usize, len: usize) -> Vec<String>
+{
+    let end = (start + len).min(memory.len());
+    (start..end).step_by(2)
+        .map(|address| match memory.get(address + 1).filter(|_| address + 1 < end) {
+            Some(&low) => {
+                let opcode = (memory[address] as u16) << 8 | low as u16;
+                format!("{:04X}  {:02X} {:02X}  {}", address, memory[address], low, disassemble(opcode))
+            },
+            None => format!("{:04X}  {:02X}     DB {:#04X}", address, memory[address], memory[address]),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use super::super::memory::Memory;
+
+    #[test]
+    fn disassemble_known_opcodes()
+    {
+        assert_eq!(disassemble(0x00E0), "CLS");
+        assert_eq!(disassemble(0x00EE), "RET");
+        assert_eq!(disassemble(0x00FF), "HIGH");
+        assert_eq!(disassemble(0x00C4), "SCD 4");
+        assert_eq!(disassemble(0x00FB), "SCR");
+        assert_eq!(disassemble(0x00FC), "SCL");
+        assert_eq!(disassemble(0x1228), "JP 0x228");
+        assert_eq!(disassemble(0x2300), "CALL 0x300");
+        assert_eq!(disassemble(0x3469), "SE V4, 0x69");
+        assert_eq!(disassemble(0x6440), "LD V4, 0x40");
+        assert_eq!(disassemble(0x6A05), "LD VA, 0x05");
+        assert_eq!(disassemble(0x845E), "SHL V4, V5");
+        assert_eq!(disassemble(0xA456), "LD I, 0x456");
+        assert_eq!(disassemble(0xB512), "JP V0, 0x512");
+        assert_eq!(disassemble(0xD455), "DRW V4, V5, 5");
+        assert_eq!(disassemble(0xF201), "PLANE 2");
+        assert_eq!(disassemble(0xF002), "AUDIO");
+        assert_eq!(disassemble(0xF000), "LD I, LONG");
+        assert_eq!(disassemble(0xF53A), "PITCH V5");
+        assert_eq!(disassemble(0xF330), "LD HF, V3");
+        assert_eq!(disassemble(0xF775), "LD R, V7");
+        assert_eq!(disassemble(0xF285), "LD V2, R");
+        assert_eq!(disassemble(0xE49E), "SKP V4");
+        assert_eq!(disassemble(0xF40A), "LD V4, K");
+        assert_eq!(disassemble(0xFF55), "LD [I], VF");
+        assert_eq!(disassemble(0xF265), "LD V2, [I]");
+    }
+
+    #[test]
+    fn disassemble_unknown_opcodes()
+    {
+        assert_eq!(disassemble(0x8FFF), "UNKNOWN 0x8FFF");
+        assert_eq!(disassemble(0x5121), "UNKNOWN 0x5121");
+        assert_eq!(disassemble(0xE4FF), "UNKNOWN 0xE4FF");
+    }
+
+    #[test]
+    fn rom_listing()
+    {
+        let mut memory = Memory::new();
+        let rom = [0x6A, 0x02, 0xD0, 0x15, 0xFF, 0xFF, 0x12];
+        memory.load_bytes(&rom, 0x200).unwrap();
+        assert_eq!(listing(&memory.memory, 0x200, rom.len()), [
+            "0200  6A 02  LD VA, 0x02",
+            "0202  D0 15  DRW V0, V1, 5",
+            "0204  FF FF  UNKNOWN 0xFFFF",
+            "0206  12     DB 0x12",
+        ]);
+        // stops at the end of the RAM
+        assert_eq!(listing(&memory.memory, 0xFFE, 4), ["0FFE  00 00  UNKNOWN 0x0000"]);
+    }
+}
@@ -0,0 +1,118 @@
+//!
+//! Whole machine run one frame at a time, for frontends embedding the core
+//!
+
+use super::cpu::{Cpu, CycleOutcome};
+use super::memory::{Memory, Display};
+use super::keyboard::Keyboard;
+
+// the 1000Hz default clock rate of the binary at 60 frames per second
+const DEFAULT_CYCLES_PER_FRAME: u32 = 16;
+
+// Called with the display once per rendered frame
+pub type FrameCallback = Box<dyn FnMut(&Display)>;
+
+// Cpu, memory and keyboard driven at the 60Hz timer rate: every frame runs
+// cycles_per_frame cycles, ticks the timers and hands the display, presented when
+// double buffered, to the frame callbacks.
+// The frontend only feeds the keyboard and draws the display from its callback.
+pub struct Emulator
+{
+    pub cpu: Cpu,
+    pub memory: Memory,
+    pub keyboard: Keyboard,
+    frame_callbacks: Vec<FrameCallback>,
+    frames: u64,
+
+    pub cycles_per_frame: u32,
+}
+
+impl Default for Emulator
+{
+    fn default() -> Emulator
+    {
+        Emulator::new()
+    }
+}
+
+impl Emulator
+{
+    pub fn new() -> Emulator
+    {
+        Emulator::with_parts(Cpu::new(), Memory::new(), Keyboard::new())
+    }
+
+    // Emulator around an already configured cpu, memory and keyboard, e.g. with a ROM loaded
+    pub fn with_parts(cpu: Cpu, memory: Memory, keyboard: Keyboard) -> Emulator
+    {
+        Emulator {
+            cpu,
+            memory,
+            keyboard,
+            frame_callbacks: Vec::new(),
+            frames: 0,
+            cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+        }
+    }
+
+    // Register a callback run after every frame, in the order they were registered
+    pub fn on_frame(&mut self, callback: FrameCallback)
+    {
+        self.frame_callbacks.push(callback);
+    }
+
+    // Run the cycles of a frame, stopping early once the instruction limit is reached,
+    // then tick the timers and call the frame callbacks
+    pub fn run_frame(&mut self)
+    {
+        for _ in 0..self.cycles_per_frame {
+            if self.cpu.cycle(&mut self.memory, &self.keyboard) == CycleOutcome::LimitReached {
+                break;
+            }
+        }
+        let _ = self.cpu.update_timers();
+        self.memory.display.present();
+        for callback in self.frame_callbacks.iter_mut() {
+            callback(self.memory.display.front());
+        }
+        self.keyboard.next_frame();
+        self.frames += 1;
+    }
+
+    // Number of frames run so far
+    pub fn frame_count(&self) -> u64
+    {
+        self.frames
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn frame_callbacks()
+    {
+        let mut emulator = Emulator::new();
+        // LD V0, 0 ; LD F, V0 ; DRW V0, V0, 5 ; JP 0x206
+        emulator.cpu.load_program(&mut emulator.memory, &[0x6000, 0xF029, 0xD005, 0x1206]).unwrap();
+        let frames = Rc::new(Cell::new(0));
+        let lit = Rc::new(Cell::new(0));
+        let counter = frames.clone();
+        emulator.on_frame(Box::new(move |_| counter.set(counter.get() + 1)));
+        let pixels = lit.clone();
+        emulator.on_frame(Box::new(move |display| pixels.set(display.pixels().iter().filter(|&&pixel| pixel != 0).count())));
+
+        for _ in 0..5 {
+            emulator.run_frame();
+        }
+        assert_eq!(frames.get(), 5);
+        assert_eq!(emulator.frame_count(), 5);
+        // the 0 digit drawn on the first frame
+        assert_eq!(lit.get(), 14);
+        assert_eq!(emulator.cpu.cycles(), 5 * DEFAULT_CYCLES_PER_FRAME as u64);
+    }
+}
@@ -0,0 +1,175 @@
+//!
+//! Recording and replay of the keys pressed every frame
+//!
+
+use std::io;
+use std::io::{Error, ErrorKind};
+use std::fs;
+use std::path::Path;
+
+use super::snapshot::Reader;
+
+const MAGIC: &[u8; 4] = b"FNI1";
+// a day of frames at 60Hz, far more than any recording while keeping a hostile log from allocating gigabytes
+const MAX_FRAMES: usize = 24 * 60 * 60 * 60;
+
+// Chip-8 keys physically pressed on every rendered frame, one bit per key
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InputLog
+{
+    frames: Vec<u16>,
+}
+
+fn to_mask(pressed: [u8; 16]) -> u16
+{
+    pressed.iter().enumerate().fold(0, |mask, (i, &key)| if key != 0 { mask | 1 << i } else { mask })
+}
+
+fn from_mask(mask: u16) -> [u8; 16]
+{
+    let mut pressed = [0; 16];
+    for (i, key) in pressed.iter_mut().enumerate() {
+        *key = (mask >> i & 1) as u8;
+    }
+    pressed
+}
+
+impl InputLog
+{
+    pub fn new() -> InputLog
+    {
+        InputLog::default()
+    }
+
+    // Append the keys pressed on the next frame
+    pub fn push(&mut self, pressed: [u8; 16])
+    {
+        self.frames.push(to_mask(pressed));
+    }
+
+    // Keys pressed on a frame, None past the end of the log
+    pub fn get(&self, frame: usize) -> Option<[u8; 16]>
+    {
+        self.frames.get(frame).map(|&mask| from_mask(mask))
+    }
+
+    // Number of frames logged
+    pub fn frame_count(&self) -> usize
+    {
+        self.frames.len()
+    }
+
+    // The frames as runs of identical key states: a u32 frame count followed by the u16 keys mask
+    pub fn to_bytes(&self) -> Vec<u8>
+    {
+        let mut bytes = MAGIC.to_vec();
+        let mut frames = self.frames.iter().peekable();
+        while let Some(&mask) = frames.next() {
+            let mut run: u32 = 1;
+            while frames.peek() == Some(&&mask) && run < u32::MAX {
+                frames.next();
+                run += 1;
+            }
+            bytes.extend_from_slice(&run.to_be_bytes());
+            bytes.extend_from_slice(&mask.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<InputLog, io::Error>
+    {
+        let mut reader = Reader::new(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not an input log file"));
+        }
+        let mut frames = Vec::new();
+        while !reader.is_empty() {
+            let run = reader.u32()?;
+            let mask = reader.u16()?;
+            if run as usize > MAX_FRAMES - frames.len() {
+                return Err(Error::new(ErrorKind::InvalidData, "input log too long"));
+            }
+            frames.resize(frames.len() + run as usize, mask);
+        }
+        Ok(InputLog { frames })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), io::Error>
+    {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<InputLog, io::Error>
+    {
+        InputLog::from_bytes(&fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn keys(pressed: &[usize]) -> [u8; 16]
+    {
+        let mut keys = [0; 16];
+        for &key in pressed {
+            keys[key] = 1;
+        }
+        keys
+    }
+
+    #[test]
+    fn input_log_round_trip()
+    {
+        let mut log = InputLog::new();
+        let frames = [keys(&[]), keys(&[]), keys(&[0x5]), keys(&[0x5, 0xF]), keys(&[0x5, 0xF]), keys(&[0x0])];
+        for &pressed in frames.iter() {
+            log.push(pressed);
+        }
+        assert_eq!(log.frame_count(), 6);
+
+        let bytes = log.to_bytes();
+        // 4 runs of 6 bytes after the magic
+        assert_eq!(bytes.len(), 4 + 4 * 6);
+        assert_eq!(&bytes[4..10], &[0, 0, 0, 2, 0, 0]);
+        assert_eq!(&bytes[16..22], &[0, 0, 0, 2, 0x80, 0x20]);
+
+        let loaded = InputLog::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded, log);
+        for (frame, &pressed) in frames.iter().enumerate() {
+            assert_eq!(loaded.get(frame), Some(pressed));
+        }
+        assert_eq!(loaded.get(6), None);
+    }
+
+    #[test]
+    fn long_runs_are_compressed()
+    {
+        let mut log = InputLog::new();
+        for _ in 0..3600 {
+            log.push(keys(&[0x2]));
+        }
+        assert_eq!(log.to_bytes(), [b'F', b'N', b'I', b'1', 0, 0, 0x0E, 0x10, 0, 0x04]);
+        assert_eq!(InputLog::from_bytes(&log.to_bytes()).unwrap(), log);
+        assert_eq!(InputLog::new().to_bytes(), MAGIC.to_vec());
+    }
+
+    #[test]
+    fn invalid_input_logs()
+    {
+        assert!(InputLog::from_bytes(b"FNC1").is_err());
+        assert!(InputLog::from_bytes(b"FN").is_err());
+        // truncated run
+        assert!(InputLog::from_bytes(&[b'F', b'N', b'I', b'1', 0, 0, 0, 1, 0]).is_err());
+        // runs adding up past the frame limit
+        let oversized = [b'F', b'N', b'I', b'1', 0xFF, 0xFF, 0xFF, 0xFF, 0, 0];
+        assert_eq!(InputLog::from_bytes(&oversized).unwrap_err().kind(), ErrorKind::InvalidData);
+        let mut split = MAGIC.to_vec();
+        for _ in 0..2 {
+            split.extend_from_slice(&(MAX_FRAMES as u32 / 2 + 1).to_be_bytes());
+            split.extend_from_slice(&[0, 0]);
+        }
+        assert_eq!(InputLog::from_bytes(&split).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
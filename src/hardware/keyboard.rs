@@ -4,12 +4,17 @@
 
 use std::ops::{Deref, DerefMut};
 
-use sdl2::EventPump;
-use sdl2::keyboard::Keycode;
-
+// The 16 chip-8 keys, pressed (1) or not (0), updated once per frame by the frontend
 pub struct Keyboard
 {
     keyboard: [u8; 16],
+    // keys reported before the last update
+    previous: [u8; 16],
+    physical: [u8; 16],
+    hold: [u32; 16],
+
+    // number of frames a key keeps being reported as pressed after its release
+    pub hold_frames: u32,
 }
 
 impl Deref for Keyboard
@@ -36,43 +41,216 @@ impl Keyboard
     {
         Keyboard {
             keyboard: [0; 16],
+            previous: [0; 16],
+            physical: [0; 16],
+            hold: [0; 16],
+            hold_frames: 0,
         }
     }
 
-    pub fn read(&mut self, event_pump: &EventPump)
-    {
-        let keys: Vec<Keycode> = event_pump
-            .keyboard_state()
-            .pressed_scancodes()
-            .filter_map(Keycode::from_scancode)
-            .collect();
-
-        self.keyboard = [0; 16];
-
-        for key in keys {
-            let index = match key {
-                Keycode::Num1 => Some(0x1),
-                Keycode::Num2 => Some(0x2),
-                Keycode::Num3 => Some(0x3),
-                Keycode::Num4 => Some(0xC),
-                Keycode::A => Some(0x4),
-                Keycode::Z => Some(0x5),
-                Keycode::E => Some(0x6),
-                Keycode::R => Some(0xD),
-                Keycode::Q => Some(0x7),
-                Keycode::S => Some(0x8),
-                Keycode::D => Some(0x9),
-                Keycode::F => Some(0xE),
-                Keycode::W => Some(0xA),
-                Keycode::X => Some(0x0),
-                Keycode::C => Some(0xB),
-                Keycode::V => Some(0xF),
-                _ => None,
-            };
-            if let Some(i) = index {
-                self.keyboard[i] = 1;
+    // Keys physically pressed on the last update, regardless of the hold
+    pub fn physical(&self) -> [u8; 16]
+    {
+        self.physical
+    }
+
+    // Update the keyboard from the keys physically pressed,
+    // a released key stays pressed while its hold counter is not exhausted
+    pub fn update(&mut self, pressed: [u8; 16])
+    {
+        self.physical = pressed;
+        self.previous = self.keyboard;
+        for (i, &key) in pressed.iter().enumerate() {
+            if key == 1 {
+                self.hold[i] = self.hold_frames;
             }
+            self.keyboard[i] = if key == 1 || self.hold[i] > 0 { 1 } else { 0 };
+        }
+    }
 
+    // Press or release a single chip-8 key, the other keys keeping their physical state
+    pub fn set_key(&mut self, nibble: u8, pressed: bool) -> Result<(), String>
+    {
+        if nibble > 0xF {
+            return Err(format!("invalid chip-8 key {:#X}, expected 0x0 to 0xF", nibble));
         }
+        let mut keys = self.physical;
+        keys[nibble as usize] = pressed as u8;
+        self.update(keys);
+        Ok(())
+    }
+
+    pub fn press(&mut self, nibble: u8) -> Result<(), String>
+    {
+        self.set_key(nibble, true)
+    }
+
+    pub fn release(&mut self, nibble: u8) -> Result<(), String>
+    {
+        self.set_key(nibble, false)
+    }
+
+    // Keys reported released before the last update and pressed by it, held keys included
+    pub fn just_pressed(&self) -> impl Iterator<Item = u8> + '_
+    {
+        (0..16).filter(move |&i| self.previous[i] == 0 && self.keyboard[i] == 1).map(|i| i as u8)
+    }
+
+    // Keys reported pressed before the last update and released by it, once their hold is over
+    pub fn just_released(&self) -> impl Iterator<Item = u8> + '_
+    {
+        (0..16).filter(move |&i| self.previous[i] == 1 && self.keyboard[i] == 0).map(|i| i as u8)
+    }
+
+    // Consume one frame of hold for every released key
+    pub fn next_frame(&mut self)
+    {
+        for i in 0..16 {
+            if self.physical[i] == 0 && self.hold[i] > 0 {
+                self.hold[i] -= 1;
+            }
+        }
+    }
+}
+
+impl Default for Keyboard
+{
+    fn default() -> Keyboard
+    {
+        Keyboard::new()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn keyboard_without_hold()
+    {
+        let mut keyboard = Keyboard::new();
+        let mut pressed = [0; 16];
+
+        pressed[0x4] = 1;
+        keyboard.update(pressed);
+        assert_eq!(keyboard[0x4], 1);
+        keyboard.next_frame();
+
+        keyboard.update([0; 16]);
+        assert_eq!(keyboard[0x4], 0);
+    }
+
+    #[test]
+    fn keyboard_hold_masks_short_release()
+    {
+        let mut keyboard = Keyboard::new();
+        keyboard.hold_frames = 2;
+        let mut pressed = [0; 16];
+        pressed[0x4] = 1;
+
+        // pressed, released for one frame, pressed again
+        keyboard.update(pressed);
+        assert_eq!(keyboard[0x4], 1);
+        keyboard.next_frame();
+        keyboard.update([0; 16]);
+        assert_eq!(keyboard[0x4], 1);
+        keyboard.next_frame();
+        keyboard.update(pressed);
+        assert_eq!(keyboard[0x4], 1);
+        keyboard.next_frame();
+
+        // a long release is reported once the hold is exhausted
+        keyboard.update([0; 16]);
+        assert_eq!(keyboard[0x4], 1);
+        keyboard.next_frame();
+        keyboard.update([0; 16]);
+        assert_eq!(keyboard[0x4], 1);
+        keyboard.next_frame();
+        keyboard.update([0; 16]);
+        assert_eq!(keyboard[0x4], 0);
+        assert_eq!(keyboard.iter().sum::<u8>(), 0);
+    }
+
+    #[test]
+    fn keyboard_press_and_release()
+    {
+        let mut keyboard = Keyboard::new();
+        keyboard.press(0x4).unwrap();
+        keyboard.press(0xF).unwrap();
+        let mut expected = [0; 16];
+        expected[0x4] = 1;
+        expected[0xF] = 1;
+        assert_eq!(*keyboard, expected);
+
+        keyboard.release(0x4).unwrap();
+        expected[0x4] = 0;
+        assert_eq!(*keyboard, expected);
+        assert_eq!(keyboard.physical(), expected);
+        assert_eq!(keyboard.just_released().collect::<Vec<_>>(), [0x4]);
+
+        keyboard.set_key(0x0, true).unwrap();
+        assert_eq!(keyboard[0x0], 1);
+
+        assert!(keyboard.press(0x10).is_err());
+        assert!(keyboard.release(0xFF).is_err());
+        assert_eq!(keyboard.iter().sum::<u8>(), 2);
+    }
+
+    #[test]
+    fn keyboard_just_released()
+    {
+        let mut keyboard = Keyboard::new();
+        let mut pressed = [0; 16];
+        assert_eq!(keyboard.just_released().next(), None);
+
+        pressed[0x4] = 1;
+        pressed[0xB] = 1;
+        keyboard.update(pressed);
+        assert_eq!(keyboard.just_released().next(), None);
+
+        pressed[0x4] = 0;
+        keyboard.update(pressed);
+        assert_eq!(keyboard.just_released().collect::<Vec<_>>(), [0x4]);
+
+        // only reported by the update releasing the key
+        keyboard.update(pressed);
+        assert_eq!(keyboard.just_released().next(), None);
+        keyboard.update([0; 16]);
+        assert_eq!(keyboard.just_released().collect::<Vec<_>>(), [0xB]);
+    }
+
+    #[test]
+    fn keyboard_just_pressed()
+    {
+        let mut keyboard = Keyboard::new();
+        let frame = |keys: &[usize]| {
+            let mut pressed = [0; 16];
+            for &key in keys {
+                pressed[key] = 1;
+            }
+            pressed
+        };
+        keyboard.update(frame(&[0x1, 0xC]));
+        assert_eq!(keyboard.just_pressed().collect::<Vec<_>>(), [0x1, 0xC]);
+        assert_eq!(keyboard.just_released().next(), None);
+        keyboard.next_frame();
+
+        // 0x1 held, 0xC released, 0x7 pressed
+        keyboard.update(frame(&[0x1, 0x7]));
+        assert_eq!(keyboard.just_pressed().collect::<Vec<_>>(), [0x7]);
+        assert_eq!(keyboard.just_released().collect::<Vec<_>>(), [0xC]);
+        keyboard.next_frame();
+
+        // with a hold, the release is only reported once the hold is over
+        keyboard.hold_frames = 1;
+        keyboard.update(frame(&[0x1]));
+        keyboard.next_frame();
+        keyboard.update(frame(&[]));
+        assert_eq!(keyboard.just_released().next(), None);
+        keyboard.next_frame();
+        keyboard.update(frame(&[]));
+        assert_eq!(keyboard.just_released().collect::<Vec<_>>(), [0x1]);
+        assert_eq!(keyboard.just_pressed().next(), None);
     }
 }
@@ -0,0 +1,241 @@
+//!
+//! Bindings from the SDL keycodes to the chip-8 keys
+//!
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use sdl2::keyboard::Keycode;
+
+// Bindings from the physical keys to the 16 chip-8 keys
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyMap
+{
+    bindings: HashMap<Keycode, u8>,
+}
+
+// Built-in keyboard layouts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout
+{
+    Azerty,
+    Qwerty,
+}
+
+impl FromStr for Layout
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s {
+            "azerty" => Ok(Layout::Azerty),
+            "qwerty" => Ok(Layout::Qwerty),
+            _ => Err(format!("unknown keyboard layout {}", s)),
+        }
+    }
+}
+
+impl Default for KeyMap
+{
+    fn default() -> KeyMap
+    {
+        KeyMap::from_layout(Layout::Azerty)
+    }
+}
+
+impl KeyMap
+{
+    // The 1234/QWER/ASDF/ZXCV block, as placed on each layout
+    pub fn from_layout(layout: Layout) -> KeyMap
+    {
+        let mut keymap = KeyMap { bindings: HashMap::new() };
+        let keys = match layout {
+            Layout::Azerty => [
+                Keycode::Num1, Keycode::Num2, Keycode::Num3, Keycode::Num4,
+                Keycode::A, Keycode::Z, Keycode::E, Keycode::R,
+                Keycode::Q, Keycode::S, Keycode::D, Keycode::F,
+                Keycode::W, Keycode::X, Keycode::C, Keycode::V,
+            ],
+            Layout::Qwerty => [
+                Keycode::Num1, Keycode::Num2, Keycode::Num3, Keycode::Num4,
+                Keycode::Q, Keycode::W, Keycode::E, Keycode::R,
+                Keycode::A, Keycode::S, Keycode::D, Keycode::F,
+                Keycode::Z, Keycode::X, Keycode::C, Keycode::V,
+            ],
+        };
+        let nibbles = [
+            0x1, 0x2, 0x3, 0xC,
+            0x4, 0x5, 0x6, 0xD,
+            0x7, 0x8, 0x9, 0xE,
+            0xA, 0x0, 0xB, 0xF,
+        ];
+        for (&key, &nibble) in keys.iter().zip(nibbles.iter()) {
+            keymap.bind(key, nibble);
+        }
+        keymap
+    }
+
+    // Apply a keymap config made of `<hex key> = <key name>` lines on top of the current bindings,
+    // e.g. `4 = Q`, blank lines and lines starting with # are ignored
+    pub fn apply(&mut self, config: &str) -> Result<(), String>
+    {
+        for (number, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=').map(str::trim);
+            let (nibble, name) = match (parts.next(), parts.next()) {
+                (Some(nibble), Some(name)) => (nibble, name),
+                _ => return Err(format!("line {}: expected `<hex key> = <key name>`", number + 1)),
+            };
+            let nibble = match u8::from_str_radix(nibble, 16) {
+                Ok(nibble) if nibble < 16 => nibble,
+                _ => return Err(format!("line {}: invalid chip-8 key {}", number + 1, nibble)),
+            };
+            let key = match key_from_name(name) {
+                Some(key) => key,
+                None => return Err(format!("line {}: unknown key name {}", number + 1, name)),
+            };
+            self.bind(key, nibble);
+        }
+        let missing = self.missing();
+        if !missing.is_empty() {
+            let missing: Vec<String> = missing.iter().map(|nibble| format!("{:X}", nibble)).collect();
+            return Err(format!("no binding for chip-8 keys {}", missing.join(", ")));
+        }
+        Ok(())
+    }
+
+    // Bind a physical key to a chip-8 key, replacing the previous bindings of both
+    pub fn bind(&mut self, key: Keycode, nibble: u8)
+    {
+        self.bindings.retain(|_, bound| *bound != nibble);
+        self.bindings.insert(key, nibble);
+    }
+
+    pub fn get(&self, key: Keycode) -> Option<u8>
+    {
+        self.bindings.get(&key).copied()
+    }
+
+    // Physical key bound to a chip-8 key
+    pub fn key_for(&self, nibble: u8) -> Option<Keycode>
+    {
+        self.bindings.iter().find(|&(_, &bound)| bound == nibble).map(|(&key, _)| key)
+    }
+
+    // Chip-8 keys without any physical key bound to them
+    pub fn missing(&self) -> Vec<u8>
+    {
+        (0..16).filter(|nibble| !self.bindings.values().any(|bound| bound == nibble)).collect()
+    }
+
+    // Chip-8 keys bound to the physical keys pressed, for Keyboard::update
+    pub fn pressed_keys(&self, keys: &[Keycode]) -> [u8; 16]
+    {
+        let mut pressed = [0; 16];
+
+        for &key in keys {
+            if let Some(i) = self.get(key) {
+                pressed[i as usize] = 1;
+            }
+        }
+        pressed
+    }
+}
+
+// Letters and digits are resolved directly from their ASCII keycode,
+// any other name goes through SDL (e.g. "Keypad 1", "Space")
+pub fn key_from_name(name: &str) -> Option<Keycode>
+{
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphanumeric() => Keycode::from_i32(c.to_ascii_lowercase() as i32),
+        _ => Keycode::from_name(name),
+    }
+}
+
+// Name of a key as key_from_name reads it, letters and digits again without going through SDL
+pub fn key_name(key: Keycode) -> String
+{
+    match std::char::from_u32(key as i32 as u32) {
+        Some(c) if c.is_ascii_alphanumeric() => c.to_ascii_uppercase().to_string(),
+        _ => key.name(),
+    }
+}
+
+// Parse a keymap config overriding the default layout
+impl FromStr for KeyMap
+{
+    type Err = String;
+
+    fn from_str(config: &str) -> Result<Self, Self::Err>
+    {
+        let mut keymap = KeyMap::default();
+        keymap.apply(config)?;
+        Ok(keymap)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn keymap_layouts()
+    {
+        let keys = [Keycode::Num1, Keycode::Q, Keycode::W, Keycode::A, Keycode::Z, Keycode::V];
+
+        let azerty = KeyMap::from_layout(Layout::Azerty).pressed_keys(&keys);
+        let mut expected = [0; 16];
+        for &i in [0x1, 0x7, 0xA, 0x4, 0x5, 0xF].iter() {
+            expected[i] = 1;
+        }
+        assert_eq!(azerty, expected);
+
+        let qwerty = KeyMap::from_layout(Layout::Qwerty).pressed_keys(&keys);
+        let mut expected = [0; 16];
+        for &i in [0x1, 0x4, 0x5, 0x7, 0xA, 0xF].iter() {
+            expected[i] = 1;
+        }
+        assert_eq!(qwerty, expected);
+
+        let qwerty = KeyMap::from_layout(Layout::Qwerty);
+        assert_eq!(qwerty.key_for(0x4), Some(Keycode::Q));
+        assert_eq!(qwerty.key_for(0x0), Some(Keycode::X));
+        assert_eq!(KeyMap::from_layout(Layout::Azerty).key_for(0x4), Some(Keycode::A));
+        assert_eq!(key_name(Keycode::Q), "Q");
+        assert_eq!(key_name(Keycode::Num7), "7");
+
+        assert_eq!("qwerty".parse::<Layout>(), Ok(Layout::Qwerty));
+        assert!("dvorak".parse::<Layout>().is_err());
+    }
+
+    #[test]
+    fn keymap_overrides_default_layout()
+    {
+        let keymap: KeyMap = "# QWERTY\n4 = Q\n5 = W\n7 = A\nA = Z\n".parse().unwrap();
+        assert_eq!(keymap.get(Keycode::Q), Some(0x4));
+        assert_eq!(keymap.get(Keycode::W), Some(0x5));
+        assert_eq!(keymap.get(Keycode::A), Some(0x7));
+        assert_eq!(keymap.get(Keycode::Z), Some(0xA));
+        assert_eq!(keymap.get(Keycode::X), Some(0x0));
+        assert_eq!(keymap.get(Keycode::Escape), None);
+        assert!(keymap.missing().is_empty());
+    }
+
+    #[test]
+    fn keymap_reports_missing_keys()
+    {
+        assert!(KeyMap::default().missing().is_empty());
+        // Q was bound to 7 and W to A
+        let err = "4 = Q\n5 = W".parse::<KeyMap>().unwrap_err();
+        assert_eq!(err, "no binding for chip-8 keys 7, A");
+        assert!("G = Q".parse::<KeyMap>().is_err());
+        assert!("4 = NotAKey".parse::<KeyMap>().is_err());
+        assert!("4 Q".parse::<KeyMap>().is_err());
+    }
+}
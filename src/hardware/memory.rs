@@ -3,18 +3,33 @@
 //!
 
 use std::ops::{Deref, DerefMut};
-use std::ops::{Index, IndexMut};
+use std::ops::{Index, IndexMut, Range};
 use std::io;
 use std::io::prelude::*;
 use std::fs::File;
 use std::io::{Error, ErrorKind};
 
+use flate2::read::GzDecoder;
+
 use super::RAM_SIZE;
+use super::MAX_RAM_SIZE;
+
+// First bytes of a gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+#[cfg(test)]
+use super::PROGRAM_START_ADDRESS;
 
 use super::DISPLAY_HEIGHT;
 use super::DISPLAY_WIDTH;
+use super::HIRES_DISPLAY_HEIGHT;
+use super::HIRES_DISPLAY_WIDTH;
 
-const SPRITES: [u8; 80] = [
+// Addresses the fonts are loaded at, the big one right after the small one
+pub const SMALL_FONT_ADDRESS: usize = 0x000;
+pub const BIG_FONT_ADDRESS: usize = SMALL_FONT_ADDRESS + SPRITES.len();
+
+// Hexadecimal digits font, 4x5 pixels, loaded at the start of the RAM
+pub const SPRITES: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
@@ -33,9 +48,28 @@ const SPRITES: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+// SUPER-CHIP 8x10 pixels decimal digits font, addressed by Fx30
+pub const BIG_SPRITES: [u8; 100] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+];
+
+#[derive(Clone)]
 pub struct Display
 {
-    display: [u8; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+    display: Vec<u8>,
+    width: usize,
+    height: usize,
+    // with double buffering, the frame last presented to the frontends
+    front: Option<Box<Display>>,
 }
 
 impl Index<[usize; 2]> for Display
@@ -44,7 +78,8 @@ impl Index<[usize; 2]> for Display
 
     fn index(&self, index: [usize; 2]) -> &Self::Output
     {
-        &self.display[index[1] * DISPLAY_WIDTH + index[0]]
+        let offset = self.checked_offset(index[0], index[1]);
+        &self.display[offset]
     }
 }
 
@@ -52,7 +87,16 @@ impl IndexMut<[usize; 2]> for Display
 {
     fn index_mut(&mut self, index: [usize; 2]) -> &mut Self::Output
     {
-        &mut self.display[index[1] * DISPLAY_WIDTH + index[0]]
+        let offset = self.checked_offset(index[0], index[1]);
+        &mut self.display[offset]
+    }
+}
+
+impl Default for Display
+{
+    fn default() -> Display
+    {
+        Display::new()
     }
 }
 
@@ -60,29 +104,263 @@ impl Display
 {
     pub fn get_sizes(&self) -> (usize, usize)
     {
-        (DISPLAY_WIDTH, DISPLAY_HEIGHT)
+        (self.width, self.height)
+    }
+
+    // Position of a pixel in the buffer, None outside of the current resolution
+    fn offset(&self, x: usize, y: usize) -> Option<usize>
+    {
+        if x < self.width && y < self.height {
+            Some(y * self.width + x)
+        } else {
+            None
+        }
+    }
+
+    fn checked_offset(&self, x: usize, y: usize) -> usize
+    {
+        match self.offset(x, y) {
+            Some(offset) => offset,
+            None => panic!("pixel ({}, {}) is outside of the {}x{} display", x, y, self.width, self.height),
+        }
+    }
+
+    // Pixel at x, y, None outside of the current resolution
+    pub fn get(&self, x: usize, y: usize) -> Option<u8>
+    {
+        self.offset(x, y).map(|offset| self.display[offset])
+    }
+
+    // Set the pixel at x, y, returns false and changes nothing outside of the current resolution
+    pub fn set(&mut self, x: usize, y: usize, value: u8) -> bool
+    {
+        match self.offset(x, y) {
+            Some(offset) => {
+                self.display[offset] = value;
+                true
+            },
+            None => false,
+        }
     }
 
     pub fn new() -> Display
     {
-        Display { display: [0; DISPLAY_WIDTH * DISPLAY_HEIGHT] }
+        Display {
+            display: vec![0; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            width: DISPLAY_WIDTH,
+            height: DISPLAY_HEIGHT,
+            front: None,
+        }
+    }
+
+    // With double buffering the instructions draw on a back buffer, shown once presented,
+    // so that frontends never see a frame drawn halfway. Off, the drawing shows immediately.
+    pub fn set_double_buffered(&mut self, double_buffered: bool)
+    {
+        self.front = None;
+        if double_buffered {
+            self.front = Some(Box::new(self.clone()));
+        }
+    }
+
+    pub fn is_double_buffered(&self) -> bool
+    {
+        self.front.is_some()
+    }
+
+    // Show the drawing done since the last call, at the render rate
+    pub fn present(&mut self)
+    {
+        if let Some(front) = self.front.as_mut() {
+            front.display.clone_from(&self.display);
+            front.width = self.width;
+            front.height = self.height;
+        }
+    }
+
+    // The display frontends render: the presented frame with double buffering, the display itself otherwise
+    pub fn front(&self) -> &Display
+    {
+        self.front.as_deref().unwrap_or(self)
     }
 
     pub fn clear(&mut self)
     {
-        self.display = [0; DISPLAY_WIDTH * DISPLAY_HEIGHT];
+        self.display = vec![0; self.width * self.height];
+    }
+
+    // Clear the given XO-CHIP planes only, bit 0 of a pixel being plane 1 and bit 1 plane 2
+    pub fn clear_planes(&mut self, planes: u8)
+    {
+        for pixel in self.display.iter_mut() {
+            *pixel &= !planes;
+        }
+    }
+
+    // Move the pixels down n rows of the current resolution, the top rows becoming blank
+    pub fn scroll_down(&mut self, n: usize)
+    {
+        let shift = n.min(self.height) * self.width;
+        let len = self.display.len();
+        self.display.copy_within(0..len - shift, shift);
+        self.display[..shift].fill(0);
+    }
+
+    // Move the pixels 4 columns right, the leftmost columns becoming blank
+    pub fn scroll_right(&mut self)
+    {
+        for row in self.display.chunks_mut(self.width) {
+            let width = row.len();
+            let shift = width.min(4);
+            row.copy_within(0..width - shift, shift);
+            row[..shift].fill(0);
+        }
+    }
+
+    // Move the pixels 4 columns left, the rightmost columns becoming blank
+    pub fn scroll_left(&mut self)
+    {
+        for row in self.display.chunks_mut(self.width) {
+            let width = row.len();
+            let shift = width.min(4);
+            row.copy_within(shift..width, 0);
+            row[width - shift..].fill(0);
+        }
+    }
+
+    // Raw pixels, row by row
+    pub fn pixels(&self) -> &[u8]
+    {
+        &self.display
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [u8]
+    {
+        &mut self.display
+    }
+
+    // Pixels changed since the previous frame as (x, y, value), all of them when the resolution changed
+    pub fn diff(&self, previous: &Display) -> Vec<(usize, usize, u8)>
+    {
+        let resized = self.get_sizes() != previous.get_sizes();
+        self.display.iter().enumerate()
+            .filter(|&(offset, &pixel)| resized || previous.display[offset] != pixel)
+            .map(|(offset, &pixel)| (offset % self.width, offset / self.width, pixel))
+            .collect()
+    }
+
+    // Switch between low (64x32) and SUPER-CHIP high (128x64) resolution, clearing the display
+    pub fn set_resolution(&mut self, high: bool)
+    {
+        let (width, height) = match high {
+            true => (HIRES_DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT),
+            false => (DISPLAY_WIDTH, DISPLAY_HEIGHT),
+        };
+        self.resize(width, height).unwrap();
+    }
+
+    // Change the resolution to any size, clearing the display
+    pub fn resize(&mut self, width: usize, height: usize) -> Result<(), String>
+    {
+        if width == 0 || height == 0 {
+            return Err(format!("invalid display size {}x{}, it cannot be empty", width, height));
+        }
+        self.width = width;
+        self.height = height;
+        self.clear();
+        Ok(())
+    }
+
+    // Draw a border, a top-left to bottom-right diagonal and a cross at the center,
+    // handy to eyeball scaling, palette or scrolling issues without a ROM.
+    // The cross is clipped on displays smaller than its 5 pixels.
+    pub fn fill_test_pattern(&mut self)
+    {
+        let (width, height) = self.get_sizes();
+        self.clear();
+        for x in 0..width {
+            self[[x, 0]] = 1;
+            self[[x, height - 1]] = 1;
+            self[[x, x * height / width]] = 1;
+        }
+        for y in 0..height {
+            self[[0, y]] = 1;
+            self[[width - 1, y]] = 1;
+        }
+        let (center_x, center_y) = (width / 2, height / 2);
+        for x in center_x.saturating_sub(2)..(center_x + 3).min(width) {
+            self[[x, center_y]] = 1;
+        }
+        for y in center_y.saturating_sub(2)..(center_y + 3).min(height) {
+            self[[center_x, y]] = 1;
+        }
+    }
+
+    // One line per row, '#' for a lit pixel and ' ' otherwise
+    pub fn to_ascii(&self) -> String
+    {
+        self.to_text('#', ' ')
+    }
+
+    // One line per row of the current resolution, with the given characters for lit and unlit pixels
+    pub fn to_text(&self, lit: char, unlit: char) -> String
+    {
+        let (width, height) = self.get_sizes();
+        let mut text = String::with_capacity((width + 1) * height);
+        for row in self.display.chunks(width) {
+            text.extend(row.iter().map(|&pixel| if pixel != 0 { lit } else { unlit }));
+            text.push('\n');
+        }
+        text
+    }
+
+    // One line per two rows, packed in half block characters: the upper half
+    // block is the even row, the lower half block the odd one
+    pub fn to_half_blocks(&self) -> String
+    {
+        let (width, height) = self.get_sizes();
+        let mut text = String::with_capacity((width * 3 + 1) * (height + 1) / 2);
+        for y in (0..height).step_by(2) {
+            for x in 0..width {
+                let top = self.display[y * width + x] != 0;
+                let bottom = y + 1 < height && self.display[(y + 1) * width + x] != 0;
+                text.push(match (top, bottom) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    // Raw RGBA pixels of the current resolution, row by row, lit pixels in fg over bg
+    pub fn to_rgba(&self, fg: (u8, u8, u8), bg: (u8, u8, u8)) -> Vec<u8>
+    {
+        let mut rgba = Vec::with_capacity(self.display.len() * 4);
+        for &pixel in self.display.iter() {
+            let (r, g, b) = if pixel != 0 { fg } else { bg };
+            rgba.extend_from_slice(&[r, g, b, 255]);
+        }
+        rgba
     }
 }
 
 pub struct Memory
 {
-    pub memory: [u8; RAM_SIZE],
+    pub memory: Vec<u8>,
     pub display: Display,
+    // region the program cannot write to, off by default, e.g. the font to catch a runaway Fx55
+    pub protected: Option<Range<usize>>,
+    // byte the RAM outside of the font is filled with before loading a ROM
+    fill: u8,
 }
 
 impl Deref for Memory
 {
-    type Target = [u8; RAM_SIZE];
+    type Target = [u8];
 
     fn deref(&self) -> &Self::Target
     {
@@ -98,35 +376,167 @@ impl DerefMut for Memory
     }
 }
 
+impl Default for Memory
+{
+    fn default() -> Memory
+    {
+        Memory::new()
+    }
+}
+
 impl Memory
 {
     pub fn new() -> Memory
+    {
+        Memory::new_with_fill(0)
+    }
+
+    // RAM filled with a byte instead of zeros, making the reads of uninitialized memory stand out
+    pub fn new_with_fill(fill: u8) -> Memory
+    {
+        Memory::with_ram_size_and_fill(RAM_SIZE, fill)
+    }
+
+    // RAM of size bytes instead of 4K, up to the 64K of XO-CHIP
+    pub fn with_ram_size(size: usize) -> Memory
+    {
+        Memory::with_ram_size_and_fill(size, 0)
+    }
+
+    pub fn with_ram_size_and_fill(size: usize, fill: u8) -> Memory
     {
         let mut memory = Memory {
-            memory: [0; RAM_SIZE],
+            memory: vec![fill; size],
             display: Display::new(),
+            protected: None,
+            fill,
         };
-        for (i, &byte) in SPRITES.iter().enumerate() {
-            memory[i] = byte;
-        }
+        memory[SMALL_FONT_ADDRESS..SMALL_FONT_ADDRESS + SPRITES.len()].copy_from_slice(&SPRITES);
+        memory[BIG_FONT_ADDRESS..BIG_FONT_ADDRESS + BIG_SPRITES.len()].copy_from_slice(&BIG_SPRITES);
         memory
     }
 
-    pub fn load(&mut self, filename: &str) -> Result<(), io::Error>
+    // Format len bytes of RAM from start as lines of 16 bytes in hexadecimal and ASCII,
+    // the region being clamped to the RAM
+    pub fn hexdump(&self, start: usize, len: usize) -> String
+    {
+        let start = start.min(self.memory.len());
+        let end = start.saturating_add(len).min(self.memory.len());
+        let mut dump = String::new();
+        for (line, bytes) in self.memory[start..end].chunks(16).enumerate() {
+            let mut hex = String::new();
+            for index in 0..16 {
+                if index == 8 {
+                    hex.push(' ');
+                }
+                match bytes.get(index) {
+                    Some(byte) => hex.push_str(&format!(" {:02X}", byte)),
+                    None => hex.push_str("   "),
+                }
+            }
+            let ascii: String = bytes.iter()
+                .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+                .collect();
+            dump.push_str(&format!("{:04X} {}  |{}|\n", start + line * 16, hex, ascii));
+        }
+        dump
+    }
+
+    // Write a byte for the program, refused when the address is write protected
+    pub fn write(&mut self, address: usize, value: u8) -> Result<(), String>
+    {
+        if let Some(protected) = &self.protected {
+            if protected.contains(&address) {
+                return Err(format!("write of {:#04X} to protected address {:#05X}", value, address));
+            }
+        }
+        self.memory[address] = value;
+        Ok(())
+    }
+
+    // Clear the RAM and the display and reload the sprites, the write protection, RAM size and fill byte are kept
+    fn reset(&mut self)
+    {
+        let protected = self.protected.take();
+        let double_buffered = self.display.is_double_buffered();
+        *self = Memory::with_ram_size_and_fill(self.memory.len(), self.fill);
+        self.protected = protected;
+        self.display.set_double_buffered(double_buffered);
+    }
+
+    // Clear the RAM and the display, then reload the sprites and the ROM
+    pub fn reset_and_reload(&mut self, filename: &str, start_address: usize) -> Result<(), io::Error>
+    {
+        self.reset();
+        self.load(filename, start_address)
+    }
+
+    pub fn reset_and_reload_bytes(&mut self, data: &[u8], start_address: usize) -> Result<(), io::Error>
+    {
+        self.reset();
+        self.load_bytes(data, start_address)
+    }
+
+    pub fn load(&mut self, filename: &str, start_address: usize) -> Result<(), io::Error>
+    {
+        self.load_from_reader(File::open(filename)?, start_address)
+    }
+
+    // Read a ROM to its end from any source, e.g. a decompressor, and copy it at start_address.
+    // Gzipped ROMs are decompressed first.
+    pub fn load_from_reader<R: Read>(&mut self, mut reader: R, start_address: usize) -> Result<(), io::Error>
     {
-        let mut f = File::open(filename)?;
         let mut buffer = Vec::new();
-        f.read_to_end(&mut buffer)?;
+        reader.read_to_end(&mut buffer)?;
+        self.load_bytes(&unpack_rom(buffer)?, start_address)
+    }
+
+    // Write opcodes in big endian from start, e.g. to lay down a program in tests
+    pub fn write_opcodes(&mut self, start: usize, opcodes: &[u16]) -> Result<(), io::Error>
+    {
+        let len_memory = self.memory.len();
+        if start > len_memory || opcodes.len() * 2 > len_memory - start {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Program size is too big: < {}", len_memory.saturating_sub(start))));
+        }
+        for (index, opcode) in opcodes.iter().enumerate() {
+            self.memory[start + index * 2..start + index * 2 + 2].copy_from_slice(&opcode.to_be_bytes());
+        }
+        Ok(())
+    }
+
+    // Copy a ROM at start_address, it must hold at least one opcode
+    pub fn load_bytes(&mut self, data: &[u8], start_address: usize) -> Result<(), io::Error>
+    {
         let len_memory = self.memory.len();
-        let len_buffer = buffer.len();
-        if len_buffer > len_memory - 0x200 {
-            return Err(Error::new(ErrorKind::Other, format!("ROM size is too big: < {}", len_memory - 0x200)));
+        let len_buffer = data.len();
+        if len_buffer < 2 {
+            return Err(Error::new(ErrorKind::InvalidData, format!("ROM is too small: {} bytes, an opcode takes 2 bytes", len_buffer)));
+        }
+        if start_address > len_memory || len_buffer > len_memory - start_address {
+            return Err(Error::new(ErrorKind::Other, format!("ROM size is too big: < {}", len_memory.saturating_sub(start_address))));
         }
-        self.memory[0x200..len_buffer + 0x200].copy_from_slice(&buffer);
+        self.memory[start_address..len_buffer + start_address].copy_from_slice(data);
         Ok(())
     }
 }
 
+// Decompress a ROM file content if it is gzipped, returning it unchanged otherwise.
+// Decompression stops past MAX_RAM_SIZE bytes, so that a gzip bomb cannot fill the memory.
+pub fn unpack_rom(data: Vec<u8>) -> Result<Vec<u8>, io::Error>
+{
+    if !data.starts_with(&GZIP_MAGIC) {
+        return Ok(data);
+    }
+    let mut buffer = Vec::new();
+    GzDecoder::new(&data[..]).take((MAX_RAM_SIZE + 1) as u64).read_to_end(&mut buffer)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("cannot decompress the gzipped ROM: {}", e)))?;
+    if buffer.len() > MAX_RAM_SIZE {
+        return Err(Error::new(ErrorKind::InvalidData,
+            format!("the gzipped ROM decompresses to more than {} bytes", MAX_RAM_SIZE)));
+    }
+    Ok(buffer)
+}
+
 #[cfg(test)]
 mod tests
 {
@@ -147,6 +557,326 @@ mod tests
         assert_eq!(display.display[4 * DISPLAY_WIDTH + 4], 1);
     }
 
+    #[test]
+    fn display_scroll()
+    {
+        let mut display = Display::new();
+        display[[10, 5]] = 1;
+        display.scroll_down(3);
+        assert_eq!(display.get(10, 5), Some(0));
+        assert_eq!(display.get(10, 8), Some(1));
+
+        display.scroll_right();
+        assert_eq!(display.get(14, 8), Some(1));
+        display.scroll_left();
+        display.scroll_left();
+        assert_eq!(display.get(6, 8), Some(1));
+        assert_eq!(display.pixels().iter().filter(|&&pixel| pixel != 0).count(), 1);
+
+        // pixels scrolled past the edges are lost
+        display[[62, 0]] = 1;
+        display.scroll_right();
+        assert_eq!(display.get(2, 1), Some(0));
+        assert_eq!(display.pixels().iter().filter(|&&pixel| pixel != 0).count(), 1);
+        display.scroll_down(40);
+        assert!(display.pixels().iter().all(|&pixel| pixel == 0));
+
+        // in the high resolution
+        display.set_resolution(true);
+        display[[100, 60]] = 1;
+        display.scroll_down(2);
+        assert_eq!(display.get(100, 62), Some(1));
+        display.scroll_right();
+        assert_eq!(display.get(104, 62), Some(1));
+
+        // displays narrower than the scroll are cleared
+        let mut narrow = Display::new();
+        narrow.resize(2, 2).unwrap();
+        narrow[[1, 0]] = 1;
+        narrow.scroll_right();
+        assert!(narrow.pixels().iter().all(|&pixel| pixel == 0));
+        narrow[[0, 1]] = 1;
+        narrow.scroll_left();
+        assert!(narrow.pixels().iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn display_double_buffer()
+    {
+        let mut display = Display::new();
+        display[[1, 1]] = 1;
+        // the display is its own front buffer by default
+        assert_eq!(display.front()[[1, 1]], 1);
+        display.present();
+        assert_eq!(display.front()[[1, 1]], 1);
+
+        display.set_double_buffered(true);
+        assert!(display.is_double_buffered());
+        assert_eq!(display.front()[[1, 1]], 1);
+        display[[1, 1]] = 0;
+        display[[2, 3]] = 1;
+        assert_eq!(display.front()[[1, 1]], 1);
+        assert_eq!(display.front()[[2, 3]], 0);
+        display.present();
+        assert_eq!(display.front()[[1, 1]], 0);
+        assert_eq!(display.front()[[2, 3]], 1);
+
+        // a resolution change shows once presented as well
+        display.set_resolution(true);
+        assert_eq!(display.front().get_sizes(), (64, 32));
+        display.present();
+        assert_eq!(display.front().get_sizes(), (128, 64));
+        assert_eq!(display.front().pixels(), display.pixels());
+
+        display[[5, 5]] = 1;
+        display.set_double_buffered(false);
+        assert_eq!(display.front()[[5, 5]], 1);
+    }
+
+    #[test]
+    fn display_diff()
+    {
+        let mut previous = Display::new();
+        previous[[0, 0]] = 1;
+        previous[[5, 3]] = 1;
+        let mut display = previous.clone();
+        assert_eq!(display.diff(&previous), []);
+
+        display[[5, 3]] = 0;
+        display[[63, 31]] = 1;
+        display[[7, 2]] = 0b10;
+        assert_eq!(display.diff(&previous), [(7, 2, 0b10), (5, 3, 0), (63, 31, 1)]);
+        assert_eq!(previous.diff(&display), [(7, 2, 0), (5, 3, 1), (63, 31, 0)]);
+
+        // a resolution change redraws everything
+        display.set_resolution(true);
+        let diff = display.diff(&previous);
+        assert_eq!(diff.len(), 128 * 64);
+        assert_eq!(diff[128 + 2], (2, 1, 0));
+    }
+
+    #[test]
+    fn display_resize()
+    {
+        let mut display = Display::new();
+        display[[3, 2]] = 1;
+        display.resize(96, 48).unwrap();
+        assert_eq!(display.get_sizes(), (96, 48));
+        assert_eq!(display.pixels().len(), 96 * 48);
+        assert!(display.pixels().iter().all(|&pixel| pixel == 0));
+        display[[95, 1]] = 1;
+        assert_eq!(display.pixels()[96 + 95], 1);
+
+        display.resize(16, 8).unwrap();
+        assert_eq!(display.get_sizes(), (16, 8));
+        assert_eq!(display.pixels(), &[0; 16 * 8][..]);
+        display[[2, 1]] = 1;
+        assert_eq!(display.pixels()[16 + 2], 1);
+        assert_eq!(display.to_ascii().lines().count(), 8);
+        assert_eq!(display.get(16, 0), None);
+
+        assert!(display.resize(0, 8).is_err());
+        assert!(display.resize(16, 0).is_err());
+        assert_eq!(display.get_sizes(), (16, 8));
+        assert_eq!(display[[2, 1]], 1);
+    }
+
+    #[test]
+    fn display_bounds_checked()
+    {
+        let mut display = Display::new();
+        assert!(display.set(63, 31, 1));
+        assert_eq!(display.get(63, 31), Some(1));
+        assert_eq!(display.get(0, 0), Some(0));
+
+        // a column past the width must not alias the next row
+        assert!(!display.set(64, 0, 1));
+        assert_eq!(display.get(0, 1), Some(0));
+        assert_eq!(display.get(64, 0), None);
+        assert_eq!(display.get(0, 32), None);
+        assert!(!display.set(0, 32, 1));
+
+        display.set_resolution(true);
+        assert!(display.set(127, 63, 1));
+        assert_eq!(display.get(127, 63), Some(1));
+        assert_eq!(display.get(128, 0), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "pixel (64, 0) is outside of the 64x32 display")]
+    fn display_index_out_of_range()
+    {
+        let mut display = Display::new();
+        display[[64, 0]] = 1;
+    }
+
+    #[test]
+    fn memory_hexdump()
+    {
+        let mut memory = Memory::new();
+        let dump = memory.hexdump(0x00, 0x50);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "0000  F0 90 90 90 F0 20 60 20  20 70 F0 10 F0 80 F0 F0  |..... `  p......|");
+        assert!(lines[4].starts_with("0040  F0 E0 90 90 90 E0 F0 80  F0 80 F0 F0 80 F0 80 80"));
+
+        // partial line, clamped to the RAM
+        memory[RAM_SIZE - 2] = b'O';
+        memory[RAM_SIZE - 1] = b'K';
+        assert_eq!(memory.hexdump(RAM_SIZE - 3, 0x100),
+            format!("0FFD  00 4F 4B{}  |.OK|\n", " ".repeat(3 * 13 + 1)));
+        assert_eq!(memory.hexdump(RAM_SIZE + 10, 16), "");
+    }
+
+    #[test]
+    fn memory_load_bytes()
+    {
+        let mut memory = Memory::new();
+        let rom = vec![0xAB; RAM_SIZE - 0x200];
+        memory.load_bytes(&rom, PROGRAM_START_ADDRESS).unwrap();
+        assert_eq!(memory[0x1FF], 0);
+        assert_eq!(memory[0x200], 0xAB);
+        assert_eq!(memory[RAM_SIZE - 1], 0xAB);
+
+        let mut memory = Memory::new();
+        assert!(memory.load_bytes(&vec![0xAB; RAM_SIZE - 0x200 + 1], PROGRAM_START_ADDRESS).is_err());
+        assert_eq!(memory[0x200], 0);
+        // shorter than an opcode
+        assert_eq!(memory.load_bytes(&[], PROGRAM_START_ADDRESS).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(memory.load_bytes(&[0x12], PROGRAM_START_ADDRESS).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(memory[0x200], 0);
+        memory.load_bytes(&[0x12, 0x00, 0x60], PROGRAM_START_ADDRESS).unwrap();
+
+        memory.load_bytes(&[0x12, 0x00], PROGRAM_START_ADDRESS).unwrap();
+        memory.display[[1, 1]] = 1;
+        memory.reset_and_reload_bytes(&[0x60, 0x01], PROGRAM_START_ADDRESS).unwrap();
+        assert_eq!(&memory.memory[0x200..0x203], &[0x60, 0x01, 0x00]);
+        assert_eq!(memory.display[[1, 1]], 0);
+
+        // ETI-660 start address
+        let mut memory = Memory::new();
+        memory.load_bytes(&vec![0xAB; RAM_SIZE - 0x600], 0x600).unwrap();
+        assert_eq!(memory[0x5FF], 0);
+        assert_eq!(memory[0x600], 0xAB);
+        assert!(memory.load_bytes(&vec![0xAB; RAM_SIZE - 0x600 + 1], 0x600).is_err());
+        assert!(memory.load_bytes(&[0x12, 0x00], RAM_SIZE + 2).is_err());
+    }
+
+    #[test]
+    fn memory_write_protect()
+    {
+        let mut memory = Memory::new();
+        memory.write(0x10, 0xAA).unwrap();
+        assert_eq!(memory[0x10], 0xAA);
+
+        memory.protected = Some(0x000..0x050);
+        assert_eq!(memory.write(0x04F, 0x12).unwrap_err(), "write of 0x12 to protected address 0x04F");
+        assert_eq!(memory[0x04F], SPRITES[0x04F]);
+        memory.write(0x050, 0x12).unwrap();
+        assert_eq!(memory[0x050], 0x12);
+
+        memory.reset_and_reload_bytes(&[0x60, 0x01], PROGRAM_START_ADDRESS).unwrap();
+        assert_eq!(memory.protected, Some(0x000..0x050));
+        assert!(memory.write(0x000, 0).is_err());
+    }
+
+    #[test]
+    fn memory_fill_byte()
+    {
+        let mut memory = Memory::new_with_fill(0xCC);
+        assert_eq!(&memory[..SPRITES.len()], &SPRITES[..]);
+        assert_eq!(&memory[BIG_FONT_ADDRESS..BIG_FONT_ADDRESS + BIG_SPRITES.len()], &BIG_SPRITES[..]);
+        assert_eq!(memory[BIG_FONT_ADDRESS + BIG_SPRITES.len()], 0xCC);
+        memory.load_bytes(&[0x60, 0x01], PROGRAM_START_ADDRESS).unwrap();
+        assert_eq!(&memory[0x1FF..0x203], &[0xCC, 0x60, 0x01, 0xCC]);
+        assert_eq!(memory[RAM_SIZE - 1], 0xCC);
+
+        memory.reset_and_reload_bytes(&[0x12, 0x00], PROGRAM_START_ADDRESS).unwrap();
+        assert_eq!(&memory[0x200..0x203], &[0x12, 0x00, 0xCC]);
+        assert_eq!(Memory::new()[0x300], 0);
+    }
+
+    #[test]
+    fn memory_ram_size()
+    {
+        let mut memory = Memory::with_ram_size(MAX_RAM_SIZE);
+        assert_eq!(memory.len(), 0x10000);
+        assert_eq!(&memory[..SPRITES.len()], &SPRITES[..]);
+        let rom: Vec<u8> = (0..0x8000).map(|n| n as u8).collect();
+        memory.load_bytes(&rom, PROGRAM_START_ADDRESS).unwrap();
+        assert_eq!(memory[0x81FF], 0xFF);
+        assert_eq!(memory[0xFFFF], 0);
+        memory[0xFFFF] = 0x42;
+        assert!(memory.hexdump(0xFFF0, 0x20).starts_with("FFF0  00 00"));
+
+        memory.reset_and_reload_bytes(&[0x12, 0x00], PROGRAM_START_ADDRESS).unwrap();
+        assert_eq!(memory.len(), 0x10000);
+        assert_eq!(memory[0xFFFF], 0);
+
+        // the same ROM does not fit in 4K
+        assert!(Memory::new().load_bytes(&rom, PROGRAM_START_ADDRESS).is_err());
+    }
+
+    #[test]
+    fn memory_write_opcodes()
+    {
+        let mut memory = Memory::new();
+        memory.write_opcodes(PROGRAM_START_ADDRESS, &[0x6A02, 0xD015, 0x1200]).unwrap();
+        assert_eq!(&memory[0x200..0x207], &[0x6A, 0x02, 0xD0, 0x15, 0x12, 0x00, 0x00]);
+
+        // the last opcode fits exactly, one past the end is refused without writing anything
+        memory.write_opcodes(RAM_SIZE - 2, &[0x1234]).unwrap();
+        assert_eq!(&memory[RAM_SIZE - 2..], &[0x12, 0x34]);
+        assert!(memory.write_opcodes(RAM_SIZE - 2, &[0x5678, 0x5678]).is_err());
+        assert!(memory.write_opcodes(RAM_SIZE + 2, &[]).is_err());
+        assert_eq!(&memory[RAM_SIZE - 2..], &[0x12, 0x34]);
+    }
+
+    #[test]
+    fn memory_load_from_reader()
+    {
+        let mut memory = Memory::new();
+        memory.load_from_reader(io::Cursor::new(vec![0x60, 0x01, 0x12, 0x00]), PROGRAM_START_ADDRESS).unwrap();
+        assert_eq!(&memory.memory[0x200..0x205], &[0x60, 0x01, 0x12, 0x00, 0x00]);
+
+        let mut memory = Memory::new();
+        let oversized = io::Cursor::new(vec![0xAB; RAM_SIZE - 0x200 + 1]);
+        assert_eq!(memory.load_from_reader(oversized, PROGRAM_START_ADDRESS).unwrap_err().kind(), ErrorKind::Other);
+        assert_eq!(memory[0x200], 0);
+    }
+
+    #[test]
+    fn memory_load_gzipped()
+    {
+        let rom: Vec<u8> = (0..200).map(|n| (n * 7) as u8).collect();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&rom).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        assert_eq!(&gzipped[..2], &GZIP_MAGIC);
+
+        let mut raw_memory = Memory::new();
+        raw_memory.load_from_reader(io::Cursor::new(&rom), PROGRAM_START_ADDRESS).unwrap();
+        let mut gzipped_memory = Memory::new();
+        gzipped_memory.load_from_reader(io::Cursor::new(&gzipped), PROGRAM_START_ADDRESS).unwrap();
+        assert_eq!(&raw_memory.memory[..], &gzipped_memory.memory[..]);
+
+        // a corrupted stream is reported instead of loaded as is
+        let truncated = &gzipped[..gzipped.len() / 2];
+        let mut memory = Memory::new();
+        let err = memory.load_from_reader(truncated, PROGRAM_START_ADDRESS).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("gzip"));
+        assert_eq!(memory[0x200], 0);
+
+        // decompression stops past the largest memory
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&vec![0; MAX_RAM_SIZE * 4]).unwrap();
+        let bomb = encoder.finish().unwrap();
+        let err = unpack_rom(bomb).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("more than"));
+    }
+
     #[test]
     fn display_index()
     {
@@ -161,5 +891,149 @@ mod tests
         display.display[4 * DISPLAY_WIDTH + 4] = 1;
         assert_eq!(display[[4,4]], 1);
     }
-}
 
+    #[test]
+    fn display_resolution()
+    {
+        let mut display = Display::new();
+        assert_eq!(display.get_sizes(), (64, 32));
+
+        display[[3, 2]] = 1;
+        display.set_resolution(true);
+        assert_eq!(display.get_sizes(), (128, 64));
+        assert_eq!(display.display.len(), 128 * 64);
+        assert_eq!(display[[3, 2]], 0);
+
+        display[[127, 63]] = 1;
+        assert_eq!(display.display[128 * 64 - 1], 1);
+        display[[100, 1]] = 1;
+        assert_eq!(display.display[128 + 100], 1);
+        display.clear();
+        assert_eq!(display.display.len(), 128 * 64);
+        assert!(display.display.iter().all(|&pixel| pixel == 0));
+
+        display.set_resolution(false);
+        assert_eq!(display.get_sizes(), (64, 32));
+        assert_eq!(display.display.len(), 64 * 32);
+        display.clear();
+        assert_eq!(display.display.len(), 64 * 32);
+    }
+
+    #[test]
+    fn display_test_pattern()
+    {
+        let mut display = Display::new();
+
+        display[[5, 20]] = 1;
+        display.fill_test_pattern();
+        // border
+        assert_eq!(display[[0, 0]], 1);
+        assert_eq!(display[[63, 0]], 1);
+        assert_eq!(display[[0, 31]], 1);
+        assert_eq!(display[[63, 31]], 1);
+        assert_eq!(display[[20, 31]], 1);
+        // diagonal
+        assert_eq!(display[[10, 5]], 1);
+        assert_eq!(display[[40, 20]], 1);
+        // center cross
+        assert_eq!(display[[30, 16]], 1);
+        assert_eq!(display[[34, 16]], 1);
+        assert_eq!(display[[32, 14]], 1);
+        assert_eq!(display[[32, 18]], 1);
+        // blank areas, previous content is gone
+        assert_eq!(display[[5, 20]], 0);
+        assert_eq!(display[[1, 1]], 0);
+        assert_eq!(display[[35, 16]], 0);
+        assert_eq!(display[[32, 19]], 0);
+
+        // the pattern fits on tiny displays
+        display.resize(1, 1).unwrap();
+        display.fill_test_pattern();
+        assert_eq!(display.to_text('#', '.'), "#\n");
+        display.resize(4, 4).unwrap();
+        display.fill_test_pattern();
+        assert_eq!(display.to_text('#', '.'), "####\n####\n####\n####\n");
+    }
+
+    #[test]
+    fn display_to_text()
+    {
+        let mut display = Display::new();
+        display[[0, 0]] = 1;
+        display[[63, 31]] = 1;
+
+        let text = display.to_text('#', '.');
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 32);
+        assert_eq!(lines[0], format!("#{}", ".".repeat(63)));
+        assert_eq!(lines[1], ".".repeat(64));
+        assert_eq!(lines[31], format!("{}#", ".".repeat(63)));
+
+        display.set_resolution(true);
+        assert_eq!(display.to_text('#', '.').lines().count(), 64);
+        assert!(display.to_text('#', '.').lines().all(|line| line.len() == 128));
+    }
+
+    #[test]
+    fn display_to_rgba()
+    {
+        let mut display = Display::new();
+        display[[0, 0]] = 1;
+        display[[2, 1]] = 1;
+        let (fg, bg) = ((255, 205, 230), (74, 74, 74));
+
+        let rgba = display.to_rgba(fg, bg);
+        assert_eq!(rgba.len(), 64 * 32 * 4);
+        assert_eq!(&rgba[0..4], &[255, 205, 230, 255]);
+        assert_eq!(&rgba[4..8], &[74, 74, 74, 255]);
+        let offset = (64 + 2) * 4;
+        assert_eq!(&rgba[offset..offset + 4], &[255, 205, 230, 255]);
+
+        display.set_resolution(true);
+        display[[127, 63]] = 1;
+        let rgba = display.to_rgba(fg, bg);
+        assert_eq!(rgba.len(), 128 * 64 * 4);
+        assert_eq!(&rgba[rgba.len() - 4..], &[255, 205, 230, 255]);
+    }
+
+    #[test]
+    fn display_to_ascii()
+    {
+        let memory = Memory::new();
+        let mut display = Display::new();
+        // digit 2 sprite at (1, 1)
+        for (row, &byte) in memory.memory[2 * 5..3 * 5].iter().enumerate() {
+            for bit in 0..8 {
+                display[[1 + bit, 1 + row]] = (byte >> (7 - bit)) & 1;
+            }
+        }
+
+        let ascii = display.to_ascii();
+        let lines: Vec<&str> = ascii.lines().collect();
+        assert_eq!(lines[0], " ".repeat(64));
+        assert_eq!(lines[1], format!(" ####{}", " ".repeat(59)));
+        assert_eq!(lines[2], format!("    #{}", " ".repeat(59)));
+        assert_eq!(lines[4], format!(" #   {}", " ".repeat(59)));
+    }
+
+    #[test]
+    fn display_to_half_blocks()
+    {
+        let mut display = Display::new();
+        display[[0, 0]] = 1;
+        display[[0, 1]] = 1;
+        display[[1, 0]] = 1;
+        display[[2, 1]] = 1;
+        display[[63, 31]] = 1;
+
+        let text = display.to_half_blocks();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 16);
+        assert_eq!(lines[0], format!("█▀▄{}", " ".repeat(61)));
+        assert_eq!(lines[1], " ".repeat(64));
+        assert_eq!(lines[15], format!("{}▄", " ".repeat(63)));
+
+        display.set_resolution(true);
+        assert_eq!(display.to_half_blocks().lines().count(), 32);
+    }
+}
@@ -1,25 +1,37 @@
-const RAM_SIZE: usize = 4096;
+// default RAM size, the XO-CHIP one being the whole 16 bits address space
+pub const RAM_SIZE: usize = 4096;
+pub const MAX_RAM_SIZE: usize = 0x10000;
+// where ROMs are loaded and executed from, unless configured otherwise
+const PROGRAM_START_ADDRESS: usize = 0x200;
 
-const DISPLAY_HEIGHT: usize = 32;
-const DISPLAY_WIDTH: usize = 64;
-const BG_COLOR: (u8, u8, u8) = (74, 74, 74);
-
-// if GRADIENT_DISPLAY is off
-const PIXEL_COLOR: (u8, u8, u8) = (255, 205, 230);
-
-// if GRADIENT_DISPLAY is on
-const GRADIENT_SATURATION: f32 = 0.2;
-const GRADIENT_VALUE: f32 = 1.0;
+pub const DISPLAY_HEIGHT: usize = 32;
+pub const DISPLAY_WIDTH: usize = 64;
+// SUPER-CHIP high resolution mode
+const HIRES_DISPLAY_HEIGHT: usize = 64;
+const HIRES_DISPLAY_WIDTH: usize = 128;
 
 mod cpu;
 mod memory;
-mod screen;
 mod keyboard;
+#[cfg(feature = "sdl")]
+mod keymap;
 mod audio;
+mod disasm;
+mod snapshot;
+mod autosave;
+mod input_log;
+mod recorder;
+mod emulator;
 
-pub use cpu::Cpu;
-pub use memory::{Memory, Display};
-pub use screen::Screen;
+pub use cpu::{Cpu, CycleOutcome, Quirks, ShiftQuirk, LoadStoreQuirk, JumpQuirk, WrapQuirk, UnknownOpcode, UnknownOpcodePolicy, StackError};
+pub use memory::{Memory, Display, SPRITES, BIG_SPRITES, SMALL_FONT_ADDRESS, BIG_FONT_ADDRESS, unpack_rom};
+pub use audio::AudioBuffer;
 pub use keyboard::Keyboard;
-pub use audio::Beeper;
-
+#[cfg(feature = "sdl")]
+pub use keymap::{KeyMap, Layout, key_from_name, key_name};
+pub use disasm::{disassemble, listing};
+pub use snapshot::Snapshot;
+pub use autosave::Autosave;
+pub use input_log::InputLog;
+pub use recorder::Recorder;
+pub use emulator::{Emulator, FrameCallback};
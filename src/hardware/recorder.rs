@@ -0,0 +1,250 @@
+//!
+//! GIF recording of the display
+//!
+
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::memory::Display;
+
+// A recorded frame, with the palette of the colors it uses and one index per pixel
+#[derive(Debug, PartialEq)]
+struct RecordedFrame
+{
+    width: usize,
+    height: usize,
+    palette: Vec<u8>,
+    indices: Vec<u8>,
+    // number of consecutive rendered frames showing this image
+    repeat: u32,
+}
+
+// Map RGBA pixels to palette indices, the colors past 256 getting the closest palette entry
+fn index_colors(rgba: &[u8]) -> (Vec<u8>, Vec<u8>)
+{
+    let mut palette: Vec<[u8; 3]> = Vec::new();
+    let mut indices = Vec::with_capacity(rgba.len() / 4);
+    for pixel in rgba.chunks(4) {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let index = match palette.iter().position(|&entry| entry == color) {
+            Some(index) => index,
+            None if palette.len() < 256 => {
+                palette.push(color);
+                palette.len() - 1
+            },
+            None => {
+                let distance = |entry: &[u8; 3]| entry.iter().zip(color.iter())
+                    .map(|(&a, &b)| (a as i32 - b as i32).pow(2)).sum::<i32>();
+                (0..palette.len()).min_by_key(|&index| distance(&palette[index])).unwrap()
+            },
+        };
+        indices.push(index as u8);
+    }
+    (palette.concat(), indices)
+}
+
+// Nearest neighbor upscaling of a frame indices by a whole factor
+fn scale_indices(indices: &[u8], width: usize, factor: usize) -> Vec<u8>
+{
+    let mut scaled = Vec::with_capacity(indices.len() * factor * factor);
+    for row in indices.chunks(width) {
+        let mut scaled_row = Vec::with_capacity(row.len() * factor);
+        for &index in row {
+            scaled_row.resize(scaled_row.len() + factor, index);
+        }
+        for _ in 0..factor {
+            scaled.extend_from_slice(&scaled_row);
+        }
+    }
+    scaled
+}
+
+// Buffers the rendered frames in memory until they are saved as an animated GIF.
+// With dedupe, identical consecutive frames are merged into a longer one to keep the file small.
+pub struct Recorder
+{
+    frames: Vec<RecordedFrame>,
+    scale: usize,
+    framerate: f32,
+    dedupe: bool,
+}
+
+impl Recorder
+{
+    // Recorder of frames rendered framerate times per second, enlarged scale times in the GIF,
+    // merging the unchanged frames with dedupe
+    pub fn new(scale: usize, framerate: f32, dedupe: bool) -> Recorder
+    {
+        Recorder {
+            frames: Vec::new(),
+            scale: scale.max(1),
+            framerate,
+            dedupe,
+        }
+    }
+
+    // Append the display as the next rendered frame, in the given colors
+    pub fn push(&mut self, display: &Display, fg: (u8, u8, u8), bg: (u8, u8, u8))
+    {
+        let (width, height) = display.get_sizes();
+        let (palette, indices) = index_colors(&display.to_rgba(fg, bg));
+        let dedupe = self.dedupe;
+        if let Some(last) = self.frames.last_mut().filter(|_| dedupe) {
+            if (last.width, last.height) == (width, height) && last.palette == palette && last.indices == indices {
+                last.repeat += 1;
+                return;
+            }
+        }
+        self.frames.push(RecordedFrame { width, height, palette, indices, repeat: 1 });
+    }
+
+    // Number of frames recorded so far, the merged ones counting once
+    pub fn frame_count(&self) -> usize
+    {
+        self.frames.len()
+    }
+
+    // Encode the recorded frames, the low resolution ones being enlarged to match the high resolution ones
+    fn write<W: Write>(&self, writer: W) -> Result<(), gif::EncodingError>
+    {
+        let width = self.frames.iter().map(|frame| frame.width).max().unwrap_or(0);
+        let height = self.frames.iter().map(|frame| frame.height).max().unwrap_or(0);
+        // GIF sizes are 16-bit, the frames within the screen fit once it does
+        let scaled = |size: usize| size.checked_mul(self.scale).filter(|&scaled| scaled <= u16::MAX as usize);
+        let (screen_width, screen_height) = match (scaled(width), scaled(height)) {
+            (Some(screen_width), Some(screen_height)) => (screen_width as u16, screen_height as u16),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+                "{}x{} frames enlarged {} times exceed the GIF size limit of {}", width, height, self.scale, u16::MAX)).into()),
+        };
+        let mut encoder = gif::Encoder::new(writer, screen_width, screen_height, &[])?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+        // delays are in hundredths of a second, rounded from the start of the recording to avoid drifting
+        let centiseconds = |rendered: u32| (rendered as f32 * 100.0 / self.framerate).round() as u32;
+        let mut rendered = 0;
+        for frame in self.frames.iter() {
+            let factor = self.scale * width / frame.width;
+            let delay = centiseconds(rendered + frame.repeat) - centiseconds(rendered);
+            rendered += frame.repeat;
+            encoder.write_frame(&gif::Frame {
+                width: (frame.width * factor) as u16,
+                height: (frame.height * factor) as u16,
+                delay: delay.max(1).min(u16::MAX as u32) as u16,
+                palette: Some(frame.palette.clone()),
+                buffer: Cow::Owned(scale_indices(&frame.indices, frame.width, factor)),
+                ..gif::Frame::default()
+            })?;
+        }
+        Ok(())
+    }
+
+    // Write the recorded frames to a GIF file
+    pub fn save(&self, path: &Path) -> Result<(), String>
+    {
+        let file = fs::File::create(path).map_err(|e| e.to_string())?;
+        self.write(io::BufWriter::new(file)).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const FG: (u8, u8, u8) = (255, 205, 230);
+    const BG: (u8, u8, u8) = (74, 74, 74);
+
+    #[test]
+    fn identical_frames_are_merged()
+    {
+        let mut recorder = Recorder::new(1, 60.0, true);
+        let mut display = Display::new();
+        assert_eq!(recorder.frame_count(), 0);
+
+        recorder.push(&display, FG, BG);
+        recorder.push(&display, FG, BG);
+        assert_eq!(recorder.frame_count(), 1);
+        assert_eq!(recorder.frames[0].repeat, 2);
+        assert_eq!(recorder.frames[0].palette, vec![74, 74, 74]);
+
+        display[[1, 0]] = 1;
+        recorder.push(&display, FG, BG);
+        assert_eq!(recorder.frame_count(), 2);
+        assert_eq!(recorder.frames[1].palette, vec![74, 74, 74, 255, 205, 230]);
+        assert_eq!(&recorder.frames[1].indices[0..3], &[0, 1, 0]);
+
+        // a color change makes a new frame too
+        recorder.push(&display, (0, 0, 0), BG);
+        assert_eq!(recorder.frame_count(), 3);
+        assert_eq!(recorder.frames.iter().map(|frame| frame.repeat).collect::<Vec<_>>(), [2, 1, 1]);
+    }
+
+    #[test]
+    fn dedupe_records_fewer_frames()
+    {
+        let mut display = Display::new();
+        let mut frames = vec![display.clone(), display.clone()];
+        display[[1, 0]] = 1;
+        frames.push(display.clone());
+        frames.push(display);
+
+        let mut kept = Recorder::new(1, 60.0, false);
+        let mut deduped = Recorder::new(1, 60.0, true);
+        for frame in frames.iter() {
+            kept.push(frame, FG, BG);
+            deduped.push(frame, FG, BG);
+        }
+        assert_eq!(kept.frame_count(), 4);
+        assert_eq!(deduped.frame_count(), 2);
+        // the merged frames last as long as the kept ones
+        assert_eq!(deduped.frames.iter().map(|frame| frame.repeat).sum::<u32>(), 4);
+    }
+
+    #[test]
+    fn scaled_indices()
+    {
+        assert_eq!(scale_indices(&[0, 1, 2, 3], 2, 2), [0, 0, 1, 1, 0, 0, 1, 1, 2, 2, 3, 3, 2, 2, 3, 3]);
+        assert_eq!(scale_indices(&[0, 1], 2, 1), [0, 1]);
+    }
+
+    #[test]
+    fn palette_is_capped_to_256_colors()
+    {
+        let rgba: Vec<u8> = (0..300u32).flat_map(|n| vec![(n % 256) as u8, (n / 256) as u8, 0, 255]).collect();
+        let (palette, indices) = index_colors(&rgba);
+        assert_eq!(palette.len(), 256 * 3);
+        assert_eq!(indices[255], 255);
+        // (0, 1, 0) is closest to (0, 0, 0)
+        assert_eq!(indices[256], 0);
+    }
+
+    #[test]
+    fn write_gif()
+    {
+        let mut recorder = Recorder::new(2, 60.0, true);
+        let mut display = Display::new();
+        recorder.push(&display, FG, BG);
+        display.set_resolution(true);
+        display[[0, 0]] = 1;
+        recorder.push(&display, FG, BG);
+
+        let mut gif = Vec::new();
+        recorder.write(&mut gif).unwrap();
+        assert_eq!(&gif[0..6], b"GIF89a");
+        // logical screen size of the high resolution frames, scaled
+        assert_eq!(&gif[6..10], &[0, 1, 128, 0]);
+        assert_eq!(gif.last(), Some(&0x3B));
+    }
+
+    #[test]
+    fn scaled_size_past_gif_limit()
+    {
+        let mut recorder = Recorder::new(1024, 60.0, true);
+        recorder.push(&Display::new(), FG, BG);
+        let error = recorder.write(&mut Vec::new()).unwrap_err().to_string();
+        assert!(error.contains("64x32 frames enlarged 1024 times"), "{}", error);
+        // without frames the scale does not matter
+        assert!(Recorder::new(usize::MAX, 60.0, true).write(&mut Vec::new()).is_ok());
+    }
+}
@@ -0,0 +1,173 @@
+//!
+//! Save states
+//!
+
+use std::io;
+use std::io::{Error, ErrorKind};
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"FNC1";
+
+// Full emulator state, as captured by Cpu::snapshot
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot
+{
+    pub(super) v_registers: [u8; 16],
+    pub(super) i_register: u16,
+    pub(super) delay_timer_register: u8,
+    pub(super) sound_timer_register: u8,
+    pub(super) pc: u16,
+    pub(super) stack: [u16; 16],
+    pub(super) stack_pointer: u8,
+    pub(super) opcode: u16,
+    pub(super) waiting_for_input: bool,
+    pub(super) input_register: u8,
+    pub(super) selected_planes: u8,
+    pub(super) audio_pattern: Option<[u8; 16]>,
+    pub(super) pitch: u8,
+    pub(super) memory: Vec<u8>,
+    pub(super) display_sizes: (usize, usize),
+    pub(super) display: Vec<u8>,
+}
+
+// Sequential reader over a serialized snapshot or input log
+pub(super) struct Reader<'a>
+{
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a>
+{
+    pub(super) fn new(bytes: &'a [u8]) -> Reader<'a>
+    {
+        Reader { bytes }
+    }
+
+    pub(super) fn is_empty(&self) -> bool
+    {
+        self.bytes.is_empty()
+    }
+
+    pub(super) fn take(&mut self, len: usize) -> Result<&'a [u8], io::Error>
+    {
+        if self.bytes.len() < len {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "file is truncated"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub(super) fn u8(&mut self) -> Result<u8, io::Error>
+    {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(super) fn u16(&mut self) -> Result<u16, io::Error>
+    {
+        let bytes = self.take(2)?;
+        Ok((bytes[0] as u16) << 8 | bytes[1] as u16)
+    }
+
+    pub(super) fn u32(&mut self) -> Result<u32, io::Error>
+    {
+        Ok((self.u16()? as u32) << 16 | self.u16()? as u32)
+    }
+}
+
+impl Snapshot
+{
+    pub fn to_bytes(&self) -> Vec<u8>
+    {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&self.v_registers);
+        bytes.extend_from_slice(&self.i_register.to_be_bytes());
+        bytes.push(self.delay_timer_register);
+        bytes.push(self.sound_timer_register);
+        bytes.extend_from_slice(&self.pc.to_be_bytes());
+        for address in self.stack.iter() {
+            bytes.extend_from_slice(&address.to_be_bytes());
+        }
+        bytes.push(self.stack_pointer);
+        bytes.extend_from_slice(&self.opcode.to_be_bytes());
+        bytes.push(self.waiting_for_input as u8);
+        bytes.push(self.input_register);
+        bytes.push(self.selected_planes);
+        bytes.push(self.audio_pattern.is_some() as u8);
+        bytes.extend_from_slice(&self.audio_pattern.unwrap_or([0; 16]));
+        bytes.push(self.pitch);
+        bytes.extend_from_slice(&(self.memory.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.memory);
+        bytes.extend_from_slice(&(self.display_sizes.0 as u16).to_be_bytes());
+        bytes.extend_from_slice(&(self.display_sizes.1 as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.display);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, io::Error>
+    {
+        let mut reader = Reader::new(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a save state file"));
+        }
+        let mut v_registers = [0; 16];
+        v_registers.copy_from_slice(reader.take(16)?);
+        let i_register = reader.u16()?;
+        let delay_timer_register = reader.u8()?;
+        let sound_timer_register = reader.u8()?;
+        let pc = reader.u16()?;
+        let mut stack = [0; 16];
+        for address in stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        let stack_pointer = reader.u8()?;
+        let opcode = reader.u16()?;
+        let waiting_for_input = reader.u8()? != 0;
+        let input_register = reader.u8()?;
+        let selected_planes = reader.u8()?;
+        let has_audio_pattern = reader.u8()? != 0;
+        let mut audio_pattern = [0; 16];
+        audio_pattern.copy_from_slice(reader.take(16)?);
+        let audio_pattern = if has_audio_pattern { Some(audio_pattern) } else { None };
+        let pitch = reader.u8()?;
+        if stack_pointer as usize > stack.len() || input_register > 0x0F || selected_planes > 0x03 {
+            return Err(Error::new(ErrorKind::InvalidData, "save state holds invalid cpu registers"));
+        }
+        let memory_len = reader.u32()? as usize;
+        let memory = reader.take(memory_len)?.to_vec();
+        let display_sizes = (reader.u16()? as usize, reader.u16()? as usize);
+        let display = reader.take(display_sizes.0 * display_sizes.1)?.to_vec();
+        if !reader.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "save state has trailing data"));
+        }
+        Ok(Snapshot {
+            v_registers,
+            i_register,
+            delay_timer_register,
+            sound_timer_register,
+            pc,
+            stack,
+            stack_pointer,
+            opcode,
+            waiting_for_input,
+            input_register,
+            selected_planes,
+            audio_pattern,
+            pitch,
+            memory,
+            display_sizes,
+            display,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), io::Error>
+    {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Snapshot, io::Error>
+    {
+        Snapshot::from_bytes(&fs::read(path)?)
+    }
+}
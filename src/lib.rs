@@ -0,0 +1,43 @@
+//!
+//! Chip-8, SUPER-CHIP and XO-CHIP emulator core: cpu, memory, display and keyboard.
+//! The fish_n_chip binary drives it with an SDL window, other frontends can do the same.
+//!
+
+mod hardware;
+
+pub use hardware::{
+    Cpu,
+    CycleOutcome,
+    Quirks,
+    ShiftQuirk,
+    LoadStoreQuirk,
+    JumpQuirk,
+    WrapQuirk,
+    UnknownOpcode,
+    UnknownOpcodePolicy,
+    StackError,
+    Memory,
+    Display,
+    SPRITES,
+    BIG_SPRITES,
+    SMALL_FONT_ADDRESS,
+    BIG_FONT_ADDRESS,
+    RAM_SIZE,
+    MAX_RAM_SIZE,
+    DISPLAY_WIDTH,
+    DISPLAY_HEIGHT,
+    unpack_rom,
+    AudioBuffer,
+    Keyboard,
+    Snapshot,
+    Autosave,
+    InputLog,
+    Recorder,
+    Emulator,
+    FrameCallback,
+    disassemble,
+    listing,
+};
+// keymaps bind SDL keycodes to the chip-8 keys
+#[cfg(feature = "sdl")]
+pub use hardware::{KeyMap, Layout, key_from_name, key_name};
@@ -29,66 +29,367 @@ const VERSION: &str = "0.1.0";
 
 const WINDOW_TITLE: &str = "fish n chips";
 
-mod hardware;
+// ROM path reading the ROM from the standard input
+const STDIN_ROM: &str = "-";
 
-use std::{thread, time};
-use clap::{Arg, App};
+// differing rows printed by --verify, the count of the others following
+const MAX_DIFF_ROWS: usize = 8;
+
+// frequency ratio applied to the beep by the PageUp/PageDown hotkeys
+#[cfg(feature = "sdl")]
+const SEMITONE: f32 = 1.059_463;
+
+mod frontend;
+mod timing;
+
+#[cfg(not(any(feature = "sdl", all(feature = "tui", unix))))]
+compile_error!("the emulator needs a frontend, enable the sdl feature, or the tui feature on unix");
+
+use std::{fs, io, time};
+use std::io::Read;
+use clap::{Arg, ArgGroup, App};
+#[cfg(feature = "sdl")]
+use std::thread;
+#[cfg(feature = "sdl")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "sdl")]
+use clap::ArgMatches;
+#[cfg(feature = "sdl")]
 use sdl2::{Sdl, EventPump, AudioSubsystem};
+#[cfg(feature = "sdl")]
 use sdl2::event::Event;
+#[cfg(feature = "sdl")]
 use sdl2::keyboard::Keycode;
+#[cfg(feature = "sdl")]
 use sdl2::pixels::Color;
+#[cfg(feature = "sdl")]
 use sdl2::render::{WindowCanvas};
 
-use hardware::{
+#[cfg(feature = "sdl")]
+use timing::{CycleBudget, PerfCounter, Ticker, TIMER_FREQUENCY, cycle_budget, next_deadline};
+
+use fish_n_chip::{
     Cpu,
+    CycleOutcome,
+    Quirks,
+    ShiftQuirk,
+    LoadStoreQuirk,
+    JumpQuirk,
+    WrapQuirk,
+    UnknownOpcodePolicy,
     Memory,
     Display,
-    Screen,
+    unpack_rom,
     Keyboard,
+    disassemble,
+    listing,
+    BIG_FONT_ADDRESS,
+    BIG_SPRITES,
+    RAM_SIZE,
+    MAX_RAM_SIZE,
+};
+#[cfg(feature = "sdl")]
+use fish_n_chip::{KeyMap, Layout, key_from_name, Snapshot, Autosave, InputLog, Recorder};
+#[cfg(feature = "sdl")]
+use frontend::{
+    Screen,
+    GamepadInput,
+    GamepadMap,
     Beeper,
+    DebugOverlay,
+    HelpOverlay,
+    parse_color,
+    PIXEL_COLOR,
+    BG_COLOR,
 };
 
-fn init_sdl_window() -> (Sdl, WindowCanvas, AudioSubsystem)
+// Audio subsystem, None when the audio is turned off
+#[cfg(feature = "sdl")]
+type Audio = Option<Result<AudioSubsystem, String>>;
+
+// Open the window, the audio subsystem failing on its own so that the emulator can run without sound.
+// Without audio, the audio subsystem is not initialized at all.
+#[cfg(feature = "sdl")]
+fn init_sdl_window(audio: bool) -> Result<(Sdl, WindowCanvas, Audio), String>
 {
-    let sdl_context = sdl2::init().unwrap();
+    let sdl_context = sdl2::init()?;
 
-    let video_subsystem = sdl_context.video().unwrap();
+    let video_subsystem = sdl_context.video()?;
     let window = video_subsystem.window(WINDOW_TITLE, 64 * 20, 32 * 20)
         .position_centered().resizable()
         .build()
-        .unwrap();
-    let mut canvas = window.into_canvas().build().unwrap();
+        .map_err(|e| format!("cannot create the window: {}", e))?;
+    let mut canvas = window.into_canvas().build()
+        .map_err(|e| format!("cannot create the window renderer: {}", e))?;
     canvas.set_draw_color(Color::RGB(255, 255, 255));
     canvas.clear();
     canvas.present();
 
-    let audio_subsystem = sdl_context.audio().unwrap();
+    let audio_subsystem = if audio { Some(sdl_context.audio()) } else { None };
 
-    (sdl_context, canvas, audio_subsystem)
+    Ok((sdl_context, canvas, audio_subsystem))
 }
 
-fn draw_window(canvas: &mut WindowCanvas, screen: &mut Screen, memory_display: &Display)
+// Beeper playing on the audio subsystem, silent when the audio is off or cannot be opened
+#[cfg(feature = "sdl")]
+fn init_beeper(audio_subsystem: Audio, frequency: f32) -> Beeper
 {
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
-    screen.draw(memory_display, canvas);
+    let audio_subsystem = match audio_subsystem {
+        Some(audio_subsystem) => audio_subsystem,
+        None => return Beeper::disabled(frequency),
+    };
+    match audio_subsystem.and_then(|audio| Beeper::new(&audio, frequency)) {
+        Ok(beeper) => beeper,
+        Err(e) => {
+            eprintln!("Warning: cannot open the audio device, sound is disabled: {}", e);
+            Beeper::disabled(frequency)
+        },
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn draw_window(canvas: &mut WindowCanvas, screen: &mut Screen, memory_display: &Display, cpu: &Cpu, overlay: Option<&DebugOverlay>, help: &HelpOverlay, keymap: &KeyMap)
+{
+    screen.draw(memory_display, canvas, cpu.beeping);
+    if let Some(overlay) = overlay {
+        overlay.draw(cpu, canvas);
+    }
+    help.draw(keymap, canvas);
     canvas.present();
 }
 
-fn check_terminate_events(event_pump: &mut EventPump) -> Result<(), ()>
+// Update the keyboard from the physical keys, through the keymap, and the game controller buttons held
+#[cfg(feature = "sdl")]
+fn read_keyboard(keyboard: &mut Keyboard, keymap: &KeyMap, event_pump: &EventPump, gamepad: &GamepadInput)
+{
+    let keys: Vec<Keycode> = event_pump
+        .keyboard_state()
+        .pressed_scancodes()
+        .filter_map(Keycode::from_scancode)
+        .collect();
+    let mut pressed = keymap.pressed_keys(&keys);
+    gamepad.press(&mut pressed);
+    keyboard.update(pressed);
+}
+
+#[cfg(feature = "sdl")]
+fn check_events(event_pump: &mut EventPump) -> Result<Vec<Keycode>, ()>
 {
-    let mut result = Ok(());
+    let mut hotkeys = Vec::new();
     for event in event_pump.poll_iter() {
         match event {
             Event::Quit { .. } |
             Event::KeyDown { keycode: Some(Keycode::Escape), ..  } => {
-                result = Err(());
-                break;
+                return Err(());
+            },
+            Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => {
+                hotkeys.push(keycode);
             },
             _ => {}
         };
     }
-    result
+    Ok(hotkeys)
+}
+
+fn print_stats(cpu: &Cpu, frames: u64, wall_time: time::Duration)
+{
+    let seconds = wall_time.as_secs_f64();
+    let ips = if seconds > 0.0 { cpu.cycles() as f64 / seconds } else { 0.0 };
+    eprintln!("cycles executed:  {}", cpu.cycles());
+    eprintln!("wall time:        {:.2}s", seconds);
+    eprintln!("average IPS:      {:.0}", ips);
+    eprintln!("frames rendered:  {}", frames);
+    eprintln!("peak stack depth: {}", cpu.peak_stack_depth());
+    eprintln!("collisions:       {}", cpu.collision_count());
+}
+
+fn print_histogram(cpu: &Cpu)
+{
+    for (class, count) in cpu.opcode_histogram().iter().enumerate() {
+        eprintln!("{:X}xxx: {}", class, count);
+    }
+}
+
+// Execute a cpu cycle, reporting breakpoints, watchpoints and traced instructions on stderr
+fn run_cycle(cpu: &mut Cpu, memory: &mut Memory, keyboard: &Keyboard) -> CycleOutcome
+{
+    let outcome = cpu.cycle(memory, keyboard);
+    match outcome {
+        CycleOutcome::Breakpoint => eprintln!("breakpoint reached at {:#05X}, cpu paused", cpu.program_counter()),
+        CycleOutcome::Watchpoint { address, old, new } =>
+            eprintln!("watchpoint at {:#05X} changed from {:#04X} to {:#04X}, cpu paused", address, old, new),
+        CycleOutcome::StackError { error, pc } => eprintln!("{} at {:#05X}, cpu paused", error, pc),
+        _ => {},
+    }
+    print_trace(cpu);
+    outcome
+}
+
+// Run the cpu without any window, ticking the timers every cycles_per_timer_tick cycles,
+// until max_cycles or the cpu instruction limit
+fn run_headless(cpu: &mut Cpu, memory: &mut Memory, keyboard: &Keyboard, max_cycles: u64, cycles_per_timer_tick: u64)
+{
+    for cycle in 1..=max_cycles {
+        if run_cycle(cpu, memory, keyboard) == CycleOutcome::LimitReached {
+            break;
+        }
+        if cycle % cycles_per_timer_tick == 0 {
+            let _ = cpu.update_timers();
+        }
+    }
+}
+
+// Run the emulator in the terminal instead of a window, returns the number of frames rendered.
+// The raw terminal input goes through termios, so the terminal frontend is only built on unix.
+#[cfg(all(feature = "tui", unix))]
+fn run_tui(cpu: &mut Cpu, memory: &mut Memory, keyboard: &mut Keyboard, cycles_per_frame: u32, fps: f32) -> Result<u64, i32>
+{
+    frontend::terminal::run(cpu, memory, keyboard, cycles_per_frame, fps).map_err(|e| {
+        eprintln!("Cannot run in the terminal: {}", e);
+        1
+    })
+}
+
+#[cfg(not(all(feature = "tui", unix)))]
+fn run_tui(_: &mut Cpu, _: &mut Memory, _: &mut Keyboard, _: u32, _: f32) -> Result<u64, i32>
+{
+    eprintln!("--tui needs the emulator to be built with the tui feature, on unix");
+    Err(1)
+}
+
+fn print_trace(cpu: &mut Cpu)
+{
+    if cpu.trace {
+        for line in cpu.take_trace() {
+            eprintln!("{}", line);
+        }
+    }
+}
+
+fn print_state(cpu: &Cpu, display: &Display)
+{
+    print!("{}", display.to_text('#', '.'));
+    let registers: Vec<String> = cpu.registers().iter().enumerate()
+        .map(|(i, value)| format!("V{:X}={:02X}", i, value))
+        .collect();
+    println!("{}", registers.join(" "));
+    println!("I={:03X} PC={:03X} DT={:02X} ST={:02X}",
+        cpu.index(), cpu.program_counter(), cpu.delay_timer(), cpu.sound_timer());
+}
+
+// Compare the display with a golden file of '#' lit and '.' unlit pixels, as printed by --headless,
+// only its first rows being compared, and print the differing rows on a mismatch
+fn verify_display(display: &Display, golden_filepath: &str) -> Result<(), i32>
+{
+    let golden = match fs::read_to_string(golden_filepath) {
+        Ok(golden) => golden,
+        Err(io_err) => {
+            eprintln!("Cannot read golden file {}: {}", golden_filepath, io_err);
+            return Err(1);
+        },
+    };
+    let text = display.to_text('#', '.');
+    let rows: Vec<&str> = text.lines().collect();
+    let expected: Vec<&str> = golden.lines().map(str::trim_end).take(rows.len()).collect();
+    let differing: Vec<usize> = (0..rows.len()).filter(|&row| expected.get(row) != Some(&rows[row])).collect();
+    if differing.is_empty() {
+        println!("Display matches {}", golden_filepath);
+        return Ok(());
+    }
+    eprintln!("Display differs from {} on {} of {} rows:", golden_filepath, differing.len(), rows.len());
+    for &row in differing.iter().take(MAX_DIFF_ROWS) {
+        eprintln!("row {:3} expected {}", row, expected.get(row).unwrap_or(&"<missing>"));
+        eprintln!("        got      {}", rows[row]);
+    }
+    if differing.len() > MAX_DIFF_ROWS {
+        eprintln!("and {} more rows", differing.len() - MAX_DIFF_ROWS);
+    }
+    Err(1)
+}
+
+// Read a ROM file, or the standard input, decompressing it if it is gzipped
+fn read_rom(rom_filepath: &str) -> Result<Vec<u8>, io::Error>
+{
+    if rom_filepath == STDIN_ROM {
+        let mut rom = Vec::new();
+        io::stdin().read_to_end(&mut rom)?;
+        return unpack_rom(rom);
+    }
+    unpack_rom(fs::read(rom_filepath)?)
+}
+
+// Parse a "start:len" memory region, both in hexadecimal
+fn parse_region(region: &str) -> Result<(usize, usize), String>
+{
+    let mut parts = region.splitn(2, ':');
+    let mut parse = || match parts.next() {
+        Some(part) => usize::from_str_radix(part.trim_start_matches("0x"), 16).map_err(|e| e.to_string()),
+        None => Err(String::from("missing length")),
+    };
+    Ok((parse()?, parse()?))
+}
+
+// Write the display as a PNG image, at its native resolution
+#[cfg(feature = "sdl")]
+fn save_screenshot(path: &Path, display: &Display, fg: (u8, u8, u8), bg: (u8, u8, u8)) -> Result<(), String>
+{
+    let (width, height) = display.get_sizes();
+    let file = fs::File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(io::BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&display.to_rgba(fg, bg)).map_err(|e| e.to_string())
+}
+
+// Flags saved by a previous run, none when the file does not exist yet
+fn load_flags(path: &str) -> Result<Vec<u8>, io::Error>
+{
+    match fs::read(path) {
+        Err(io_err) if io_err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        result => result,
+    }
+}
+
+fn save_flags(cpu: &Cpu, path: Option<&str>)
+{
+    if let Some(path) = path {
+        if let Err(io_err) = fs::write(path, cpu.flags()) {
+            eprintln!("Cannot save flags to {}: {}", path, io_err);
+        }
+    }
+}
+
+fn print_disassembly(rom_filepath: &str) -> Result<(), i32>
+{
+    let rom = match read_rom(rom_filepath) {
+        Ok(rom) => rom,
+        Err(io_err) => {
+            eprintln!("Cannot load ROM file {}: {}", rom_filepath, io_err);
+            return Err(1);
+        },
+    };
+    for bytes in rom.chunks(2) {
+        let opcode = (bytes[0] as u16) << 8 | *bytes.get(1).unwrap_or(&0) as u16;
+        println!("{}", disassemble(opcode));
+    }
+    Ok(())
+}
+
+fn print_rom_listing(rom_filepath: &str, start_address: usize, ram_size: usize) -> Result<(), i32>
+{
+    let mut memory = Memory::with_ram_size(ram_size);
+    let rom = match read_rom(rom_filepath).and_then(|rom| memory.load_bytes(&rom, start_address).map(|_| rom)) {
+        Ok(rom) => rom,
+        Err(io_err) => {
+            eprintln!("Cannot load ROM file {}: {}", rom_filepath, io_err);
+            return Err(1);
+        },
+    };
+    for line in listing(&memory.memory, start_address, rom.len()) {
+        println!("{}", line);
+    }
+    Ok(())
 }
 
 fn run() -> Result<(), i32>
@@ -97,42 +398,622 @@ fn run() -> Result<(), i32>
         .version(VERSION)
         .author("Arthur Cros <arthur.cros@etna.io>")
         .about("Simple Chip8 emulator")
+        .arg(Arg::with_name("autosave_interval")
+            .long("autosave-interval")
+            .takes_value(true)
+            .value_name("seconds")
+            .conflicts_with_all(&["headless", "tui", "verify"])
+            .help("Save the emulator state this often, in turn to two .autosave1.state and .autosave2.state files next to the ROM"))
+        .arg(Arg::with_name("breakpoint")
+            .long("break")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("address")
+            .help("Pause the cpu before executing the instruction at this hexadecimal address (resume with P)"))
         .arg(Arg::with_name("clock_rate")
             .short("c")
             .long("clock-rate")
             .default_value("1000")
             .help("Clock rate of the cpu in Hz"))
+        .arg(Arg::with_name("cosmac")
+            .long("cosmac")
+            .conflicts_with("quirks")
+            .help("Behave as the COSMAC VIP interpreter: the chip8 quirks, 8xy1/8xy2/8xy3 resetting VF and a 540Hz clock \
+                   unless --clock-rate is given"))
+        .arg(Arg::with_name("cycles_per_frame")
+            .long("cycles-per-frame")
+            .takes_value(true)
+            .value_name("cycles")
+            .help("Execute this many cpu cycles per frame instead of following the clock rate"))
+        .arg(Arg::with_name("cycle_accurate")
+            .long("cycle-accurate")
+            .help("Charge the slow instructions, like sprite drawing, several cycles of the cpu budget"))
         .arg(Arg::with_name("framerate")
             .short("f")
             .long("framerate")
             .default_value("60")
             .help("framerate in frame per second"))
+        .arg(Arg::with_name("fill_byte")
+            .long("fill-byte")
+            .takes_value(true)
+            .value_name("byte")
+            .help("Fill the RAM with this hexadecimal byte instead of zeros, e.g. CC to spot the uninitialized reads"))
+        .arg(Arg::with_name("flags_file")
+            .long("flags-file")
+            .takes_value(true)
+            .value_name("file")
+            .help("Load the SUPER-CHIP Fx75/Fx85 flags from this file and save them to it on exit, e.g. for high scores"))
         .arg(Arg::with_name("frequency")
             .short("v")
             .long("frequence")
             .default_value("553.0")
             .help("Choose frequency for the beep"))
+        .arg(Arg::with_name("volume")
+            .long("volume")
+            .default_value("0.25")
+            .help("Volume of the beep, from 0.0 to 1.0 (toggle mute with M)"))
         .arg(Arg::with_name("gradient")
             .short("g")
             .long("gradient-colors")
             .help("Enable gradient coloring of pixels"))
+        .arg(Arg::with_name("gradient_speed")
+            .long("gradient-speed")
+            .default_value("1")
+            .help("Hue degrees the gradient moves by every frame"))
+        .arg(Arg::with_name("gradient_saturation")
+            .long("gradient-saturation")
+            .default_value("0.2")
+            .help("Saturation of the gradient colors, from 0.0 to 1.0"))
+        .arg(Arg::with_name("gradient_value")
+            .long("gradient-value")
+            .default_value("1.0")
+            .help("Value (brightness) of the gradient colors, from 0.0 to 1.0"))
+        .arg(Arg::with_name("fg")
+            .long("fg")
+            .takes_value(true)
+            .value_name("RRGGBB")
+            .help("Color of the lit pixels when gradient coloring is off, e.g. FFCDE6"))
+        .arg(Arg::with_name("bg")
+            .long("bg")
+            .takes_value(true)
+            .value_name("RRGGBB")
+            .help("Background color, e.g. 4A4A4A"))
+        .arg(Arg::with_name("letterbox")
+            .long("letterbox")
+            .takes_value(true)
+            .value_name("RRGGBB")
+            .help("Color of the borders when the window aspect ratio differs from the display, e.g. 000000"))
+        .arg(Arg::with_name("color_cycle_on_beep")
+            .long("color-cycle-on-beep")
+            .help("Shift the pixels hue while the beep is playing"))
+        .arg(Arg::with_name("dump_memory")
+            .long("dump-memory")
+            .takes_value(true)
+            .value_name("start:len")
+            .requires("headless")
+            .help("Print a hexadecimal dump of the RAM region after the headless run, e.g. 200:40"))
+        .arg(Arg::with_name("dump_rom")
+            .long("dump-rom")
+            .conflicts_with_all(&["test_pattern", "disassemble"])
+            .help("Print the addresses, bytes and mnemonics of the ROM loaded at the start address instead of running it"))
+        .arg(Arg::with_name("fade")
+            .long("fade")
+            .help("Fade turned off pixels out over a few frames to reduce flicker"))
+        .arg(Arg::with_name("debug_overlay")
+            .long("debug-overlay")
+            .help("Draw the cpu registers over the game (toggle with F3)"))
+        .arg(Arg::with_name("deterministic")
+            .long("deterministic")
+            .help("Seed the random number generator with 0 unless --seed is given"))
+        .arg(Arg::with_name("disassemble")
+            .long("disassemble")
+            .conflicts_with("test_pattern")
+            .help("Print the disassembled ROM instead of running it"))
+        .arg(Arg::with_name("double_buffer")
+            .long("double-buffer")
+            .help("Only show the frames once complete, against tearing at high clock rates (some ROMs expect the flicker)"))
+        .arg(Arg::with_name("freeze_timers")
+            .long("freeze-timers")
+            .help("Start with the delay and sound timers frozen (toggle with T)"))
+        .arg(Arg::with_name("gamepad_map")
+            .long("gamepad-map")
+            .takes_value(true)
+            .value_name("file")
+            .help("Override the game controller bindings with `<hex key> = <button name>` lines"))
+        .arg(Arg::with_name("headless")
+            .long("headless")
+            .requires("max_cycles")
+            .help("Run without a window and dump the final display and registers to stdout"))
+        .arg(Arg::with_name("index_overflow_quirk")
+            .long("index-overflow-quirk")
+            .help("Make Fx1E set VF when I goes past 0xFFF (Amiga interpreter, e.g. for Spacefight 2091!)"))
+        .arg(Arg::with_name("jump_quirk")
+            .long("jump-quirk")
+            .takes_value(true)
+            .possible_values(&["v0", "vx"])
+            .help("Register added by Bnnn: v0 (CHIP-8) or vx with x the high nibble of nnn (SUPER-CHIP) [default: v0]"))
+        .arg(Arg::with_name("key_hold_frames")
+            .long("key-hold-frames")
+            .default_value("0")
+            .help("Keep keys pressed for this many frames after their release"))
+        .arg(Arg::with_name("keymap")
+            .long("keymap")
+            .takes_value(true)
+            .value_name("file")
+            .help("Override the layout key bindings with `<hex key> = <key name>` lines"))
+        .arg(Arg::with_name("layout")
+            .long("layout")
+            .possible_values(&["azerty", "qwerty"])
+            .default_value("azerty")
+            .help("Keyboard layout the chip-8 keypad is mapped on"))
+        .arg(Arg::with_name("logic_quirk")
+            .long("logic-quirk")
+            .help("Make 8xy1, 8xy2 and 8xy3 reset VF (COSMAC VIP)"))
+        .arg(Arg::with_name("log_unknown_opcodes")
+            .long("log-unknown-opcodes")
+            .help("Print skipped unknown opcodes to stderr, once per address"))
+        .arg(Arg::with_name("max_cycles")
+            .long("max-cycles")
+            .takes_value(true)
+            .value_name("cycles")
+            .visible_alias("cycles")
+            .requires("headless_run")
+            .help("Number of cpu cycles executed in headless mode"))
+        .arg(Arg::with_name("max_instructions")
+            .long("max-instructions")
+            .takes_value(true)
+            .value_name("count")
+            .help("Quit after executing this many instructions, e.g. to end a stuck ROM in a script"))
+        .arg(Arg::with_name("no_audio")
+            .long("no-audio")
+            .help("Run without sound, leaving the audio device alone"))
+        .arg(Arg::with_name("profile")
+            .long("profile")
+            .help("Print how many instructions of each opcode class were executed on exit"))
+        .arg(Arg::with_name("record")
+            .long("record")
+            .takes_value(true)
+            .value_name("file.gif")
+            .help("Record every rendered frame to an animated GIF, written on exit (frames are kept in memory until then)"))
+        .arg(Arg::with_name("record_dedupe")
+            .long("record-dedupe")
+            .requires("record")
+            .help("Merge the recorded frames identical to the previous one into a longer frame"))
+        .arg(Arg::with_name("record_input")
+            .long("record-input")
+            .takes_value(true)
+            .value_name("file")
+            .conflicts_with("replay_input")
+            .requires("cycles_per_frame")
+            .help("Record the keys pressed every frame to a file, written on exit"))
+        .arg(Arg::with_name("record_scale")
+            .long("record-scale")
+            .default_value("1")
+            .help("Enlarge the recorded GIF by this whole factor"))
+        .arg(Arg::with_name("replay_input")
+            .long("replay-input")
+            .takes_value(true)
+            .value_name("file")
+            .requires("cycles_per_frame")
+            .help("Play the keys recorded with --record-input instead of reading the keyboard"))
+        .arg(Arg::with_name("report_slow")
+            .long("report-slow")
+            .takes_value(true)
+            .value_name("threshold_us")
+            .help("Log instructions taking longer than this many microseconds"))
+        .arg(Arg::with_name("resume")
+            .long("resume")
+            .conflicts_with_all(&["headless", "tui", "verify"])
+            .help("Start from the latest autosave of the ROM, see --autosave-interval"))
+        .arg(Arg::with_name("quirks")
+            .long("quirks")
+            .possible_values(&["chip8", "schip", "xochip"])
+            .value_name("preset")
+            .help("Start from the quirks of an interpreter, the individual quirk options override them"))
+        .arg(Arg::with_name("ram_size")
+            .long("ram-size")
+            .default_value("4096")
+            .value_name("bytes")
+            .help("Size of the RAM, up to the 65536 bytes of XO-CHIP"))
+        .arg(Arg::with_name("seed")
+            .long("seed")
+            .takes_value(true)
+            .value_name("u64")
+            .help("Seed the random number generator for reproducible runs"))
+        .arg(Arg::with_name("show_fps")
+            .long("show-fps")
+            .help("Show the frames and instructions per second in the window title"))
+        .arg(Arg::with_name("shift_quirk")
+            .long("shift-quirk")
+            .takes_value(true)
+            .possible_values(&["vx", "vy"])
+            .help("Register shifted by 8xy6/8xye: vx (SUPER-CHIP) or vy (CHIP-8) [default: vx]"))
+        .arg(Arg::with_name("load_store_quirk")
+            .long("load-store-quirk")
+            .takes_value(true)
+            .possible_values(&["increment", "increment-minus-one", "none"])
+            .help("How Fx55/Fx65 update I: increment (COSMAC VIP), increment-minus-one (CHIP-48) or none [default: none]"))
+        .arg(Arg::with_name("start_address")
+            .long("start-address")
+            .default_value("200")
+            .value_name("address")
+            .help("Hexadecimal address the ROM is loaded and executed at, e.g. 600 for ETI-660 ROMs"))
+        .arg(Arg::with_name("start_paused")
+            .long("start-paused")
+            .conflicts_with_all(&["headless", "tui", "verify"])
+            .help("Pause the cpu before the first instruction, to set up the debugging (resume with P)"))
+        .arg(Arg::with_name("stats")
+            .long("stats")
+            .help("Print run statistics on exit"))
+        .arg(Arg::with_name("strict_stack")
+            .long("strict-stack")
+            .help("Abort on stack overflows and underflows instead of pausing the cpu"))
+        .arg(Arg::with_name("test_pattern")
+            .long("test-pattern")
+            .help("Display a test pattern instead of running a ROM"))
+        .arg(Arg::with_name("trace")
+            .long("trace")
+            .help("Log every executed instruction to stderr"))
+        .arg(Arg::with_name("trace_disasm")
+            .long("trace-disasm")
+            .help("Log every executed instruction to stderr as disassembly, with the values of the registers it reads"))
+        .arg(Arg::with_name("tui")
+            .long("tui")
+            .conflicts_with("headless")
+            .help("Run in the terminal instead of a window, keys 1234 QWER ASDF ZXCV (needs the tui feature)"))
+        .arg(Arg::with_name("turbo_key")
+            .long("turbo-key")
+            .default_value("Tab")
+            .value_name("key name")
+            .help("Key fast-forwarding the emulation while held"))
+        .arg(Arg::with_name("turbo_multiplier")
+            .long("turbo-multiplier")
+            .default_value("4")
+            .help("How many times more cpu cycles run while the turbo key is held, timers still run at 60Hz"))
+        .arg(Arg::with_name("unknown_opcode")
+            .long("unknown-opcode")
+            .possible_values(&["ignore", "pause"])
+            .default_value("ignore")
+            .help("What to do on unknown opcodes: skip them or pause the cpu"))
+        .arg(Arg::with_name("verify")
+            .long("verify")
+            .takes_value(true)
+            .value_name("golden_file")
+            .conflicts_with("tui")
+            .requires("max_cycles")
+            .help("Run headless with the seed 0 unless --seed is given, and exit with 0 if the display matches the golden file, \
+                   as printed by --headless, or 1 with the differing rows"))
+        .arg(Arg::with_name("vblank_quirk")
+            .long("vblank-quirk")
+            .help("Make Dxyn wait for the next frame, limiting draws to 60 per second (COSMAC VIP)"))
+        .arg(Arg::with_name("watchpoint")
+            .long("watch")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("address")
+            .help("Pause the cpu after an instruction changes the byte at this hexadecimal address (resume with P)"))
+        .arg(Arg::with_name("wrap_quirk")
+            .long("wrap-quirk")
+            .takes_value(true)
+            .possible_values(&["wrap", "clip"])
+            .help("Sprite pixels past the display edges: wrap around or clip (COSMAC VIP) [default: wrap]"))
+        .arg(Arg::with_name("write_protect")
+            .long("write-protect")
+            .possible_values(&["font", "rom"])
+            .value_name("region")
+            .help("Report and ignore the program writes to the fonts or to the loaded ROM"))
         .arg(Arg::with_name("rom_filepath")
-            .required(true)
-            .help("Filepath to ROM"))
+            .required_unless("test_pattern")
+            .help("Filepath to ROM, possibly gzipped, - to read it from the standard input"))
+        .group(ArgGroup::with_name("headless_run")
+            .args(&["headless", "verify"]))
         .get_matches();
 
-    let clock_rate = match arg.value_of("clock_rate").unwrap().parse::<f32>() {
-        Ok(clock_rate) => (1.0 / clock_rate * 1000.0) as u32,
+    if arg.is_present("disassemble") {
+        return print_disassembly(arg.value_of("rom_filepath").unwrap());
+    }
+
+    let clock_hz = match arg.value_of("clock_rate").unwrap().parse::<f32>() {
+        Ok(_) if arg.is_present("cosmac") && arg.occurrences_of("clock_rate") == 0 => Quirks::COSMAC_CLOCK_RATE,
+        Ok(clock_hz) if clock_hz > 0.0 => clock_hz,
+        Ok(clock_hz) => {
+            eprintln!("Clock rate must be positive: {}", clock_hz);
+            return Err(1);
+        },
         Err(e) => {
             eprintln!("Clock rate must be a number: {}", e);
             return Err(1);
         },
     };
 
-    let framerate = match arg.value_of("framerate").unwrap().parse::<f32>() {
-        Ok(framerate) => (1000.0 / framerate) as u32,
+    let cycles_per_frame = match arg.value_of("cycles_per_frame").map(|cycles| cycles.parse::<u32>()) {
+        None => None,
+        Some(Ok(cycles)) if cycles > 0 => Some(cycles),
+        Some(Ok(cycles)) => {
+            eprintln!("Cycles per frame must be positive: {}", cycles);
+            return Err(1);
+        },
+        Some(Err(e)) => {
+            eprintln!("Cycles per frame must be a positive number: {}", e);
+            return Err(1);
+        },
+    };
+    if cycles_per_frame.is_some() && arg.occurrences_of("clock_rate") > 0 {
+        eprintln!("--clock-rate and --cycles-per-frame cannot be used together");
+        return Err(1);
+    }
+
+    let fps = match arg.value_of("framerate").unwrap().parse::<f32>() {
+        Ok(fps) if fps > 0.0 => fps,
+        Ok(fps) => {
+            eprintln!("Framerate must be positive: {}", fps);
+            return Err(1);
+        },
+        Err(e) => {
+            eprintln!("Framerate must be a number: {}", e);
+            return Err(1);
+        },
+    };
+
+    let start_address = match usize::from_str_radix(arg.value_of("start_address").unwrap().trim_start_matches("0x"), 16) {
+        Ok(address) => address,
         Err(e) => {
-            eprintln!("Clock rate must be a number: {}", e);
+            eprintln!("Start address must be an hexadecimal address: {}", e);
+            return Err(1);
+        },
+    };
+
+    let fill_byte = match arg.value_of("fill_byte").map(|byte| u8::from_str_radix(byte.trim_start_matches("0x"), 16)) {
+        None => 0,
+        Some(Ok(byte)) => byte,
+        Some(Err(e)) => {
+            eprintln!("Fill byte must be an hexadecimal byte: {}", e);
+            return Err(1);
+        },
+    };
+
+    let ram_size = match arg.value_of("ram_size").unwrap().parse::<usize>() {
+        Ok(size) if (RAM_SIZE..=MAX_RAM_SIZE).contains(&size) => size,
+        Ok(size) => {
+            eprintln!("RAM size must be between {} and {} bytes: {}", RAM_SIZE, MAX_RAM_SIZE, size);
+            return Err(1);
+        },
+        Err(e) => {
+            eprintln!("RAM size must be a number: {}", e);
+            return Err(1);
+        },
+    };
+
+    if arg.is_present("dump_rom") {
+        return print_rom_listing(arg.value_of("rom_filepath").unwrap(), start_address, ram_size);
+    }
+
+    let key_hold_frames = match arg.value_of("key_hold_frames").unwrap().parse::<u32>() {
+        Ok(frames) => frames,
+        Err(e) => {
+            eprintln!("Key hold frames must be a positive number: {}", e);
+            return Err(1);
+        },
+    };
+
+    let seed = match arg.value_of("seed").map(|seed| seed.parse::<u64>()) {
+        None if arg.is_present("deterministic") || arg.is_present("verify") => Some(0),
+        None => None,
+        Some(Ok(seed)) => Some(seed),
+        Some(Err(e)) => {
+            eprintln!("Seed must be a positive number: {}", e);
+            return Err(1);
+        },
+    };
+
+    let slow_threshold = match arg.value_of("report_slow").map(|threshold| threshold.parse::<u64>()) {
+        None => None,
+        Some(Ok(threshold)) => Some(time::Duration::from_micros(threshold)),
+        Some(Err(e)) => {
+            eprintln!("Slow instruction threshold must be a number of microseconds: {}", e);
+            return Err(1);
+        },
+    };
+
+    let mut quirks = match arg.value_of("quirks") {
+        Some(preset) => preset.parse::<Quirks>().unwrap(),
+        None if arg.is_present("cosmac") => Quirks::cosmac(),
+        None => Quirks::default(),
+    };
+    if let Some(shift_quirk) = arg.value_of("shift_quirk") {
+        quirks.shift = shift_quirk.parse::<ShiftQuirk>().unwrap();
+    }
+    if let Some(load_store_quirk) = arg.value_of("load_store_quirk") {
+        quirks.load_store = load_store_quirk.parse::<LoadStoreQuirk>().unwrap();
+    }
+    if let Some(jump_quirk) = arg.value_of("jump_quirk") {
+        quirks.jump = jump_quirk.parse::<JumpQuirk>().unwrap();
+    }
+    if let Some(wrap_quirk) = arg.value_of("wrap_quirk") {
+        quirks.wrap = wrap_quirk.parse::<WrapQuirk>().unwrap();
+    }
+    if arg.is_present("vblank_quirk") {
+        quirks.vblank = true;
+    }
+    if arg.is_present("index_overflow_quirk") {
+        quirks.index_overflow = true;
+    }
+    if arg.is_present("logic_quirk") {
+        quirks.logic = true;
+    }
+    let unknown_opcode = arg.value_of("unknown_opcode").unwrap().parse::<UnknownOpcodePolicy>().unwrap();
+
+    let mut memory = Memory::with_ram_size_and_fill(ram_size, fill_byte);
+    let mut keyboard = Keyboard::new();
+    keyboard.hold_frames = key_hold_frames;
+    let mut cpu = Cpu::new_with_quirks(start_address, quirks);
+    cpu.timers_frozen = arg.is_present("freeze_timers");
+    cpu.slow_threshold = slow_threshold;
+    cpu.unknown_opcode = unknown_opcode;
+    cpu.warn_unknown = arg.is_present("log_unknown_opcodes");
+    cpu.trace = arg.is_present("trace") || arg.is_present("trace_disasm");
+    cpu.trace_disasm = arg.is_present("trace_disasm");
+    cpu.profile = arg.is_present("profile");
+    cpu.strict_stack = arg.is_present("strict_stack");
+    if arg.is_present("start_paused") {
+        cpu.pause();
+    }
+    if let Some(seed) = seed {
+        cpu.set_seed(seed);
+    }
+    let flags_filepath = arg.value_of("flags_file");
+    if let Some(path) = flags_filepath {
+        match load_flags(path) {
+            Ok(flags) => cpu.set_flags(&flags),
+            Err(io_err) => {
+                eprintln!("Cannot load flags from {}: {}", path, io_err);
+                return Err(1);
+            },
+        }
+    }
+    for address in arg.values_of("breakpoint").into_iter().flatten() {
+        match u16::from_str_radix(address.trim_start_matches("0x"), 16) {
+            Ok(address) => cpu.add_breakpoint(address),
+            Err(e) => {
+                eprintln!("Breakpoint must be an hexadecimal address: {}", e);
+                return Err(1);
+            },
+        }
+    }
+    cpu.max_instructions = match arg.value_of("max_instructions").map(|count| count.parse::<u64>()) {
+        None => None,
+        Some(Ok(count)) => Some(count),
+        Some(Err(e)) => {
+            eprintln!("Max instructions must be a positive number: {}", e);
+            return Err(1);
+        },
+    };
+    for address in arg.values_of("watchpoint").into_iter().flatten() {
+        match u16::from_str_radix(address.trim_start_matches("0x"), 16) {
+            Ok(address) => cpu.add_watchpoint(address),
+            Err(e) => {
+                eprintln!("Watchpoint must be an hexadecimal address: {}", e);
+                return Err(1);
+            },
+        }
+    }
+    let test_pattern = arg.is_present("test_pattern");
+    if test_pattern {
+        memory.display.fill_test_pattern();
+    }
+    let rom = match arg.value_of("rom_filepath").filter(|_| !test_pattern) {
+        None => Vec::new(),
+        Some(rom_filepath) => match read_rom(rom_filepath).and_then(|rom| memory.load_bytes(&rom, start_address).map(|_| rom)) {
+            Ok(rom) => {
+                if rom.len() % 2 == 1 {
+                    eprintln!("Warning: ROM file {} has an odd size of {} bytes, its last byte is not a whole opcode",
+                        rom_filepath, rom.len());
+                }
+                rom
+            },
+            Err(io_err) => {
+                eprintln!("Cannot load ROM file {}: {}", rom_filepath, io_err);
+                return Err(1);
+            },
+        },
+    };
+    memory.protected = match arg.value_of("write_protect") {
+        Some("font") => Some(0..BIG_FONT_ADDRESS + BIG_SPRITES.len()),
+        Some("rom") => Some(start_address..start_address + rom.len()),
+        _ => None,
+    };
+    memory.display.set_double_buffered(arg.is_present("double_buffer"));
+
+    if arg.is_present("headless_run") {
+        let max_cycles = match arg.value_of("max_cycles").unwrap().parse::<u64>() {
+            Ok(cycles) => cycles,
+            Err(e) => {
+                eprintln!("Max cycles must be a positive number: {}", e);
+                return Err(1);
+            },
+        };
+        let cycles_per_timer_tick = match cycles_per_frame {
+            Some(cycles) => cycles as u64,
+            None => (clock_hz / 60.0) as u64,
+        };
+        let dump_region = match arg.value_of("dump_memory").map(parse_region) {
+            None => None,
+            Some(Ok(region)) => Some(region),
+            Some(Err(e)) => {
+                eprintln!("Memory region must be given as <start>:<len> in hexadecimal: {}", e);
+                return Err(1);
+            },
+        };
+        let start = time::Instant::now();
+        if !test_pattern {
+            run_headless(&mut cpu, &mut memory, &keyboard, max_cycles, cycles_per_timer_tick.max(1));
+        }
+        let elapsed = start.elapsed();
+        save_flags(&cpu, flags_filepath);
+        if let Some(golden_filepath) = arg.value_of("verify") {
+            return verify_display(&memory.display, golden_filepath);
+        }
+        print_state(&cpu, &memory.display);
+        if let Some((start, len)) = dump_region {
+            print!("{}", memory.hexdump(start, len));
+        }
+        if arg.is_present("stats") {
+            // nothing is rendered without a window
+            print_stats(&cpu, 0, elapsed);
+        }
+        if cpu.profile {
+            print_histogram(&cpu);
+        }
+        return Ok(());
+    }
+
+    if arg.is_present("tui") {
+        let cycles = cycles_per_frame.unwrap_or((clock_hz / fps) as u32).max(1);
+        let start = time::Instant::now();
+        let result = run_tui(&mut cpu, &mut memory, &mut keyboard, cycles, fps);
+        save_flags(&cpu, flags_filepath);
+        let frames = result?;
+        if arg.is_present("stats") {
+            print_stats(&cpu, frames, start.elapsed());
+        }
+        if cpu.profile {
+            print_histogram(&cpu);
+        }
+        return Ok(());
+    }
+
+    #[cfg(feature = "sdl")]
+    return run_window(&arg, cpu, memory, keyboard, &rom, start_address, Speed { clock_hz, fps, cycles_per_frame });
+    #[cfg(not(feature = "sdl"))]
+    {
+        eprintln!("This build has no window, run the ROM with --tui or --headless");
+        Err(1)
+    }
+}
+
+// Emulation speed asked on the command line
+#[cfg(feature = "sdl")]
+struct Speed
+{
+    clock_hz: f32,
+    fps: f32,
+    cycles_per_frame: Option<u32>,
+}
+
+// Run the emulator in the SDL window until it is closed or the instruction limit is reached
+#[cfg(feature = "sdl")]
+fn run_window(arg: &ArgMatches, mut cpu: Cpu, mut memory: Memory, mut keyboard: Keyboard, rom: &[u8], start_address: usize, speed: Speed) -> Result<(), i32>
+{
+    let Speed { clock_hz, fps, cycles_per_frame } = speed;
+    let test_pattern = arg.is_present("test_pattern");
+    let flags_filepath = arg.value_of("flags_file");
+
+    let record_scale = match arg.value_of("record_scale").unwrap().parse::<usize>() {
+        Ok(scale) if scale > 0 => scale,
+        _ => {
+            eprintln!("Record scale must be a positive number");
             return Err(1);
         },
     };
@@ -145,66 +1026,393 @@ fn run() -> Result<(), i32>
         },
     };
 
-    let (sdl_context, mut canvas, audio_subsystem) = init_sdl_window();
+    let volume = match arg.value_of("volume").unwrap().parse::<f32>() {
+        Ok(volume) => volume,
+        Err(e) => {
+            eprintln!("Volume must be a number: {}", e);
+            return Err(1);
+        },
+    };
+
+    let gradient_speed = match arg.value_of("gradient_speed").unwrap().parse::<u32>() {
+        Ok(speed) => speed,
+        Err(e) => {
+            eprintln!("Gradient speed must be a positive number: {}", e);
+            return Err(1);
+        },
+    };
+
+    let gradient_saturation = match arg.value_of("gradient_saturation").unwrap().parse::<f32>() {
+        Ok(saturation) if (0.0..=1.0).contains(&saturation) => saturation,
+        Ok(saturation) => {
+            eprintln!("Gradient saturation must be between 0.0 and 1.0: {}", saturation);
+            return Err(1);
+        },
+        Err(e) => {
+            eprintln!("Gradient saturation must be a number: {}", e);
+            return Err(1);
+        },
+    };
+
+    let gradient_value = match arg.value_of("gradient_value").unwrap().parse::<f32>() {
+        Ok(value) if (0.0..=1.0).contains(&value) => value,
+        Ok(value) => {
+            eprintln!("Gradient value must be between 0.0 and 1.0: {}", value);
+            return Err(1);
+        },
+        Err(e) => {
+            eprintln!("Gradient value must be a number: {}", e);
+            return Err(1);
+        },
+    };
+
+    let fg_color = match arg.value_of("fg").map(parse_color).unwrap_or(Ok(PIXEL_COLOR)) {
+        Ok(color) => color,
+        Err(e) => {
+            eprintln!("Foreground color: {}", e);
+            return Err(1);
+        },
+    };
+
+    let bg_color = match arg.value_of("bg").map(parse_color).unwrap_or(Ok(BG_COLOR)) {
+        Ok(color) => color,
+        Err(e) => {
+            eprintln!("Background color: {}", e);
+            return Err(1);
+        },
+    };
+
+    let letterbox_color = match arg.value_of("letterbox").map(parse_color).transpose() {
+        Ok(color) => color,
+        Err(e) => {
+            eprintln!("Letterbox color: {}", e);
+            return Err(1);
+        },
+    };
+
+    let mut keymap = KeyMap::from_layout(arg.value_of("layout").unwrap().parse::<Layout>().unwrap());
+    if let Some(keymap_filepath) = arg.value_of("keymap") {
+        let applied = fs::read_to_string(keymap_filepath)
+            .map_err(|io_err| format!("Cannot load keymap file {}: {}", keymap_filepath, io_err))
+            .and_then(|config| keymap.apply(&config)
+                .map_err(|e| format!("Invalid keymap {}: {}", keymap_filepath, e)));
+        if let Err(e) = applied {
+            eprintln!("{}", e);
+            return Err(1);
+        }
+    }
+
+    let autosave_interval = match arg.value_of("autosave_interval").map(|seconds| seconds.parse::<f64>()) {
+        None => None,
+        Some(Ok(seconds)) if seconds > 0.0 => Some(time::Duration::from_secs_f64(seconds)),
+        _ => {
+            eprintln!("Autosave interval must be a positive number of seconds");
+            return Err(1);
+        },
+    };
+
+    let turbo_key = match key_from_name(arg.value_of("turbo_key").unwrap()) {
+        Some(key) => key,
+        None => {
+            eprintln!("Unknown turbo key name: {}", arg.value_of("turbo_key").unwrap());
+            return Err(1);
+        },
+    };
+
+    let turbo_multiplier = match arg.value_of("turbo_multiplier").unwrap().parse::<u32>() {
+        Ok(multiplier) if multiplier > 0 => multiplier,
+        _ => {
+            eprintln!("Turbo multiplier must be a positive number");
+            return Err(1);
+        },
+    };
+
+    let replay = match arg.value_of("replay_input").map(InputLog::load) {
+        None => None,
+        Some(Ok(replay)) => Some(replay),
+        Some(Err(io_err)) => {
+            eprintln!("Cannot load input log {}: {}", arg.value_of("replay_input").unwrap(), io_err);
+            return Err(1);
+        },
+    };
+
+    let mut gamepad_map = GamepadMap::default();
+    if let Some(gamepad_map_filepath) = arg.value_of("gamepad_map") {
+        let applied = fs::read_to_string(gamepad_map_filepath)
+            .map_err(|io_err| format!("Cannot load gamepad map file {}: {}", gamepad_map_filepath, io_err))
+            .and_then(|config| gamepad_map.apply(&config)
+                .map_err(|e| format!("Invalid gamepad map {}: {}", gamepad_map_filepath, e)));
+        if let Err(e) = applied {
+            eprintln!("{}", e);
+            return Err(1);
+        }
+    }
+
+    let (sdl_context, mut canvas, audio_subsystem) = match init_sdl_window(!arg.is_present("no_audio")) {
+        Ok(sdl) => sdl,
+        Err(e) => {
+            eprintln!("Cannot initialize SDL: {}", e);
+            return Err(1);
+        },
+    };
     let texture_creator = canvas.texture_creator();
-    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut event_pump = match sdl_context.event_pump() {
+        Ok(event_pump) => event_pump,
+        Err(e) => {
+            eprintln!("Cannot read the SDL events: {}", e);
+            return Err(1);
+        },
+    };
+    let gamepad = GamepadInput::open(&sdl_context, gamepad_map);
+    if let Some(name) = gamepad.name() {
+        println!("Using game controller {}", name);
+    }
 
-    let mut memory = Memory::new();
-    let mut screen = Screen::new(&texture_creator, arg.is_present("gradient"));
-    let mut keyboard = Keyboard::new();
-    let beeper = Beeper::new(&audio_subsystem, frequency);
-    let mut cpu = Cpu::new();
-    if let Err(io_err) = memory.load(arg.value_of("rom_filepath").unwrap()) {
-        eprintln!("Cannot load ROM file {}: {}", arg.value_of("rom_filepath").unwrap(), io_err);
-        return Err(1);
+    let mut screen = Screen::new(&texture_creator, arg.is_present("gradient"), fg_color, bg_color);
+    screen.color_cycle_on_beep = arg.is_present("color_cycle_on_beep");
+    screen.fade = arg.is_present("fade");
+    screen.gradient_speed = gradient_speed;
+    screen.gradient_saturation = gradient_saturation;
+    screen.gradient_value = gradient_value;
+    if let Some(color) = letterbox_color {
+        screen.letterbox_color = color;
+    }
+    let mut beeper = init_beeper(audio_subsystem, frequency);
+    beeper.set_volume(volume);
+    let state_filepath = arg.value_of("rom_filepath")
+        .filter(|rom_filepath| !test_pattern && *rom_filepath != STDIN_ROM)
+        .map(|rom| Path::new(rom).with_extension("state"));
+    let autosave_requested = autosave_interval.is_some() || arg.is_present("resume");
+    let mut autosave = arg.value_of("rom_filepath")
+        .filter(|rom_filepath| autosave_requested && !test_pattern && *rom_filepath != STDIN_ROM)
+        .map(Autosave::new);
+    if autosave_requested && autosave.is_none() {
+        eprintln!("Warning: autosaves are named after the ROM file, there are none without one");
+    }
+    match autosave.as_ref().and_then(Autosave::latest) {
+        Some(latest) if arg.is_present("resume") => {
+            let restored = Snapshot::load(latest).map_err(|io_err| io_err.to_string())
+                .and_then(|snapshot| cpu.restore(&mut memory, &snapshot));
+            if let Err(err) = restored {
+                eprintln!("Cannot resume from {}: {}", latest.display(), err);
+                return Err(1);
+            }
+            println!("Resumed from {}", latest.display());
+        },
+        Some(latest) => println!("Found the autosave {}, run with --resume to continue from it", latest.display()),
+        None if arg.is_present("resume") && autosave.is_some() => eprintln!("Warning: no autosave to resume from"),
+        None => {},
     }
 
-    let mut last_tick = time::Instant::now();
-    #[allow(unused_assignments)]
-    let mut delta = 0;
-    let mut delta_render = 0;
-    let mut delta_timer = 0;
-    let mut delta_cycle = 0;
+    let mut recorder = arg.value_of("record").map(|_| Recorder::new(record_scale, fps, arg.is_present("record_dedupe")));
+    let mut overlay = if arg.is_present("debug_overlay") { Some(DebugOverlay::new()) } else { None };
+    let mut help = HelpOverlay::new();
+    let mut input_log = arg.value_of("record_input").map(|_| InputLog::new());
+    // keys only change between frames while recording or replaying them, so that replays match
+    let keys_per_frame = input_log.is_some() || replay.is_some();
+
+    let start = time::Instant::now();
+    let mut frames: u64 = 0;
+    let mut cpu_ticker = Ticker::with_frequency(clock_hz as f64, start);
+    let mut timer_ticker = Ticker::with_frequency(TIMER_FREQUENCY, start);
+    let mut render_ticker = Ticker::with_frequency(fps as f64, start);
+    let mut autosave_ticker = autosave_interval.map(|interval| Ticker::new(interval, start));
+    let mut perf_counter = if arg.is_present("show_fps") { Some(PerfCounter::new(start, cpu.cycles())) } else { None };
+    let cycle_accurate = arg.is_present("cycle_accurate");
+    let mut budget = CycleBudget::default();
 
     'running: loop {
-        let tick = time::Instant::now();
-        delta = tick.duration_since(last_tick).as_millis();
-        last_tick = tick;
-        delta_render += delta;
-        delta_timer += delta;
-        delta_cycle += delta;
-        if let Err(()) = check_terminate_events(&mut event_pump) {
-            break 'running;
+        let hotkeys = match check_events(&mut event_pump) {
+            Ok(hotkeys) => hotkeys,
+            Err(()) => break 'running,
+        };
+        if !keys_per_frame {
+            read_keyboard(&mut keyboard, &keymap, &event_pump, &gamepad);
+        }
+        // the turbo key is not logged, it would make the replays diverge
+        let turbo = !keys_per_frame && event_pump.keyboard_state().pressed_scancodes()
+            .filter_map(Keycode::from_scancode)
+            .any(|key| key == turbo_key);
+        for hotkey in hotkeys {
+            match hotkey {
+                Keycode::T => cpu.timers_frozen = !cpu.timers_frozen,
+                Keycode::P if cpu.is_paused() => cpu.resume(),
+                Keycode::P => cpu.pause(),
+                Keycode::N if cpu.is_paused() => {
+                    cpu.step(&mut memory, &keyboard);
+                },
+                Keycode::M => beeper.set_muted(!beeper.is_muted()),
+                Keycode::PageUp => beeper.set_frequency(beeper.frequency() * SEMITONE),
+                Keycode::PageDown => beeper.set_frequency(beeper.frequency() / SEMITONE),
+                Keycode::F1 => if let Some(rom_filepath) = arg.value_of("rom_filepath").filter(|_| !test_pattern) {
+                    cpu.reset();
+                    // a ROM file is read again, to pick up its changes
+                    let reloaded = match rom_filepath {
+                        STDIN_ROM => memory.reset_and_reload_bytes(rom, start_address),
+                        _ => memory.reset_and_reload(rom_filepath, start_address),
+                    };
+                    if let Err(io_err) = reloaded {
+                        eprintln!("Cannot reload ROM file {}: {}", rom_filepath, io_err);
+                    }
+                },
+                Keycode::F2 | Keycode::Question => help.toggle(&mut cpu),
+                Keycode::F3 => if let Some(overlay) = overlay.as_mut() {
+                    overlay.toggle();
+                },
+                Keycode::F5 => if let Some(path) = &state_filepath {
+                    if let Err(io_err) = cpu.snapshot(&memory).save(path) {
+                        eprintln!("Cannot save state to {}: {}", path.display(), io_err);
+                    }
+                },
+                Keycode::F9 => if let Some(path) = &state_filepath {
+                    let restored = Snapshot::load(path).map_err(|io_err| io_err.to_string())
+                        .and_then(|snapshot| cpu.restore(&mut memory, &snapshot));
+                    if let Err(err) = restored {
+                        eprintln!("Cannot load state from {}: {}", path.display(), err);
+                    }
+                },
+                Keycode::F12 => {
+                    let timestamp = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap_or_default().as_millis();
+                    let path = PathBuf::from(format!("screenshot-{}.png", timestamp));
+                    let (fg, bg) = screen.colors();
+                    match save_screenshot(&path, memory.display.front(), fg, bg) {
+                        Ok(()) => println!("Screenshot saved to {}", path.display()),
+                        Err(e) => eprintln!("Cannot save screenshot to {}: {}", path.display(), e),
+                    }
+                },
+                _ => {}
+            }
         }
-        keyboard.read(&event_pump);
-        if delta_cycle > clock_rate as u128 {
-            cpu.do_cycle(&mut memory, &keyboard);
-            delta_cycle = 0;
+        let now = time::Instant::now();
+        if cycles_per_frame.is_none() {
+            let cycles = cycle_budget(cpu_ticker.due(now), turbo_multiplier, turbo);
+            if !test_pattern && !cpu.is_halted(&memory) {
+                budget.spend(cycles, || {
+                    let outcome = run_cycle(&mut cpu, &mut memory, &keyboard);
+                    if cycle_accurate { outcome.cost() } else { 1 }
+                });
+            }
+            for _ in 0..timer_ticker.due(now) {
+                let _ = cpu.update_timers();
+            }
         }
-        if delta_timer > (1.0 / 60.0 * 1000.0) as u128 {
-            if let Ok(_) = cpu.update_timers() {
-                delta_timer = 0;
+        if let (Some(autosave), Some(ticker)) = (autosave.as_mut(), autosave_ticker.as_mut()) {
+            if ticker.due(now) > 0 {
+                if let Err(io_err) = autosave.save(&cpu.snapshot(&memory)) {
+                    eprintln!("Cannot autosave: {}", io_err);
+                }
             }
         }
+        if let Some(max_instructions) = cpu.max_instructions.filter(|_| cpu.instruction_limit_reached()) {
+            println!("Stopped after {} instructions", max_instructions);
+            break 'running;
+        }
         if cpu.beeping {
+            beeper.set_buffer(cpu.audio_buffer());
             beeper.beep();
         } else {
             beeper.pause_beep();
         }
-        if delta_render > framerate as u128 {
-            draw_window(&mut canvas, &mut screen, &memory.display);
-            delta_render = 0;
+        if render_ticker.due(now) > 0 {
+            match &replay {
+                Some(replay) => keyboard.update(replay.get(frames as usize).unwrap_or([0; 16])),
+                None if keys_per_frame => read_keyboard(&mut keyboard, &keymap, &event_pump, &gamepad),
+                None => {},
+            }
+            if let Some(input_log) = input_log.as_mut() {
+                input_log.push(keyboard.physical());
+            }
+            if let Some(cycles) = cycles_per_frame {
+                if !test_pattern && !cpu.is_halted(&memory) {
+                    let cycles = cycle_budget(cycles, turbo_multiplier, turbo);
+                    budget.spend(cycles, || {
+                        let outcome = run_cycle(&mut cpu, &mut memory, &keyboard);
+                        if cycle_accurate { outcome.cost() } else { 1 }
+                    });
+                }
+                let _ = cpu.update_timers();
+            }
+            memory.display.present();
+            draw_window(&mut canvas, &mut screen, memory.display.front(), &cpu, overlay.as_ref(), &help, &keymap);
+            if let Some((fps, ips)) = perf_counter.as_mut().and_then(|counter| counter.frame(now, cpu.cycles())) {
+                let title = format!("{} - {:.0} FPS / {:.0} IPS", WINDOW_TITLE, fps, ips);
+                let _ = canvas.window_mut().set_title(&title);
+            }
+            if let Some(recorder) = recorder.as_mut() {
+                let (fg, bg) = screen.colors();
+                recorder.push(memory.display.front(), fg, bg);
+            }
+            keyboard.next_frame();
+            frames += 1;
+        }
+        let deadline = match cycles_per_frame {
+            None => next_deadline(&[&cpu_ticker, &timer_ticker, &render_ticker]),
+            Some(_) => next_deadline(&[&render_ticker]),
+        };
+        if let Some(wait) = deadline.and_then(|deadline| deadline.checked_duration_since(time::Instant::now())) {
+            thread::sleep(wait);
         }
-        thread::sleep(time::Duration::from_millis(1));
+    }
+    if let (Some(input_log), Some(input_log_filepath)) = (&input_log, arg.value_of("record_input")) {
+        match input_log.save(input_log_filepath) {
+            Ok(()) => println!("Recorded the keys of {} frames to {}", input_log.frame_count(), input_log_filepath),
+            Err(io_err) => eprintln!("Cannot save input log to {}: {}", input_log_filepath, io_err),
+        }
+    }
+    if let (Some(recorder), Some(record_filepath)) = (&recorder, arg.value_of("record")) {
+        match recorder.save(Path::new(record_filepath)) {
+            Ok(()) => println!("Recorded {} frames to {}", recorder.frame_count(), record_filepath),
+            Err(e) => eprintln!("Cannot save recording to {}: {}", record_filepath, e),
+        }
+    }
+    save_flags(&cpu, flags_filepath);
+    if arg.is_present("stats") {
+        print_stats(&cpu, frames, start.elapsed());
+    }
+    if cpu.profile {
+        print_histogram(&cpu);
     }
     Ok(())
 }
 
-fn main()
+// Process exit status of a run, 0 when it quit normally
+fn exit_code(result: Result<(), i32>) -> i32
 {
-    std::process::exit(match run() {
-        Ok(_) => 1,
+    match result {
+        Ok(()) => 0,
         Err(errcode) => errcode,
-    });
+    }
+}
+
+fn main()
+{
+    std::process::exit(exit_code(run()));
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn run_exit_codes()
+    {
+        assert_eq!(exit_code(Ok(())), 0);
+        assert_eq!(exit_code(Err(1)), 1);
+    }
+
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn beeper_without_audio()
+    {
+        let beeper = init_beeper(None, 440.0);
+        assert_eq!(beeper.frequency(), 440.0);
+        beeper.beep();
+        beeper.pause_beep();
+        assert!(!beeper.is_muted());
+
+        let beeper = init_beeper(Some(Err(String::from("no audio device"))), 880.0);
+        assert_eq!(beeper.frequency(), 880.0);
+    }
 }
 
@@ -0,0 +1,253 @@
+//!
+//! Emulation speed helpers
+//!
+
+use std::time::{Duration, Instant};
+
+// Hz, the delay and sound timers decrement rate
+pub const TIMER_FREQUENCY: f64 = 60.0;
+
+// Lateness past which a ticker gives up catching up, e.g. after the window was dragged
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+// Periodic event with deadlines fixed from its start, so that late ticks do not make it drift
+pub struct Ticker
+{
+    period: Duration,
+    next: Instant,
+}
+
+impl Ticker
+{
+    // Ticker firing every period, the first time one period after start
+    pub fn new(period: Duration, start: Instant) -> Ticker
+    {
+        Ticker {
+            period: period.max(Duration::from_nanos(1)),
+            next: start + period,
+        }
+    }
+
+    // Ticker firing frequency times per second
+    pub fn with_frequency(frequency: f64, start: Instant) -> Ticker
+    {
+        Ticker::new(Duration::from_secs_f64(1.0 / frequency), start)
+    }
+
+    // Number of ticks due at now, moving the deadline past now.
+    // A ticker lagging behind by more than MAX_LAG fires once and starts over from now.
+    pub fn due(&mut self, now: Instant) -> u32
+    {
+        if now < self.next {
+            return 0;
+        }
+        let late = now - self.next;
+        if late > MAX_LAG {
+            self.next = now + self.period;
+            return 1;
+        }
+        let ticks = (late.as_nanos() / self.period.as_nanos()) as u32 + 1;
+        self.next += self.period * ticks;
+        ticks
+    }
+
+    pub fn deadline(&self) -> Instant
+    {
+        self.next
+    }
+}
+
+// Nearest deadline among the tickers
+#[cfg(feature = "sdl")]
+pub fn next_deadline(tickers: &[&Ticker]) -> Option<Instant>
+{
+    tickers.iter().map(|ticker| ticker.deadline()).min()
+}
+
+// Cpu cycles to run for one step of the clock, multiplied while the turbo key is held
+#[cfg(feature = "sdl")]
+pub fn cycle_budget(cycles: u32, turbo_multiplier: u32, turbo: bool) -> u32
+{
+    if turbo {
+        cycles.saturating_mul(turbo_multiplier)
+    } else {
+        cycles
+    }
+}
+
+// Spends cpu cycle budgets on instructions of varying cost, an instruction
+// overrunning the budget borrowing the extra cycles from the next one
+#[cfg(feature = "sdl")]
+#[derive(Default)]
+pub struct CycleBudget
+{
+    debt: u32,
+}
+
+#[cfg(feature = "sdl")]
+impl CycleBudget
+{
+    // Run instructions until the budget is spent, run returning the cost of each
+    pub fn spend<F: FnMut() -> u32>(&mut self, budget: u32, mut run: F)
+    {
+        if budget <= self.debt {
+            self.debt -= budget;
+            return;
+        }
+        let mut left = budget - self.debt;
+        self.debt = 0;
+        while left > 0 {
+            let cost = run().max(1);
+            if cost > left {
+                self.debt = cost - left;
+            }
+            left = left.saturating_sub(cost);
+        }
+    }
+}
+
+// Frames rendered and instructions executed per second, averaged over one second windows
+#[cfg(feature = "sdl")]
+pub struct PerfCounter
+{
+    window_start: Instant,
+    frames: u32,
+    // instructions counter at the start of the window
+    instructions: u64,
+}
+
+#[cfg(feature = "sdl")]
+impl PerfCounter
+{
+    pub fn new(start: Instant, instructions: u64) -> PerfCounter
+    {
+        PerfCounter {
+            window_start: start,
+            frames: 0,
+            instructions,
+        }
+    }
+
+    // Count a rendered frame, instructions being the total executed so far.
+    // Returns the frames and instructions per second once a window is complete.
+    pub fn frame(&mut self, now: Instant, instructions: u64) -> Option<(f64, f64)>
+    {
+        self.frames += 1;
+        let elapsed = now.saturating_duration_since(self.window_start).as_secs_f64();
+        if elapsed < 1.0 {
+            return None;
+        }
+        // the counter may have been reset during the window
+        let executed = instructions.saturating_sub(self.instructions);
+        let rates = (self.frames as f64 / elapsed, executed as f64 / elapsed);
+        *self = PerfCounter::new(now, instructions);
+        Some(rates)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn ticker_deadlines_do_not_drift()
+    {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut ticker = Ticker::new(ms(10), start);
+        assert_eq!(ticker.deadline(), start + ms(10));
+
+        assert_eq!(ticker.due(start + ms(9)), 0);
+        // a late tick keeps the next deadline on the original grid
+        assert_eq!(ticker.due(start + ms(13)), 1);
+        assert_eq!(ticker.deadline(), start + ms(20));
+        assert_eq!(ticker.due(start + ms(19)), 0);
+        // ticks missed meanwhile are all reported
+        assert_eq!(ticker.due(start + ms(45)), 3);
+        assert_eq!(ticker.deadline(), start + ms(50));
+        assert_eq!(ticker.due(start + ms(50)), 1);
+
+        // too late, it starts over
+        assert_eq!(ticker.due(start + ms(500)), 1);
+        assert_eq!(ticker.deadline(), start + ms(510));
+    }
+
+    #[test]
+    fn sixty_hertz_ticker()
+    {
+        let start = Instant::now();
+        let mut ticker = Ticker::with_frequency(60.0, start);
+        // the period is rounded to the nanosecond, hence the extra millisecond
+        let ticks: u32 = (1..=1001).map(|ms| ticker.due(start + Duration::from_millis(ms))).sum();
+        assert_eq!(ticks, 60);
+    }
+
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn nearest_deadline()
+    {
+        let start = Instant::now();
+        let cpu = Ticker::with_frequency(1000.0, start);
+        let timers = Ticker::with_frequency(60.0, start);
+        assert_eq!(next_deadline(&[&timers, &cpu]), Some(start + Duration::from_millis(1)));
+        assert_eq!(next_deadline(&[&timers]), Some(timers.deadline()));
+        assert_eq!(next_deadline(&[]), None);
+    }
+
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn turbo_multiplies_cycle_budget()
+    {
+        assert_eq!(cycle_budget(1, 4, false), 1);
+        assert_eq!(cycle_budget(1, 4, true), 4);
+        assert_eq!(cycle_budget(10, 8, true), 80);
+        assert_eq!(cycle_budget(10, 1, true), 10);
+        assert_eq!(cycle_budget(u32::MAX, 2, true), u32::MAX);
+    }
+
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn cycle_budget_debt()
+    {
+        let mut budget = CycleBudget::default();
+        let mut runs = 0;
+        budget.spend(10, || { runs += 1; 1 });
+        assert_eq!(runs, 10);
+
+        // a 7 cycles draw overruns a budget of 3 by 4 cycles
+        let mut runs = 0;
+        budget.spend(3, || { runs += 1; 7 });
+        assert_eq!(runs, 1);
+        assert_eq!(budget.debt, 4);
+
+        // the debt is paid before anything else runs
+        let mut runs = 0;
+        budget.spend(3, || { runs += 1; 1 });
+        assert_eq!(runs, 0);
+        budget.spend(3, || { runs += 1; 1 });
+        assert_eq!(runs, 2);
+        assert_eq!(budget.debt, 0);
+    }
+
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn perf_counter_rates()
+    {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut counter = PerfCounter::new(start, 100);
+        for frame in 1..60 {
+            assert_eq!(counter.frame(start + ms(frame * 16), 100 + frame * 10), None);
+        }
+        assert_eq!(counter.frame(start + ms(1000), 700), Some((60.0, 600.0)));
+
+        for frame in 1..30 {
+            assert_eq!(counter.frame(start + ms(1000 + frame * 33), 700), None);
+        }
+        assert_eq!(counter.frame(start + ms(2000), 4700), Some((30.0, 4000.0)));
+
+        // a single frame two seconds later, after the instruction count was reset
+        assert_eq!(counter.frame(start + ms(4000), 0), Some((0.5, 0.0)));
+    }
+}
@@ -0,0 +1,112 @@
+//!
+//! End to end tests: small ROMs run headless, checked through the display and the memory
+//!
+
+use std::{env, fs, process};
+
+use fish_n_chip::{Cpu, Keyboard, Memory};
+
+const CYCLES_PER_TIMER_TICK: u64 = 16;
+
+// Load a ROM at 0x200 and run it for the given number of cycles without any input
+fn run_rom(rom: &[u8], cycles: u64) -> (Cpu, Memory)
+{
+    let mut cpu = Cpu::new();
+    let mut memory = Memory::new();
+    let keyboard = Keyboard::new();
+    memory.load_bytes(rom, 0x200).unwrap();
+    for cycle in 1..=cycles {
+        cpu.cycle(&mut memory, &keyboard);
+        if cycle % CYCLES_PER_TIMER_TICK == 0 {
+            let _ = cpu.update_timers();
+        }
+    }
+    (cpu, memory)
+}
+
+// The first rows of the display, without their trailing unlit pixels
+fn top_rows(memory: &Memory, rows: usize) -> Vec<String>
+{
+    memory.display.to_ascii().lines().take(rows).map(|row| row.trim_end().to_string()).collect()
+}
+
+#[test]
+fn draw_font_digit_0()
+{
+    // LD V0, 0 ; LD F, V0 ; LD V1, 5 ; LD V2, 2 ; DRW V1, V2, 5 ; JP 0x20A
+    let rom = [0x60, 0x00, 0xF0, 0x29, 0x61, 0x05, 0x62, 0x02, 0xD1, 0x25, 0x12, 0x0A];
+    let (cpu, memory) = run_rom(&rom, 50);
+
+    assert_eq!(top_rows(&memory, 8), [
+        "",
+        "",
+        "     ####",
+        "     #  #",
+        "     #  #",
+        "     #  #",
+        "     ####",
+        "",
+    ]);
+    assert_eq!(memory.display.to_ascii().matches('#').count(), 14);
+    assert_eq!(cpu.program_counter(), 0x20A);
+}
+
+#[test]
+fn draw_twice_erases_the_sprite()
+{
+    // LD V0, 8 ; LD F, V0 ; DRW V0, V0, 5 ; DRW V0, V0, 5 ; JP 0x208
+    let rom = [0x60, 0x08, 0xF0, 0x29, 0xD0, 0x05, 0xD0, 0x05, 0x12, 0x08];
+    let (_, memory) = run_rom(&rom, 20);
+
+    assert!(!memory.display.to_ascii().contains('#'));
+}
+
+#[test]
+fn arithmetic_results_in_memory()
+{
+    // LD V0, 0x2A ; ADD V0, 0x17 ; LD V1, 5 ; SUB V0, V1 ;
+    // LD I, 0x300 ; LD B, V0 ; LD I, 0x310 ; LD [I], V1 ; JP 0x210
+    let rom = [
+        0x60, 0x2A, 0x70, 0x17, 0x61, 0x05, 0x80, 0x15,
+        0xA3, 0x00, 0xF0, 0x33, 0xA3, 0x10, 0xF1, 0x55,
+        0x12, 0x10,
+    ];
+    let (_, memory) = run_rom(&rom, 30);
+
+    // 0x2A + 0x17 - 5 = 60
+    assert_eq!(&memory[0x300..0x303], &[0, 6, 0]);
+    assert_eq!(&memory[0x310..0x312], &[60, 5]);
+    assert_eq!(memory[0x312], 0);
+}
+
+#[test]
+fn verify_exit_codes()
+{
+    // LD V0, 0 ; LD F, V0 ; LD V1, 5 ; LD V2, 2 ; DRW V1, V2, 5 ; JP 0x20A
+    let rom = [0x60, 0x00, 0xF0, 0x29, 0x61, 0x05, 0x62, 0x02, 0xD1, 0x25, 0x12, 0x0A];
+    let mut golden = vec![".".repeat(64); 32];
+    for (row, line) in ["####", "#..#", "#..#", "#..#", "####"].iter().enumerate() {
+        golden[2 + row].replace_range(5..9, line);
+    }
+    let rom_filepath = env::temp_dir().join(format!("fish_n_chip_verify_{}.ch8", process::id()));
+    let golden_filepath = rom_filepath.with_extension("txt");
+    fs::write(&rom_filepath, rom).unwrap();
+    fs::write(&golden_filepath, golden.join("\n") + "\n").unwrap();
+
+    let verify = |cycles: &str| process::Command::new(env!("CARGO_BIN_EXE_fish_n_chip"))
+        .arg(&rom_filepath)
+        .arg("--verify")
+        .arg(&golden_filepath)
+        .args(["--cycles", cycles])
+        .output()
+        .unwrap();
+    let matching = verify("50");
+    // the digit is not drawn yet
+    let differing = verify("3");
+    fs::remove_file(&rom_filepath).unwrap();
+    fs::remove_file(&golden_filepath).unwrap();
+
+    assert_eq!(matching.status.code(), Some(0));
+    assert_eq!(differing.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&differing.stderr).contains("on 5 of 32 rows"));
+}